@@ -0,0 +1,59 @@
+//! Captures a page without ffmpeg and reports the average brightness of the
+//! first 60 frames.
+//!
+//! ```sh
+//! cargo run --example capture_frames -- https://example.com /path/to/chromium
+//! ```
+
+use std::ops::ControlFlow;
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+use clap::Parser;
+
+use browser_stream::cli::CliArgs;
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let mut args = std::env::args().skip(1);
+    let url = args
+        .next()
+        .context("usage: capture_frames <url> <chromium path>")?;
+    let chromium = args
+        .next()
+        .map(PathBuf::from)
+        .context("usage: capture_frames <url> <chromium path>")?;
+
+    let config = CliArgs::try_parse_from([
+        "browser-stream",
+        "--url",
+        url.as_str(),
+        "--output-to-null",
+        "--width",
+        "640",
+        "--height",
+        "360",
+    ])?
+    .into_config()?;
+
+    let mut frames = 0;
+    browser_stream::capture_frames(&config, Some(&chromium), |frame| {
+        frames += 1;
+        let brightness = frame
+            .data
+            .iter()
+            .map(|&value| u64::from(value))
+            .sum::<u64>()
+            / frame.data.len() as u64;
+        println!(
+            "frame {frames}: {}x{} brightness {brightness}",
+            frame.width, frame.height
+        );
+        if frames == 60 {
+            ControlFlow::Break(())
+        } else {
+            ControlFlow::Continue(())
+        }
+    })
+    .await
+}
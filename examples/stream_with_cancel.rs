@@ -0,0 +1,30 @@
+//! Streams a page with the same flags as the CLI and stops it from code after a
+//! minute, the way an application managing its own lifecycle would.
+//!
+//! ```sh
+//! cargo run --example stream_with_cancel -- --url https://example.com \
+//!   --output rtmp://live.example.com/app/key
+//! ```
+
+use std::time::Duration;
+
+use anyhow::Result;
+use clap::Parser;
+use tokio_util::sync::CancellationToken;
+
+use browser_stream::cli::CliArgs;
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let config = CliArgs::try_parse_from(std::env::args())?.into_config()?;
+
+    let cancel = CancellationToken::new();
+    let stop = cancel.clone();
+    tokio::spawn(async move {
+        tokio::time::sleep(Duration::from_secs(60)).await;
+        stop.cancel();
+    });
+
+    // Returns `Ok` once cancelled; errors mean the stream failed on its own.
+    browser_stream::pipeline::run(config, Some(cancel)).await
+}
@@ -1,175 +1,568 @@
+use std::collections::VecDeque;
+use std::ops::ControlFlow;
 use std::path::{Path, PathBuf};
-use std::time::Duration;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
 
 use anyhow::{Context, Result, anyhow};
+use chromiumoxide::Page;
 use chromiumoxide::browser::{Browser, BrowserConfig};
+use chromiumoxide::cdp::browser_protocol::emulation::{
+    SetDeviceMetricsOverrideParams, SetEmulatedMediaParams,
+};
+use chromiumoxide::cdp::browser_protocol::fetch::{
+    ContinueRequestParams, EnableParams as FetchEnableParams, EventRequestPaused,
+    FailRequestParams, RequestPattern, RequestStage,
+};
+use chromiumoxide::cdp::browser_protocol::input::{
+    DispatchMouseEventParams, DispatchMouseEventType, InsertTextParams, MouseButton,
+};
+use chromiumoxide::cdp::browser_protocol::log::{
+    EnableParams as LogEnableParams, EventEntryAdded, LogEntryLevel,
+};
+use chromiumoxide::cdp::browser_protocol::network::{
+    ErrorReason, Headers, ResourceType, SetExtraHttpHeadersParams,
+};
 use chromiumoxide::cdp::browser_protocol::page::{
-    EventScreencastFrame, ScreencastFrameAckParams, StartScreencastFormat, StartScreencastParams,
-    StopScreencastParams,
+    AddScriptToEvaluateOnNewDocumentParams, CaptureScreenshotFormat, CaptureScreenshotParams,
+    EventScreencastFrame, FrameTree, GetFrameTreeParams, ScreencastFrameAckParams,
+    StartScreencastFormat, StartScreencastParams, StopScreencastParams,
+};
+use chromiumoxide::cdp::browser_protocol::target::TargetInfo;
+use chromiumoxide::cdp::js_protocol::runtime::{
+    ConsoleApiCalledType, EventConsoleApiCalled, EventExceptionThrown, RemoteObject,
 };
+use chromiumoxide::error::CdpError;
+use chromiumoxide::handler::HandlerConfig;
 use chromiumoxide::handler::viewport::Viewport;
-use futures::StreamExt;
+use chromiumoxide::listeners::EventStream;
+use chromiumoxide::page::ScreenshotParams;
+use futures::{FutureExt, Stream, StreamExt};
 use tokio::io::{AsyncBufReadExt, BufReader};
-use tokio::sync::mpsc;
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::JoinHandle;
 use tokio::time::MissedTickBehavior;
-use tracing::{debug, error, info, warn};
+use tokio_util::sync::CancellationToken;
+use tracing::{Instrument, debug, error, info, warn};
 
-use crate::cli::AppConfig;
+use crate::cli::{
+    AppConfig, BlankAction, FrameRate, MIN_BITRATE_KBPS, NetworkThrottle, domain_allowed,
+    parse_truthy,
+};
 use crate::encoder::FfmpegEncoder;
 use crate::error::RuntimeError;
-use crate::frame::{RgbFrame, decode_screencast_frame};
-
-pub async fn stream_browser_to_encoder(
-    config: &AppConfig,
-    chromium_path: &Path,
-    encoder: &mut FfmpegEncoder,
-) -> Result<()> {
-    let viewport = Viewport {
-        width: config.width,
-        height: config.height,
-        device_scale_factor: Some(1.0),
-        emulating_mobile: false,
-        is_landscape: config.width >= config.height,
-        has_touch: false,
-    };
+use crate::frame::{
+    RgbFrame, composite_grid, decode_screencast_frame, grid_cells, load_image_frame, mean_abs_diff,
+    save_png, uniform_color,
+};
+use crate::pump::{SourceItem, checked_frames, pump_frames, write_first_frame};
+use crate::startup::StartupTimeline;
 
-    let mut browser_builder = BrowserConfig::builder()
-        .chrome_executable(chromium_path)
-        .window_size(config.width, config.height)
-        .new_headless_mode()
-        .viewport(viewport)
-        .arg("--autoplay-policy=no-user-gesture-required")
-        .arg("--disable-background-timer-throttling")
-        .arg("--disable-backgrounding-occluded-windows")
-        .arg("--disable-renderer-backgrounding");
+/// A browser and page left running by a failed attempt under `--reuse-browser`,
+/// or launched ahead of ffmpeg by [`prelaunch_browser`].
+pub struct KeptBrowser {
+    session: BrowserSession,
+    handler_failure: HandlerFailure,
+    /// Startup milestones of a pre-launched browser, continued by the attempt.
+    timeline: Option<StartupTimeline>,
+}
 
-    if no_sandbox_from_env() {
-        browser_builder = browser_builder.no_sandbox();
+impl KeptBrowser {
+    pub async fn close(self) {
+        self.session.close().await;
     }
+}
 
-    let browser_config = browser_builder
-        .build()
-        .map_err(|err| anyhow!("failed to build browser config: {err}"))?;
-
-    info!(chromium = %chromium_path.display(), "starting chromium");
+/// `--warm-start`: launches the browser and loads the page, including
+/// `--startup-delay-ms`, before ffmpeg is spawned, so ffmpeg's first frame is the
+/// settled page instead of ffmpeg idling through the browser's startup. Pass the
+/// result to [`stream_browser_to_encoder`] in its `browser_slot`.
+pub async fn prelaunch_browser(
+    config: &AppConfig,
+    chromium_path: Option<&Path>,
+) -> Result<KeptBrowser> {
+    let mut timeline = StartupTimeline::new(Instant::now());
+    let (session, handler_failure) = launch_session(config, chromium_path, &mut timeline).await?;
+    Ok(KeptBrowser {
+        session,
+        handler_failure,
+        timeline: Some(timeline),
+    })
+}
 
-    let (mut browser, mut handler) = Browser::launch(browser_config)
+/// `--fit-content`: measures the page loaded by [`prelaunch_browser`] and resizes
+/// its viewport to the page's scroll size, see [`fit_content_size`]. Returns the
+/// new size, which the caller locks in as the capture and encode size.
+pub async fn fit_viewport_to_content(
+    browser: &KeptBrowser,
+    config: &AppConfig,
+) -> Result<(u32, u32)> {
+    let page = &browser.session.page;
+    let measured = page
+        .evaluate(
+            "(() => { const root = document.documentElement, body = document.body || root; \
+             return [Math.max(root.scrollWidth, body.scrollWidth), \
+             Math.max(root.scrollHeight, body.scrollHeight)]; })()",
+        )
         .await
-        .context("failed to launch chromium")?;
+        .context("failed to measure page size")?;
+    let [content_width, content_height]: [u32; 2] = measured
+        .into_value()
+        .context("page size measurement returned an unexpected value")?;
+    let (width, height) = fit_content_size(
+        content_width,
+        content_height,
+        config.fit_content_max_width,
+        config.fit_content_max_height,
+    );
+    info!(
+        content_width,
+        content_height, width, height, "fitting capture to page content"
+    );
+    page.execute(SetDeviceMetricsOverrideParams::new(
+        i64::from(width),
+        i64::from(height),
+        1.0,
+        false,
+    ))
+    .await
+    .context("failed to resize viewport to page content")?;
+    Ok((width, height))
+}
 
-    let handler_task = tokio::spawn(async move {
-        while let Some(item) = handler.next().await {
-            if let Err(err) = item {
-                error!("chromium handler error: {err}");
-                break;
-            }
-        }
+/// Capture size for a page whose content measures `content_width`x`content_height`:
+/// at most the given maximum, at least 16 pixels, and rounded down to even sizes
+/// so every output pixel format accepts it.
+pub fn fit_content_size(
+    content_width: u32,
+    content_height: u32,
+    max_width: u32,
+    max_height: u32,
+) -> (u32, u32) {
+    let fit = |content: u32, max: u32| content.clamp(16, max.max(16)) & !1;
+    (
+        fit(content_width, max_width),
+        fit(content_height, max_height),
+    )
+}
+
+/// Whether a failed attempt can hand its browser to the next one. Only failures
+/// on the encoder side qualify: ffmpeg exiting, stalling on its output or its
+/// input pipe breaking, and frame rate downshifts and bitrate changes, which
+/// restart ffmpeg only.
+pub fn browser_survives(err: &anyhow::Error) -> bool {
+    let encoder_side = err.downcast_ref::<RuntimeError>().is_some_and(|runtime| {
+        matches!(
+            runtime,
+            RuntimeError::EncoderExited { .. }
+                | RuntimeError::EncoderInitTimeout { .. }
+                | RuntimeError::FpsDownshift { .. }
+                | RuntimeError::BitrateChange { .. }
+        )
+    });
+    let broken_pipe = err.chain().any(|cause| {
+        cause
+            .downcast_ref::<std::io::Error>()
+            .is_some_and(|io| io.kind() == std::io::ErrorKind::BrokenPipe)
     });
+    encoder_side || broken_pipe
+}
 
-    let page = browser
-        .new_page("about:blank")
-        .await
-        .context("failed to create page")?;
+/// Whether a failed attempt keeps its browser even without `--reuse-browser`:
+/// restarts asked for while streaming, which only need a new ffmpeg.
+pub fn browser_always_kept(err: &anyhow::Error) -> bool {
+    matches!(
+        err.downcast_ref::<RuntimeError>(),
+        Some(RuntimeError::BitrateChange { .. })
+    )
+}
 
-    page.goto(config.website_url.as_str())
-        .await
-        .with_context(|| format!("failed loading {}", config.website_url))?;
+/// Streams the page into `encoder`. `last_frame` is held on the output until the
+/// page produces its first frame, and receives the newest frame when streaming stops.
+/// `produced_frames` is set once the page has produced a frame, so callers can tell
+/// an attempt that never reached the page from one that dropped mid-stream.
+///
+/// A browser in `browser_slot` is used instead of launching a new one. With
+/// `--reuse-browser`, the browser is put back there when the attempt fails in a
+/// way [`browser_survives`].
+///
+/// Cancelling `cancel` stops the stream with [`RuntimeError::ShutdownRequested`].
+/// The CLI cancels it on ctrl-c.
+pub async fn stream_browser_to_encoder(
+    config: &AppConfig,
+    chromium_path: Option<&Path>,
+    encoder: &mut FfmpegEncoder,
+    last_frame: &mut Option<RgbFrame>,
+    produced_frames: &mut bool,
+    browser_slot: &mut Option<KeptBrowser>,
+    cancel: &CancellationToken,
+) -> Result<()> {
+    let (mut session, mut handler_failure, mut timeline) = match browser_slot.take() {
+        Some(KeptBrowser {
+            session,
+            handler_failure,
+            timeline: Some(timeline),
+        }) => {
+            info!("using pre-launched browser");
+            (session, handler_failure, timeline)
+        }
+        Some(kept) => {
+            info!("reusing running browser");
+            (
+                kept.session,
+                kept.handler_failure,
+                StartupTimeline::new(Instant::now()),
+            )
+        }
+        None => {
+            let mut timeline = StartupTimeline::new(Instant::now());
+            let (session, handler_failure) = encoder
+                .hold_frame_while(
+                    last_frame.as_ref(),
+                    config.fps.interval(),
+                    launch_session(config, chromium_path, &mut timeline),
+                )
+                .await??;
+            (session, handler_failure, timeline)
+        }
+    };
+    timeline.encoder_spawned(encoder.spawned_at());
+    let memory_guard = match config.max_browser_memory_mb {
+        Some(limit_mb) => match session.browser_pid() {
+            Some(pid) => Some((pid, limit_mb)),
+            None => {
+                warn!("browser process id unavailable; memory guard disabled");
+                None
+            }
+        },
+        None => None,
+    };
+    let page = &session.page;
 
-    // `goto` waits for page load completion. Delay further for dynamic JS/CSS settling.
-    tokio::time::sleep(Duration::from_millis(config.startup_delay_ms)).await;
+    if let Some(path) = config.verify_screenshot.as_deref()
+        && let Err(err) = save_verify_screenshot(page, path).await
+    {
+        warn!("{err:#}");
+    }
+
+    let console_task = if config.log_console {
+        Some(spawn_console_logger(page).await?)
+    } else {
+        None
+    };
 
-    let mut frame_events = page
+    let frame_events = page
         .event_listener::<EventScreencastFrame>()
         .await
         .context("failed to register screencast event listener")?;
 
-    let start_params = StartScreencastParams::builder()
-        .format(StartScreencastFormat::Jpeg)
-        .quality(80_i64)
-        .max_width(i64::from(config.width))
-        .max_height(i64::from(config.height))
-        .every_nth_frame(1_i64)
-        .build();
+    let start_params = screencast_params(config);
 
-    page.execute(start_params)
+    page.execute(start_params.clone())
         .await
         .context("failed to start screencast")?;
 
-    info!("runtime controls: type `r` then Enter to refresh the page");
+    info!(
+        "runtime controls: type `{}r` then Enter to refresh the page",
+        config.control_prefix
+    );
 
-    let mut control_rx = spawn_control_listener();
-    let frame_interval = Duration::from_secs_f64(1.0_f64 / f64::from(config.fps));
+    let mut control_rx = spawn_control_listener(config.control_prefix.clone());
+    let mut control_open = true;
+    let input_tx = spawn_input_dispatcher(page.clone(), config);
+    // Set by `viewers 0`: the screencast is stopped and the last frame repeated.
+    let mut paused_for_viewers = false;
+    let frame_interval = config.fps.interval();
     let mut frame_tick = tokio::time::interval(frame_interval);
     frame_tick.set_missed_tick_behavior(MissedTickBehavior::Skip);
     frame_tick.tick().await;
     let mut stats_tick = tokio::time::interval(Duration::from_secs(5));
     stats_tick.set_missed_tick_behavior(MissedTickBehavior::Skip);
     stats_tick.tick().await;
+    let mut memory_tick = tokio::time::interval(Duration::from_secs(15));
+    memory_tick.set_missed_tick_behavior(MissedTickBehavior::Skip);
+    memory_tick.tick().await;
+    let mut auto_scroller = config.auto_scroll.map(|px_per_sec| {
+        AutoScroller::new(
+            px_per_sec,
+            Duration::from_secs(config.scroll_pause_sec),
+            Instant::now(),
+        )
+    });
+    let mut scroll_tick = tokio::time::interval(
+        auto_scroller
+            .as_ref()
+            .map_or(Duration::from_secs(1), |scroller| scroller.interval),
+    );
+    scroll_tick.set_missed_tick_behavior(MissedTickBehavior::Skip);
+    scroll_tick.tick().await;
 
-    let first_frame_timeout = tokio::time::sleep(Duration::from_millis(config.frame_timeout_ms));
-    tokio::pin!(first_frame_timeout);
+    let duration_limit = tokio::time::sleep(config.max_duration().unwrap_or(Duration::MAX));
+    tokio::pin!(duration_limit);
     let mut latest_frame: Option<RgbFrame> = None;
+    let mut pacing = PacingMonitor::default();
     let mut decoded_frames: u64 = 0;
     let mut encoded_frames: u64 = 0;
+    // With `--blank-timeout-sec`, when the page's frames turned a single flat color.
+    let mut blank_since: Option<Instant> = None;
+    let mut blank_warned = false;
+    // With `--dump-frames`, the PNG write still running; at most one is in flight.
+    let mut frame_dump: Option<tokio::task::JoinHandle<()>> = None;
+    let baseline = config
+        .baseline
+        .as_deref()
+        .map(|path| load_image_frame(path, config.width, config.height))
+        .transpose()?;
+    // With `--diff-alert`, whether the last compared frame was over the threshold.
+    let mut diff_alerting = false;
+    // With `--reload-wait`, frames rendered before this deadline are held back.
+    let mut reload_settle_deadline: Option<tokio::time::Instant> = None;
+    let mut pending_reload_frame: Option<RgbFrame> = None;
+    let queue_stats = ScreencastQueueStats::default();
 
     let stream_result: Result<()> = async {
+        let frames = checked_frames(
+            screencast_frames(page, config, frame_events, &queue_stats),
+            config,
+        );
+        tokio::pin!(frames);
         loop {
             tokio::select! {
                 biased;
-                _ = frame_tick.tick() => {
-                    if let Some(frame) = latest_frame.as_ref() {
+                scheduled = frame_tick.tick() => {
+                    // A tick firing a whole interval late means an output frame slot was missed.
+                    pacing.record_tick(scheduled.elapsed() >= frame_interval);
+                    if let Some(frame) = latest_frame.as_ref().or(last_frame.as_ref()) {
                         encoder.write_frame(frame).await?;
                         encoded_frames = encoded_frames.saturating_add(1);
                     }
                 }
-                maybe_event = frame_events.next() => {
-                    let event = maybe_event.context("screencast event stream ended unexpectedly")?;
+                frame = frames.next() => {
+                    let frame = frame.context("screencast event stream ended unexpectedly")??;
 
-                    page.execute(ScreencastFrameAckParams::new(event.session_id))
-                        .await
-                        .context("failed to ack screencast frame")?;
+                    if config.blank_timeout_sec.is_some() {
+                        if uniform_color(&frame, BLANK_COLOR_TOLERANCE).is_some() {
+                            blank_since.get_or_insert_with(Instant::now);
+                        } else {
+                            blank_since = None;
+                            blank_warned = false;
+                        }
+                    }
+
+                    if let Some(dir) = config.dump_frames.as_deref()
+                        && decoded_frames.is_multiple_of(config.dump_every)
+                    {
+                        dump_frame(&mut frame_dump, dir, decoded_frames, &frame);
+                    }
 
-                    let frame = decode_screencast_frame(event.data.as_ref(), config.width, config.height)
-                        .context("failed to decode screencast frame")?;
+                    if reload_settle_deadline.is_some() {
+                        decoded_frames = decoded_frames.saturating_add(1);
+                        pending_reload_frame = Some(frame);
+                        continue;
+                    }
 
                     if latest_frame.is_none() {
-                        info!("received first screencast frame");
-                        // Prime ffmpeg immediately so it can initialize output without waiting for the first tick.
-                        encoder.write_frame(&frame).await?;
+                        write_first_frame(config, encoder, &frame, Some(&mut timeline)).await?;
                         encoded_frames = encoded_frames.saturating_add(1);
                     }
                     decoded_frames = decoded_frames.saturating_add(1);
                     latest_frame = Some(frame);
                 }
                 _ = stats_tick.tick() => {
+                    let (late_ticks, sustained_lag) = pacing.end_window();
+                    let progress = encoder.progress().unwrap_or_default();
                     debug!(
                         decoded_frames,
                         encoded_frames,
+                        late_ticks,
+                        frame_backlog = queue_stats.backlog.load(Ordering::Relaxed),
+                        peak_frame_backlog = queue_stats.peak_backlog.swap(0, Ordering::Relaxed),
+                        backpressured_frames = queue_stats.backpressured_frames.load(Ordering::Relaxed),
+                        stale_dropped_frames = queue_stats.stale_dropped_frames.load(Ordering::Relaxed),
+                        buffer_dropped_frames = queue_stats.buffer_dropped_frames.load(Ordering::Relaxed),
+                        ffmpeg_frame = progress.frame,
+                        ffmpeg_fps = progress.fps,
+                        output_bitrate_kbps = progress.bitrate_kbps,
+                        output_dropped_frames = encoder.dropped_frames(),
+                        ffmpeg_drop_frames = progress.drop_frames,
+                        ffmpeg_out_time_us = progress.out_time_us,
                         has_frame = latest_frame.is_some(),
                         "streaming stats"
                     );
+                    if sustained_lag
+                        && config.adaptive_fps
+                        && let Some(to) = downshift_fps(config.fps)
+                    {
+                        return Err(RuntimeError::FpsDownshift { from: config.fps, to }.into());
+                    }
+                    if let (Some(baseline), Some(frame)) = (baseline.as_ref(), latest_frame.as_ref()) {
+                        check_baseline_diff(frame, baseline, config.diff_threshold, &mut diff_alerting);
+                    }
+                    if let (Some(timeout_sec), Some(since)) = (config.blank_timeout_sec, blank_since)
+                        && since.elapsed() >= Duration::from_secs(timeout_sec)
+                    {
+                        let blank_sec = since.elapsed().as_secs();
+                        match config.on_blank {
+                            BlankAction::Warn if !blank_warned => {
+                                warn!(blank_sec, "page has shown a single flat color; it may be blank or crashed");
+                                blank_warned = true;
+                            }
+                            BlankAction::Warn => {}
+                            BlankAction::Reload => {
+                                warn!(blank_sec, "page has shown a single flat color; reloading");
+                                page.reload()
+                                    .await
+                                    .context("blank page reload failed")?;
+                                check_navigation_status(page, config).await?;
+                                apply_page_overrides(page, config).await?;
+                                // Give the reloaded page a full timeout before acting again.
+                                blank_since = Some(Instant::now());
+                            }
+                            BlankAction::Fail => {
+                                return Err(RuntimeError::BlankPage { blank_sec }.into());
+                            }
+                        }
+                    }
+                }
+                _ = memory_tick.tick(), if memory_guard.is_some() => {
+                    let Some((pid, limit_mb)) = memory_guard else {
+                        continue;
+                    };
+                    let rss_kb = tokio::task::spawn_blocking(move || process_tree_rss_kb(pid))
+                        .await
+                        .unwrap_or(None);
+                    if let Some(rss_kb) = rss_kb {
+                        let rss_mb = rss_kb / 1024;
+                        debug!(rss_mb, limit_mb, "browser memory usage");
+                        if rss_mb > limit_mb {
+                            return Err(RuntimeError::BrowserMemoryExceeded { rss_mb, limit_mb }.into());
+                        }
+                    }
+                }
+                _ = scroll_tick.tick(), if auto_scroller.is_some() && !paused_for_viewers => {
+                    let Some(scroller) = auto_scroller.as_mut() else {
+                        continue;
+                    };
+                    let now = Instant::now();
+                    let action = scroller.next(now);
+                    if action == ScrollAction::Wait {
+                        continue;
+                    }
+                    match apply_scroll_action(page, action).await {
+                        Ok(true) => {
+                            debug!("auto-scroll reached the bottom");
+                            scroller.reached_bottom(now);
+                        }
+                        Ok(false) => {}
+                        Err(err) => debug!("auto-scroll step failed: {err}"),
+                    }
                 }
-                command = control_rx.recv() => {
+                command = control_rx.recv(), if control_open => {
                     match command {
                         Some(ControlCommand::Refresh) => {
                             page.reload()
                                 .await
                                 .context("manual refresh failed")?;
-                            info!("manual refresh applied");
+                            check_navigation_status(page, config).await?;
+                            apply_page_overrides(page, config).await?;
+                            if let Some(scroller) = auto_scroller.as_mut() {
+                                scroller.restart(Instant::now());
+                            }
+                            if config.reload_wait {
+                                reload_settle_deadline = Some(
+                                    tokio::time::Instant::now()
+                                        + Duration::from_millis(config.startup_delay_ms),
+                                );
+                                info!(
+                                    startup_delay_ms = config.startup_delay_ms,
+                                    "manual refresh loaded; holding previous frame while the page settles"
+                                );
+                            } else {
+                                info!("manual refresh applied");
+                            }
+                        }
+                        Some(ControlCommand::Viewers(0)) if !paused_for_viewers => {
+                            page.execute(StopScreencastParams::default())
+                                .await
+                                .context("failed to pause screencast")?;
+                            paused_for_viewers = true;
+                            info!("no viewers; repeating the last frame until a viewer joins");
+                        }
+                        Some(ControlCommand::Viewers(viewers)) if viewers > 0 && paused_for_viewers => {
+                            page.execute(start_params.clone())
+                                .await
+                                .context("failed to resume screencast")?;
+                            paused_for_viewers = false;
+                            info!(viewers, "viewer joined; resuming full-rate capture");
+                        }
+                        Some(ControlCommand::Viewers(viewers)) => {
+                            debug!(viewers, paused_for_viewers, "viewer count noted");
+                        }
+                        Some(ControlCommand::Click { x, y })
+                            if x >= config.capture_width || y >= config.capture_height =>
+                        {
+                            warn!(
+                                x,
+                                y,
+                                width = config.capture_width,
+                                height = config.capture_height,
+                                "ignoring click outside the viewport"
+                            );
+                        }
+                        Some(input @ (ControlCommand::Click { .. }
+                            | ControlCommand::Type(_)
+                            | ControlCommand::Scroll { .. })) => {
+                            let _ = input_tx.send(input);
+                        }
+                        Some(ControlCommand::Bitrate(kbps)) if kbps < MIN_BITRATE_KBPS => {
+                            warn!(kbps, min_kbps = MIN_BITRATE_KBPS, "ignoring bitrate below the minimum");
+                        }
+                        Some(ControlCommand::Bitrate(_)) if !config.renditions.is_empty() => {
+                            warn!("ignoring bitrate command; each --rendition sets its own bitrate");
+                        }
+                        Some(ControlCommand::Bitrate(kbps)) if kbps == config.bitrate_kbps => {
+                            debug!(kbps, "bitrate unchanged");
+                        }
+                        Some(ControlCommand::Bitrate(kbps)) => {
+                            // x264 cannot change rate control mid-stream, so ffmpeg is restarted.
+                            return Err(RuntimeError::BitrateChange { from: config.bitrate_kbps, to: kbps }.into());
                         }
                         Some(ControlCommand::Help) => {
-                            info!("runtime controls: `r` or `refresh` reloads the page");
+                            info!(
+                                "runtime controls: `{prefix}r` or `{prefix}refresh` reloads the page, \
+                                 `{prefix}viewers N` pauses capture while N is 0, \
+                                 `{prefix}bitrate KBPS` restarts ffmpeg at a new bitrate, \
+                                 `{prefix}click X Y`, `{prefix}type TEXT` and `{prefix}scroll DX DY` \
+                                 send input to the page",
+                                prefix = config.control_prefix
+                            );
                         }
                         None => {
                             // stdin closed; continue streaming without runtime controls.
+                            control_open = false;
                         }
                     }
                 }
-                _ = tokio::signal::ctrl_c() => {
+                _ = tokio::time::sleep_until(
+                    reload_settle_deadline.unwrap_or_else(tokio::time::Instant::now)
+                ), if reload_settle_deadline.is_some() => {
+                    reload_settle_deadline = None;
+                    if let Some(frame) = pending_reload_frame.take() {
+                        latest_frame = Some(frame);
+                    }
+                    info!("manual refresh applied");
+                }
+                failure = &mut handler_failure => {
+                    let reason = failure.unwrap_or_else(|_| "CDP handler task stopped".to_string());
+                    return Err(RuntimeError::BrowserDisconnected { reason }.into());
+                }
+                () = cancel.cancelled() => {
                     return Err(RuntimeError::ShutdownRequested.into());
                 }
-                _ = &mut first_frame_timeout, if latest_frame.is_none() => {
-                    return Err(RuntimeError::ScreencastTimeout.into());
+                _ = &mut duration_limit, if config.max_duration_sec.is_some() => {
+                    info!(max_duration_sec = config.max_duration_sec, "max duration reached; stopping");
+                    return Ok(());
                 }
             }
         }
@@ -179,111 +572,2005 @@ pub async fn stream_browser_to_encoder(
     if let Err(err) = page.execute(StopScreencastParams::default()).await {
         warn!("failed to stop screencast cleanly: {err}");
     }
-
-    if let Err(err) = browser.close().await {
-        warn!("failed to close browser cleanly: {err}");
+    if let Some(console_task) = console_task {
+        console_task.abort();
     }
-    if let Err(err) = browser.wait().await {
-        warn!("failed to wait for browser process: {err}");
+    if latest_frame.is_some() {
+        *last_frame = latest_frame;
+        *produced_frames = true;
     }
 
-    handler_task.abort();
+    if let Err(err) = &stream_result
+        && (config.reuse_browser && browser_survives(err) || browser_always_kept(err))
+    {
+        info!("keeping browser open for the next attempt");
+        *browser_slot = Some(KeptBrowser {
+            session,
+            handler_failure,
+            timeline: None,
+        });
+        return stream_result;
+    }
+
+    if let Err(err) = encoder
+        .hold_frame_while(last_frame.as_ref(), frame_interval, session.close())
+        .await
+    {
+        warn!("failed to hold frame while closing browser: {err:#}");
+    }
 
     stream_result
 }
 
-fn no_sandbox_from_env() -> bool {
-    match std::env::var("BROWSER_STREAM_NO_SANDBOX") {
-        Ok(value) => parse_truthy(&value),
-        Err(_) => false,
+/// Streams the `--grid` pages into `encoder` as one 2x2 composite. Every page gets
+/// a viewport of a quarter of the capture size, and its frames are decoded into
+/// its [`grid_cells`] slot of the encode size. Cells stay black until their page
+/// produces a frame; the first frame from any page starts the output.
+///
+/// Runtime controls and the page-health checks of [`stream_browser_to_encoder`]
+/// only apply to single-page streams.
+pub async fn stream_grid_to_encoder(
+    config: &AppConfig,
+    chromium_path: Option<&Path>,
+    encoder: &mut FfmpegEncoder,
+    last_frame: &mut Option<RgbFrame>,
+    produced_frames: &mut bool,
+    cancel: &CancellationToken,
+) -> Result<()> {
+    // Pages share the browser's default viewport, so all use the top-left cell size.
+    let (_, _, cell_width, cell_height) =
+        grid_cells(config.capture_width, config.capture_height)[0];
+    let cell_config = AppConfig {
+        capture_width: cell_width,
+        capture_height: cell_height,
+        ..config.clone()
+    };
+    let frame_interval = config.fps.interval();
+
+    let mut timeline = StartupTimeline::new(Instant::now());
+    let (session, handler_failure) = encoder
+        .hold_frame_while(
+            last_frame.as_ref(),
+            frame_interval,
+            launch_session(&cell_config, chromium_path, &mut timeline),
+        )
+        .await??;
+    timeline.encoder_spawned(encoder.spawned_at());
+
+    let mut navigation_guards = Vec::new();
+    let stream_result: Result<()> = async {
+        let mut pages = vec![session.page.clone()];
+        let opening = open_grid_pages(
+            &session.browser,
+            &cell_config,
+            &config.grid[1..],
+            &mut navigation_guards,
+        );
+        pages.extend(
+            encoder
+                .hold_frame_while(last_frame.as_ref(), frame_interval, opening)
+                .await??,
+        );
+
+        let mut listeners = Vec::with_capacity(pages.len());
+        for (index, page) in pages.iter().enumerate() {
+            let frame_events = page
+                .event_listener::<EventScreencastFrame>()
+                .await
+                .context("failed to register screencast event listener")?;
+            page.execute(screencast_params(&cell_config))
+                .await
+                .context("failed to start screencast")?;
+            listeners.push(frame_events.map(move |event| (index, event)));
+        }
+        info!(pages = pages.len(), "streaming page grid");
+
+        let encode_cells = &grid_cells(config.width, config.height);
+        let mut cells: Vec<Option<RgbFrame>> = vec![None; pages.len()];
+        let pages = &pages;
+        let frames = futures::stream::select_all(listeners)
+            .then(move |(index, event)| async move {
+                ack_screencast_frame(&pages[index], &event).await?;
+                let (_, _, width, height) = encode_cells[index];
+                Ok((
+                    index,
+                    decode_screencast_frame(event.data.as_ref(), width, height),
+                ))
+            })
+            .map(move |decoded: Result<_>| {
+                let (index, decoded) = decoded?;
+                Ok(decoded.map(|frame| {
+                    cells[index] = Some(frame);
+                    composite_grid(config.width, config.height, &cells)
+                }))
+            });
+
+        pump_frames(
+            config,
+            until_handler_fails(frames, handler_failure),
+            encoder,
+            last_frame,
+            produced_frames,
+            Some(&mut timeline),
+            cancel,
+        )
+        .await
     }
+    .await;
+
+    for navigation_guard in navigation_guards {
+        navigation_guard.abort();
+    }
+    if let Err(err) = encoder
+        .hold_frame_while(last_frame.as_ref(), frame_interval, session.close())
+        .await
+    {
+        warn!("failed to hold frame while closing browser: {err:#}");
+    }
+
+    stream_result
 }
 
-fn parse_truthy(value: &str) -> bool {
-    matches!(
-        value.trim().to_ascii_lowercase().as_str(),
-        "1" | "true" | "yes"
-    )
+/// Ends `frames` with [`RuntimeError::BrowserDisconnected`] once the CDP handler
+/// stops, so a frame pump notices the browser going away.
+fn until_handler_fails<S>(
+    frames: S,
+    handler_failure: HandlerFailure,
+) -> impl Stream<Item = SourceItem>
+where
+    S: Stream<Item = SourceItem>,
+{
+    let disconnected = futures::stream::once(handler_failure).map(|failure| {
+        let reason = failure.unwrap_or_else(|_| "CDP handler task stopped".to_string());
+        Err(RuntimeError::BrowserDisconnected { reason }.into())
+    });
+    futures::stream::select(frames, disconnected)
 }
 
-pub fn chromium_executable_name() -> &'static str {
-    if cfg!(target_os = "windows") {
-        "headless_shell.exe"
-    } else {
-        "headless_shell"
+/// Opens one more page per URL in `urls` next to the session's page, set up the
+/// same way. Their `--allow-domain` guards are added to `navigation_guards`.
+async fn open_grid_pages(
+    browser: &Browser,
+    config: &AppConfig,
+    urls: &[url::Url],
+    navigation_guards: &mut Vec<JoinHandle<()>>,
+) -> Result<Vec<Page>> {
+    let mut pages = Vec::with_capacity(urls.len());
+    for url in urls {
+        let page = browser
+            .new_page(config.splash_url.as_str())
+            .await
+            .context("failed to create page")?;
+        if config.capture_cursor {
+            install_cursor_overlay(&page).await?;
+        }
+        apply_page_overrides(&page, config).await?;
+        navigation_guards.extend(spawn_navigation_guard(&page, config).await?);
+        page.goto(url.as_str())
+            .await
+            .with_context(|| format!("failed loading {url}"))?;
+        check_navigation_status(&page, config).await?;
+        pages.push(page);
     }
+    tokio::time::sleep(Duration::from_millis(config.startup_delay_ms)).await;
+    Ok(pages)
 }
 
-pub fn default_chromium_sidecar_path(exe_dir: &Path) -> PathBuf {
-    exe_dir
-        .join("..")
-        .join("sidecar")
-        .join("chromium")
-        .join(chromium_executable_name())
-}
+/// `--screenshot-mode`: streams the page into `encoder` from a PNG screenshot taken
+/// every `--screenshot-interval-sec` instead of a screencast. Each screenshot is
+/// repeated at the output rate until the next one replaces it, so a mostly static
+/// page costs one capture and decode per interval rather than one per paint.
+/// `last_frame`, `produced_frames` and `cancel` behave as in
+/// [`stream_browser_to_encoder`].
+///
+/// Runtime controls and the page-health checks of [`stream_browser_to_encoder`]
+/// only apply to screencast streams.
+pub async fn stream_screenshots_to_encoder(
+    config: &AppConfig,
+    chromium_path: Option<&Path>,
+    encoder: &mut FfmpegEncoder,
+    last_frame: &mut Option<RgbFrame>,
+    produced_frames: &mut bool,
+    cancel: &CancellationToken,
+) -> Result<()> {
+    let frame_interval = config.fps.interval();
+    let mut timeline = StartupTimeline::new(Instant::now());
+    let (session, handler_failure) = encoder
+        .hold_frame_while(
+            last_frame.as_ref(),
+            frame_interval,
+            launch_session(config, chromium_path, &mut timeline),
+        )
+        .await??;
+    timeline.encoder_spawned(encoder.spawned_at());
 
-#[derive(Debug, Copy, Clone)]
-enum ControlCommand {
-    Refresh,
-    Help,
+    if let Some(path) = config.verify_screenshot.as_deref()
+        && let Err(err) = save_verify_screenshot(&session.page, path).await
+    {
+        warn!("{err:#}");
+    }
+
+    // Captures run on their own task so a slow screenshot does not delay frame ticks.
+    let (shot_tx, shots) = mpsc::channel(1);
+    let capture_task = tokio::spawn(capture_screenshots(
+        session.page.clone(),
+        Duration::from_secs(config.screenshot_interval_sec),
+        shot_tx,
+    ));
+    info!(
+        interval_sec = config.screenshot_interval_sec,
+        "streaming page screenshots"
+    );
+
+    let shots = futures::stream::unfold(shots, |mut shots| async move {
+        let shot = shots.recv().await?;
+        Some((shot, shots))
+    });
+    let frames = shots
+        .map(|shot| shot.map(|data| decode_screencast_frame(&data, config.width, config.height)));
+    let stream_result = pump_frames(
+        config,
+        until_handler_fails(frames, handler_failure),
+        encoder,
+        last_frame,
+        produced_frames,
+        Some(&mut timeline),
+        cancel,
+    )
+    .await;
+
+    capture_task.abort();
+    if let Err(err) = encoder
+        .hold_frame_while(last_frame.as_ref(), frame_interval, session.close())
+        .await
+    {
+        warn!("failed to hold frame while closing browser: {err:#}");
+    }
+
+    stream_result
 }
 
-fn parse_control_command(input: &str) -> Option<ControlCommand> {
-    match input.trim().to_ascii_lowercase().as_str() {
-        "r" | "refresh" => Some(ControlCommand::Refresh),
-        "h" | "help" => Some(ControlCommand::Help),
-        _ => None,
+/// Sends a base64 PNG screenshot of `page` to `shots` right away and then every
+/// `interval`, until the receiver is dropped. Capture failures are sent too.
+async fn capture_screenshots(page: Page, interval: Duration, shots: mpsc::Sender<Result<String>>) {
+    let params = CaptureScreenshotParams::builder()
+        .format(CaptureScreenshotFormat::Png)
+        .build();
+    let mut tick = tokio::time::interval(interval);
+    tick.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    loop {
+        tick.tick().await;
+        let shot = page
+            .execute(params.clone())
+            .await
+            .map(|response| AsRef::<str>::as_ref(&response.result.data).to_string())
+            .context("failed to capture screenshot");
+        if shots.send(shot).await.is_err() {
+            return;
+        }
     }
 }
 
-fn spawn_control_listener() -> mpsc::UnboundedReceiver<ControlCommand> {
-    let (tx, rx) = mpsc::unbounded_channel();
+/// Runs the browser and screencast without an encoder, handing every decoded frame
+/// to `on_frame` until it returns [`ControlFlow::Break`]. Fails like a stream
+/// attempt would: no first frame within `--frame-timeout-ms`, too many undecodable
+/// frames in a row, or the browser going away.
+///
+/// Runtime controls, pacing and the page-health checks of
+/// [`stream_browser_to_encoder`] are not applied; frames arrive at the rate
+/// Chromium paints them.
+pub async fn capture_frames<F>(
+    config: &AppConfig,
+    chromium_path: Option<&Path>,
+    on_frame: F,
+) -> Result<()>
+where
+    F: FnMut(&RgbFrame) -> ControlFlow<()>,
+{
+    let (session, handler_failure) = launch_session(
+        config,
+        chromium_path,
+        &mut StartupTimeline::new(Instant::now()),
+    )
+    .await?;
+    let page = &session.page;
 
-    tokio::spawn(async move {
-        let mut lines = BufReader::new(tokio::io::stdin()).lines();
-        while let Ok(Some(line)) = lines.next_line().await {
-            if let Some(command) = parse_control_command(&line) {
-                if tx.send(command).is_err() {
-                    break;
-                }
+    let result = async {
+        let frame_events = page
+            .event_listener::<EventScreencastFrame>()
+            .await
+            .context("failed to register screencast event listener")?;
+        page.execute(screencast_params(config))
+            .await
+            .context("failed to start screencast")?;
+
+        let payloads = frame_events.then(|event| async move {
+            if let Err(err) = ack_screencast_frame(page, &event).await {
+                warn!("{err:#}");
             }
+            AsRef::<str>::as_ref(&event.data).to_string()
+        });
+        tokio::select! {
+            result = forward_decoded_frames(Box::pin(payloads), config, on_frame) => result,
+            reason = handler_failure => Err(RuntimeError::BrowserDisconnected {
+                reason: reason.unwrap_or_else(|_| "CDP handler stopped".to_string()),
+            }
+            .into()),
         }
+    }
+    .await;
+
+    if let Err(err) = page.execute(StopScreencastParams::default()).await {
+        warn!("failed to stop screencast cleanly: {err}");
+    }
+    session.close().await;
+    result
+}
+
+/// Decodes the base64 images from `payloads` at the configured size and hands
+/// them to `on_frame`: the browser-independent half of [`capture_frames`]. Ends
+/// with `Ok` once `on_frame` breaks, and with an error when `payloads` ends first.
+pub async fn forward_decoded_frames<S, F>(
+    payloads: S,
+    config: &AppConfig,
+    mut on_frame: F,
+) -> Result<()>
+where
+    S: Stream<Item = String> + Unpin,
+    F: FnMut(&RgbFrame) -> ControlFlow<()>,
+{
+    let decoded = payloads.map(|payload| {
+        Ok(decode_screencast_frame(
+            &payload,
+            config.width,
+            config.height,
+        ))
     });
+    let frames = checked_frames(decoded, config);
+    tokio::pin!(frames);
+    let mut delivered_frames: u64 = 0;
+    loop {
+        let frame = frames
+            .next()
+            .await
+            .context("screencast event stream ended unexpectedly")??;
+        delivered_frames = delivered_frames.saturating_add(1);
+        if on_frame(&frame).is_break() {
+            debug!(delivered_frames, "frame callback stopped the capture");
+            return Ok(());
+        }
+    }
+}
 
-    rx
+/// Launches chromium, loads the website and writes a single screenshot to `path`
+/// without starting the screencast.
+pub async fn capture_verify_screenshot(
+    config: &AppConfig,
+    chromium_path: Option<&Path>,
+    path: &Path,
+) -> Result<()> {
+    let (session, _) = launch_session(
+        config,
+        chromium_path,
+        &mut StartupTimeline::new(Instant::now()),
+    )
+    .await?;
+    let result = save_verify_screenshot(&session.page, path).await;
+    session.close().await;
+    result
 }
 
-#[cfg(test)]
-mod tests {
-    use super::{ControlCommand, parse_control_command, parse_truthy};
+/// Launches chromium, loads the website and prints the CDP targets and the page's
+/// frame tree to stdout. Cross-origin iframes show up as separate `iframe` targets.
+pub async fn list_cdp_targets(config: &AppConfig, chromium_path: Option<&Path>) -> Result<()> {
+    let (mut session, _) = launch_session(
+        config,
+        chromium_path,
+        &mut StartupTimeline::new(Instant::now()),
+    )
+    .await?;
+    let result = print_cdp_targets(&mut session).await;
+    session.close().await;
+    result
+}
 
-    #[test]
-    fn parses_refresh_shortcut() {
-        assert!(matches!(
-            parse_control_command("r"),
-            Some(ControlCommand::Refresh)
-        ));
+async fn print_cdp_targets(session: &mut BrowserSession) -> Result<()> {
+    let targets = session
+        .browser
+        .fetch_targets()
+        .await
+        .context("failed to fetch CDP targets")?;
+
+    println!("targets:");
+    for target in &targets {
+        println!(
+            "  [{}] {} {} {:?}",
+            target.r#type,
+            target.target_id.as_ref(),
+            target.url,
+            target.title
+        );
     }
 
-    #[test]
-    fn parses_refresh_word() {
-        assert!(matches!(
-            parse_control_command(" refresh "),
-            Some(ControlCommand::Refresh)
-        ));
+    let frame_tree = session
+        .page
+        .execute(GetFrameTreeParams::default())
+        .await
+        .context("failed to fetch frame tree")?;
+
+    println!("frames:");
+    print_frame_tree(&frame_tree.result.frame_tree, 1);
+    Ok(())
+}
+
+fn print_frame_tree(tree: &FrameTree, depth: usize) {
+    let indent = "  ".repeat(depth);
+    let frame = &tree.frame;
+    match frame.name.as_deref() {
+        Some(name) if !name.is_empty() => {
+            println!("{indent}{} {} (name={name})", frame.id.as_ref(), frame.url)
+        }
+        _ => println!("{indent}{} {}", frame.id.as_ref(), frame.url),
     }
 
-    #[test]
-    fn ignores_unknown_commands() {
-        assert!(parse_control_command("noop").is_none());
+    for child in tree.child_frames.iter().flatten() {
+        print_frame_tree(child, depth + 1);
     }
+}
 
-    #[test]
-    fn truthy_parser() {
-        assert!(parse_truthy("true"));
-        assert!(parse_truthy("1"));
-        assert!(parse_truthy("YES"));
-        assert!(!parse_truthy("0"));
-        assert!(!parse_truthy("false"));
+type HandlerFailure = oneshot::Receiver<String>;
+
+/// Whether a CDP handler error means the connection to the browser is gone.
+/// Others, such as a message chromiumoxide cannot parse from a newer Chromium or
+/// a timed-out request, leave the event loop usable.
+fn handler_error_is_fatal(err: &CdpError) -> bool {
+    matches!(
+        err,
+        CdpError::Ws(_)
+            | CdpError::Io(_)
+            | CdpError::ChannelSendError(_)
+            | CdpError::LaunchExit(..)
+            | CdpError::LaunchTimeout(_)
+            | CdpError::LaunchIo(..)
+    )
+}
+
+struct BrowserSession {
+    browser: Browser,
+    page: Page,
+    handler_task: JoinHandle<()>,
+    navigation_guard: Option<JoinHandle<()>>,
+    /// Attached to a browser we did not launch, which must be left running.
+    attached: bool,
+    /// The page was created by us rather than selected from existing tabs.
+    owns_page: bool,
+}
+
+impl BrowserSession {
+    /// Process id of a browser we launched; `None` when attached.
+    fn browser_pid(&mut self) -> Option<u32> {
+        self.browser
+            .get_mut_child()
+            .and_then(|child| child.as_mut_inner().id())
+    }
+
+    async fn close(mut self) {
+        if let Some(navigation_guard) = self.navigation_guard.take() {
+            navigation_guard.abort();
+        }
+        if self.attached {
+            if self.owns_page
+                && let Err(err) = self.page.close().await
+            {
+                warn!("failed to close page cleanly: {err}");
+            }
+            self.handler_task.abort();
+            return;
+        }
+
+        if let Err(err) = self.browser.close().await {
+            warn!("failed to close browser cleanly: {err}");
+        }
+        if let Err(err) = self.browser.wait().await {
+            warn!("failed to wait for browser process: {err}");
+        }
+
+        self.handler_task.abort();
+    }
+}
+
+/// Launches chromium and loads the website. The returned receiver resolves with a
+/// reason once the CDP handler stops, which means the page can no longer be driven.
+async fn launch_session(
+    config: &AppConfig,
+    chromium_path: Option<&Path>,
+    timeline: &mut StartupTimeline,
+) -> Result<(BrowserSession, HandlerFailure)> {
+    let viewport = Viewport {
+        width: config.capture_width,
+        height: config.capture_height,
+        device_scale_factor: Some(1.0),
+        emulating_mobile: false,
+        is_landscape: config.capture_width >= config.capture_height,
+        has_touch: false,
+    };
+
+    let starting = async {
+        match config.connect_url.as_deref() {
+            Some(connect_url) => {
+                info!(connect_url, "attaching to running chromium");
+                let handler_config = HandlerConfig {
+                    viewport: Some(viewport),
+                    ..HandlerConfig::default()
+                };
+                Browser::connect_with_config(connect_url, handler_config)
+                    .await
+                    .with_context(|| format!("failed to connect to chromium at {connect_url}"))
+            }
+            None => {
+                let chromium_path =
+                    chromium_path.context("chromium path is required unless attaching")?;
+                let browser_config = build_browser_config(config, chromium_path, viewport)?;
+                info!(chromium = %chromium_path.display(), "starting chromium");
+                Browser::launch(browser_config)
+                    .await
+                    .context("failed to launch chromium")
+            }
+        }
+    };
+
+    let launch_timeout = Duration::from_millis(config.browser_launch_timeout_ms);
+    let (mut browser, mut handler) = match tokio::time::timeout(launch_timeout, starting).await {
+        Ok(started) => started?,
+        Err(_) => {
+            return Err(RuntimeError::BrowserLaunchTimeout {
+                timeout_ms: config.browser_launch_timeout_ms,
+            }
+            .into());
+        }
+    };
+    timeline.browser_launched(Instant::now());
+
+    let (failure_tx, handler_failure) = oneshot::channel();
+    let max_handler_errors = config.max_handler_errors;
+    let handler_events = async move {
+        let mut consecutive_errors: u32 = 0;
+        let reason = loop {
+            match handler.next().await {
+                Some(Ok(())) => consecutive_errors = 0,
+                Some(Err(err)) if handler_error_is_fatal(&err) => {
+                    error!("chromium handler error: {err}");
+                    break err.to_string();
+                }
+                Some(Err(err)) => {
+                    consecutive_errors = consecutive_errors.saturating_add(1);
+                    if consecutive_errors >= max_handler_errors {
+                        error!(consecutive_errors, "chromium handler error: {err}");
+                        break format!(
+                            "{consecutive_errors} consecutive handler errors, last: {err}"
+                        );
+                    }
+                    warn!(consecutive_errors, "ignoring chromium handler error: {err}");
+                }
+                None => break "CDP event stream ended".to_string(),
+            }
+        };
+        let _ = failure_tx.send(reason);
+    };
+    let handler_task = tokio::spawn(handler_events.in_current_span());
+
+    if let Some(filter) = config.target_filter.as_deref() {
+        let page = attach_to_existing_page(&mut browser, filter).await?;
+        if config.capture_cursor {
+            install_cursor_overlay(&page).await?;
+        }
+        apply_page_overrides(&page, config).await?;
+        let navigation_guard = spawn_navigation_guard(&page, config).await?;
+
+        return Ok((
+            BrowserSession {
+                browser,
+                page,
+                handler_task,
+                navigation_guard,
+                attached: true,
+                owns_page: false,
+            },
+            handler_failure,
+        ));
+    }
+
+    let page = browser
+        .new_page(config.splash_url.as_str())
+        .await
+        .context("failed to create page")?;
+
+    if config.capture_cursor {
+        install_cursor_overlay(&page).await?;
+    }
+    apply_page_overrides(&page, config).await?;
+    let navigation_guard = spawn_navigation_guard(&page, config).await?;
+
+    page.goto(config.website_url.as_str())
+        .await
+        .with_context(|| format!("failed loading {}", config.website_url))?;
+    timeline.page_loaded(Instant::now());
+    check_navigation_status(&page, config).await?;
+
+    // `goto` waits for page load completion. Delay further for dynamic JS/CSS settling.
+    tokio::time::sleep(Duration::from_millis(config.startup_delay_ms)).await;
+
+    Ok((
+        BrowserSession {
+            browser,
+            page,
+            handler_task,
+            navigation_guard,
+            attached: config.connect_url.is_some(),
+            owns_page: true,
+        },
+        handler_failure,
+    ))
+}
+
+/// Logs the HTTP status of the page's current document and, with
+/// `--fail-on-http-error`, turns a 4xx/5xx into [`RuntimeError::HttpError`] so the
+/// retry loop reconnects instead of streaming an error page.
+async fn check_navigation_status(page: &Page, config: &AppConfig) -> Result<()> {
+    let request =
+        tokio::time::timeout(Duration::from_secs(5), page.wait_for_navigation_response()).await;
+    let Ok(Ok(Some(request))) = request else {
+        debug!("navigation response unavailable");
+        return Ok(());
+    };
+    let Some(response) = request.response.as_ref() else {
+        debug!("navigation finished without an HTTP response");
+        return Ok(());
+    };
+    let status = u16::try_from(response.status).unwrap_or(u16::MAX);
+    let url = response.url.clone();
+    if status < 400 {
+        info!(status, url, "page responded");
+        return Ok(());
+    }
+    if config.fail_on_http_error {
+        return Err(RuntimeError::HttpError { status, url }.into());
+    }
+    warn!(
+        status,
+        url, "page responded with an HTTP error; streaming it anyway"
+    );
+    Ok(())
+}
+
+/// Applies per-page emulation settings. Called on the initial page and again after
+/// every reload so the settings survive navigation.
+async fn apply_page_overrides(page: &Page, config: &AppConfig) -> Result<()> {
+    if let Some(throttle) = config.network_throttle {
+        apply_network_throttle(page, throttle).await?;
+    }
+
+    if let Some(media) = config.emulate_media {
+        page.execute(
+            SetEmulatedMediaParams::builder()
+                .media(media.as_str())
+                .build(),
+        )
+        .await
+        .context("failed to emulate media type")?;
+        info!(media = media.as_str(), "emulating CSS media type");
+    }
+
+    let headers = extra_http_headers(config);
+    if !headers.is_empty() {
+        let names: Vec<&str> = headers.iter().map(|(name, _)| *name).collect();
+        let headers: serde_json::Map<String, serde_json::Value> = headers
+            .into_iter()
+            .map(|(name, value)| (name.to_string(), value.into()))
+            .collect();
+        page.execute(SetExtraHttpHeadersParams::new(Headers::new(headers)))
+            .await
+            .context("failed to set extra HTTP headers")?;
+        info!(headers = ?names, "applied extra HTTP headers");
+    }
+
+    Ok(())
+}
+
+/// Screencast frames never contain the mouse pointer, so `--capture-cursor` draws
+/// one into the page instead. It follows `mousemove`/`mousedown` events and stays
+/// hidden until the first one.
+const CURSOR_OVERLAY_SCRIPT: &str = r#"(() => {
+  if (window.__browserStreamCursor) return;
+  window.__browserStreamCursor = true;
+  const arrow = "<svg xmlns='http://www.w3.org/2000/svg' width='16' height='24'>"
+    + "<path d='M1 1 L1 19 L5.5 14.5 L8.5 21.5 L11 20.5 L8 13.5 L14 13.5 Z' "
+    + "fill='black' stroke='white' stroke-width='1.5'/></svg>";
+  const cursor = document.createElement("div");
+  cursor.style.cssText = "position:fixed;left:0;top:0;width:16px;height:24px;"
+    + "z-index:2147483647;pointer-events:none;display:none;"
+    + `background:url("data:image/svg+xml,${encodeURIComponent(arrow)}") no-repeat;`;
+  const follow = (event) => {
+    cursor.style.transform = `translate(${event.clientX}px, ${event.clientY}px)`;
+    cursor.style.display = "block";
+    if (!cursor.isConnected) (document.body || document.documentElement).appendChild(cursor);
+  };
+  window.addEventListener("mousemove", follow, true);
+  window.addEventListener("mousedown", follow, true);
+})();"#;
+
+/// Registers [`CURSOR_OVERLAY_SCRIPT`] for every document the page loads, and
+/// runs it on the current one.
+async fn install_cursor_overlay(page: &Page) -> Result<()> {
+    page.execute(
+        AddScriptToEvaluateOnNewDocumentParams::builder()
+            .source(CURSOR_OVERLAY_SCRIPT)
+            .run_immediately(true)
+            .build()
+            .map_err(|err| anyhow!("invalid cursor overlay script params: {err}"))?,
+    )
+    .await
+    .context("failed to install cursor overlay")?;
+    info!("drawing cursor overlay");
+    Ok(())
+}
+
+/// Pauses main-frame document requests and aborts those whose host is outside
+/// `--allow-domain`, so the page stays on its current document. Does nothing when
+/// no allowlist is configured.
+async fn spawn_navigation_guard(page: &Page, config: &AppConfig) -> Result<Option<JoinHandle<()>>> {
+    if config.allow_domains.is_empty() {
+        return Ok(None);
+    }
+
+    let mut paused_requests = page
+        .event_listener::<EventRequestPaused>()
+        .await
+        .context("failed to register request interception listener")?;
+    let pattern = RequestPattern::builder()
+        .resource_type(ResourceType::Document)
+        .request_stage(RequestStage::Request)
+        .build();
+    page.execute(FetchEnableParams::builder().pattern(pattern).build())
+        .await
+        .context("failed to enable request interception")?;
+    info!(domains = ?config.allow_domains, "restricting navigation to allowed domains");
+
+    let page = page.clone();
+    let allowed = config.allow_domains.clone();
+    let guard = async move {
+        while let Some(event) = paused_requests.next().await {
+            let main_frame = page.mainframe().await.ok().flatten();
+            let blocked = main_frame.as_ref() == Some(&event.frame_id)
+                && !navigation_allowed(&event.request.url, &allowed);
+            let result = if blocked {
+                warn!(url = %event.request.url, "blocked navigation outside allowed domains");
+                page.execute(FailRequestParams::new(
+                    event.request_id.clone(),
+                    ErrorReason::Aborted,
+                ))
+                .await
+                .map(|_| ())
+            } else {
+                page.execute(ContinueRequestParams::new(event.request_id.clone()))
+                    .await
+                    .map(|_| ())
+            };
+            if let Err(err) = result {
+                debug!("failed to resolve intercepted request: {err}");
+            }
+        }
+    };
+    Ok(Some(tokio::spawn(guard.in_current_span())))
+}
+
+/// Non-network schemes such as `about:` and `data:` are always allowed.
+fn navigation_allowed(url: &str, allowed: &[String]) -> bool {
+    match url::Url::parse(url) {
+        Ok(url) if matches!(url.scheme(), "http" | "https") => url
+            .host_str()
+            .is_some_and(|host| domain_allowed(host, allowed)),
+        Ok(_) => true,
+        Err(_) => false,
+    }
+}
+
+/// Forwards page console output, log entries and uncaught exceptions to tracing
+/// under the `console` target. Errors are logged at warn, everything else at debug.
+async fn spawn_console_logger(page: &Page) -> Result<JoinHandle<()>> {
+    let mut console_events = page
+        .event_listener::<EventConsoleApiCalled>()
+        .await
+        .context("failed to register console event listener")?;
+    let mut log_events = page
+        .event_listener::<EventEntryAdded>()
+        .await
+        .context("failed to register log event listener")?;
+    let mut exception_events = page
+        .event_listener::<EventExceptionThrown>()
+        .await
+        .context("failed to register exception event listener")?;
+    page.execute(LogEnableParams::default())
+        .await
+        .context("failed to enable log domain")?;
+
+    let forward = async move {
+        let mut limiter = ConsoleRateLimiter::new(Instant::now());
+        loop {
+            let (is_error, message) = tokio::select! {
+                Some(event) = console_events.next() => (
+                    event.r#type == ConsoleApiCalledType::Error,
+                    format!("console.{}: {}", event.r#type.as_ref(), format_console_args(&event.args)),
+                ),
+                Some(event) = log_events.next() => (
+                    event.entry.level == LogEntryLevel::Error,
+                    match event.entry.url.as_deref() {
+                        Some(url) => format!("{}: {} ({url})", event.entry.source.as_ref(), event.entry.text),
+                        None => format!("{}: {}", event.entry.source.as_ref(), event.entry.text),
+                    },
+                ),
+                Some(event) = exception_events.next() => {
+                    let details = &event.exception_details;
+                    let description = details
+                        .exception
+                        .as_ref()
+                        .and_then(|exception| exception.description.as_deref())
+                        .unwrap_or(&details.text);
+                    (true, format!("uncaught exception: {description}"))
+                }
+                else => break,
+            };
+
+            let (admitted, dropped) = limiter.admit(Instant::now());
+            if dropped > 0 {
+                warn!(target: "console", dropped, "suppressed noisy page console output");
+            }
+            if !admitted {
+                continue;
+            }
+            if is_error {
+                warn!(target: "console", "{message}");
+            } else {
+                debug!(target: "console", "{message}");
+            }
+        }
+    };
+    Ok(tokio::spawn(forward.in_current_span()))
+}
+
+fn format_console_args(args: &[RemoteObject]) -> String {
+    args.iter()
+        .map(|arg| match (&arg.value, &arg.description) {
+            (Some(serde_json::Value::String(value)), _) => value.clone(),
+            (Some(value), _) => value.to_string(),
+            (None, Some(description)) => description.clone(),
+            (None, None) => arg.r#type.as_ref().to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Caps forwarded console messages per window so noisy pages cannot flood the log.
+struct ConsoleRateLimiter {
+    window_start: Instant,
+    forwarded: u32,
+    dropped: u64,
+}
+
+impl ConsoleRateLimiter {
+    const WINDOW: Duration = Duration::from_secs(10);
+    const MAX_PER_WINDOW: u32 = 50;
+
+    fn new(now: Instant) -> Self {
+        Self {
+            window_start: now,
+            forwarded: 0,
+            dropped: 0,
+        }
+    }
+
+    /// Returns whether a message may be logged now, plus the number of messages
+    /// dropped in the previous window once that window has elapsed.
+    fn admit(&mut self, now: Instant) -> (bool, u64) {
+        let mut dropped = 0;
+        if now.duration_since(self.window_start) >= Self::WINDOW {
+            dropped = std::mem::take(&mut self.dropped);
+            self.window_start = now;
+            self.forwarded = 0;
+        }
+
+        if self.forwarded < Self::MAX_PER_WINDOW {
+            self.forwarded += 1;
+            (true, dropped)
+        } else {
+            self.dropped += 1;
+            (false, dropped)
+        }
+    }
+}
+
+/// Queue counters reported in the streaming stats, updated by [`screencast_frames`]
+/// while the stream loop reads them.
+#[derive(Default)]
+struct ScreencastQueueStats {
+    backlog: AtomicUsize,
+    /// Largest backlog since the last stats line; reset when it is reported.
+    peak_backlog: AtomicUsize,
+    backpressured_frames: AtomicU64,
+    stale_dropped_frames: AtomicU64,
+    buffer_dropped_frames: AtomicU64,
+}
+
+/// Acks and decodes the page's screencast `events` into items for
+/// [`checked_frames`]. Events that arrived while the previous one was decoded are
+/// queued; only the newest frames reach the output, so the oldest past
+/// [`FRAME_BACKLOG_SOFT_CAP`] or `--max-frame-buffer-bytes` are acked unread.
+fn screencast_frames<'a>(
+    page: &'a Page,
+    config: &'a AppConfig,
+    events: EventStream<EventScreencastFrame>,
+    stats: &'a ScreencastQueueStats,
+) -> impl Stream<Item = SourceItem> + 'a {
+    futures::stream::unfold(
+        (events, VecDeque::new()),
+        move |(mut events, mut queued)| async move {
+            match next_screencast_frame(page, config, &mut events, &mut queued, stats).await {
+                Ok(Some(decoded)) => Some((Ok(decoded), (events, queued))),
+                Ok(None) => None,
+                Err(err) => Some((Err(err), (events, queued))),
+            }
+        },
+    )
+}
+
+async fn next_screencast_frame(
+    page: &Page,
+    config: &AppConfig,
+    events: &mut EventStream<EventScreencastFrame>,
+    queued: &mut VecDeque<Arc<EventScreencastFrame>>,
+    stats: &ScreencastQueueStats,
+) -> Result<Option<Result<RgbFrame>>> {
+    let mut event = match queued.pop_front() {
+        Some(event) => event,
+        None => match events.next().await {
+            Some(event) => event,
+            None => return Ok(None),
+        },
+    };
+    while let Some(Some(next)) = events.next().now_or_never() {
+        queued.push_back(next);
+    }
+    stats
+        .peak_backlog
+        .fetch_max(queued.len(), Ordering::Relaxed);
+    while queued.len() > FRAME_BACKLOG_SOFT_CAP {
+        ack_screencast_frame(page, &event).await?;
+        stats.stale_dropped_frames.fetch_add(1, Ordering::Relaxed);
+        event = queued.pop_front().expect("queue is longer than the cap");
+    }
+    if config.max_frame_buffer_bytes.is_some() {
+        let dropped = drop_frames_over_buffer_limit(page, config, queued).await?;
+        stats
+            .buffer_dropped_frames
+            .fetch_add(dropped, Ordering::Relaxed);
+    }
+    stats.backlog.store(queued.len(), Ordering::Relaxed);
+
+    // Each ack lets Chromium send another frame. Past the soft cap, hold the ack
+    // until this frame is decoded so Chromium slows to our decode rate.
+    let backpressure = queued.len() >= FRAME_BACKLOG_SOFT_CAP;
+    if !backpressure {
+        ack_screencast_frame(page, &event).await?;
+    }
+    let decoded = decode_screencast_frame(event.data.as_ref(), config.width, config.height);
+    if backpressure {
+        ack_screencast_frame(page, &event).await?;
+        stats.backpressured_frames.fetch_add(1, Ordering::Relaxed);
+    }
+    Ok(Some(decoded))
+}
+
+/// Screencast frames already queued behind the one being handled before acks are
+/// deferred until after decode. Older frames past it are dropped undecoded, so the
+/// queue never holds more than this.
+const FRAME_BACKLOG_SOFT_CAP: usize = 3;
+
+/// How many of the oldest queued frames, with encoded sizes `queued_sizes`, must go
+/// for the queue plus `held_bytes` of decoded frames to fit in `limit`. The newest
+/// queued frame is never counted, so capture keeps moving however low the limit.
+fn frames_over_buffer_limit(queued_sizes: &[u64], held_bytes: u64, limit: u64) -> usize {
+    let mut total = held_bytes.saturating_add(queued_sizes.iter().sum());
+    let mut dropped = 0;
+    for size in queued_sizes
+        .iter()
+        .take(queued_sizes.len().saturating_sub(1))
+    {
+        if total <= limit {
+            break;
+        }
+        total -= size;
+        dropped += 1;
+    }
+    dropped
+}
+
+/// Drops the oldest queued screencast frames past `--max-frame-buffer-bytes`,
+/// acking each so Chromium keeps sending. The budget covers the queue, the frame
+/// about to be decoded and every decoded frame the stream loop can hold: the
+/// latest and last frames, plus the pending one with `--reload-wait`. Returns how
+/// many were dropped.
+async fn drop_frames_over_buffer_limit(
+    page: &Page,
+    config: &AppConfig,
+    queued_events: &mut VecDeque<Arc<EventScreencastFrame>>,
+) -> Result<u64> {
+    let Some(limit) = config.max_frame_buffer_bytes else {
+        return Ok(0);
+    };
+    let held_frames = 3 + u64::from(config.reload_wait);
+    let held_bytes = held_frames * u64::from(config.width) * u64::from(config.height) * 3;
+    let queued_sizes: Vec<u64> = queued_events
+        .iter()
+        .map(|event| AsRef::<str>::as_ref(&event.data).len() as u64)
+        .collect();
+    let over = frames_over_buffer_limit(&queued_sizes, held_bytes, limit);
+    for event in queued_events.drain(..over) {
+        ack_screencast_frame(page, &event).await?;
+    }
+    Ok(over as u64)
+}
+
+fn screencast_params(config: &AppConfig) -> StartScreencastParams {
+    StartScreencastParams::builder()
+        .format(StartScreencastFormat::Jpeg)
+        .quality(80_i64)
+        .max_width(i64::from(config.capture_width))
+        .max_height(i64::from(config.capture_height))
+        .every_nth_frame(1_i64)
+        .build()
+}
+
+async fn ack_screencast_frame(page: &Page, event: &EventScreencastFrame) -> Result<()> {
+    page.execute(ScreencastFrameAckParams::new(event.session_id))
+        .await
+        .context("failed to ack screencast frame")?;
+    Ok(())
+}
+
+/// Tracks late frame ticks per stats window to detect a host that cannot sustain `--fps`.
+#[derive(Debug, Default)]
+struct PacingMonitor {
+    ticks: u32,
+    late_ticks: u32,
+    strained_windows: u32,
+}
+
+impl PacingMonitor {
+    /// Consecutive windows with at least 20% late ticks before lag counts as sustained.
+    const SUSTAINED_WINDOWS: u32 = 3;
+
+    fn record_tick(&mut self, late: bool) {
+        self.ticks = self.ticks.saturating_add(1);
+        if late {
+            self.late_ticks = self.late_ticks.saturating_add(1);
+        }
+    }
+
+    /// Closes the current window, returning its late tick count and whether lag
+    /// has now been sustained.
+    fn end_window(&mut self) -> (u32, bool) {
+        let late_ticks = std::mem::take(&mut self.late_ticks);
+        let ticks = std::mem::take(&mut self.ticks);
+        if ticks > 0 && late_ticks.saturating_mul(5) >= ticks {
+            self.strained_windows = self.strained_windows.saturating_add(1);
+        } else {
+            self.strained_windows = 0;
+        }
+        (late_ticks, self.strained_windows >= Self::SUSTAINED_WINDOWS)
+    }
+}
+
+/// Compares `frame` with the `--diff-alert` baseline, warning once when the
+/// difference rises above `threshold` and logging again when it falls back.
+fn check_baseline_diff(frame: &RgbFrame, baseline: &RgbFrame, threshold: u8, alerting: &mut bool) {
+    let Some(diff) = mean_abs_diff(frame, baseline) else {
+        return;
+    };
+    let diff = (diff * 100.0).round() / 100.0;
+    let over = diff > f64::from(threshold);
+    if over && !*alerting {
+        warn!(diff, threshold, "frame differs from the baseline image");
+    } else if !over && *alerting {
+        info!(diff, threshold, "frame matches the baseline image again");
+    } else {
+        debug!(diff, threshold, "compared frame with the baseline image");
+    }
+    *alerting = over;
+}
+
+/// Per-channel difference still treated as the same color by `--blank-timeout-sec`,
+/// to absorb JPEG noise.
+const BLANK_COLOR_TOLERANCE: u8 = 2;
+
+/// Drives `--auto-scroll`: rests at the top, scrolls down in whole-pixel steps,
+/// rests at the bottom, jumps back to the top and starts over.
+#[derive(Debug)]
+struct AutoScroller {
+    /// Time between scroll steps; long enough that each step is at least 1px.
+    interval: Duration,
+    step_px: f64,
+    pause: Duration,
+    paused_until: Instant,
+    at_bottom: bool,
+}
+
+#[derive(Debug, PartialEq)]
+enum ScrollAction {
+    Wait,
+    ScrollBy(f64),
+    ToTop,
+}
+
+impl AutoScroller {
+    const MIN_INTERVAL: Duration = Duration::from_millis(50);
+
+    fn new(px_per_sec: u32, pause: Duration, now: Instant) -> Self {
+        let interval = Self::MIN_INTERVAL.max(Duration::from_secs(1) / px_per_sec.max(1));
+        Self {
+            interval,
+            step_px: f64::from(px_per_sec) * interval.as_secs_f64(),
+            pause,
+            paused_until: now + pause,
+            at_bottom: false,
+        }
+    }
+
+    /// Starts over from the top, e.g. after the page was reloaded.
+    fn restart(&mut self, now: Instant) {
+        self.paused_until = now + self.pause;
+        self.at_bottom = false;
+    }
+
+    fn next(&mut self, now: Instant) -> ScrollAction {
+        if now < self.paused_until {
+            return ScrollAction::Wait;
+        }
+        if self.at_bottom {
+            self.restart(now);
+            return ScrollAction::ToTop;
+        }
+        ScrollAction::ScrollBy(self.step_px)
+    }
+
+    fn reached_bottom(&mut self, now: Instant) {
+        self.at_bottom = true;
+        self.paused_until = now + self.pause;
+    }
+}
+
+/// Runs one [`AutoScroller`] step in the page. Returns whether a downward step
+/// hit the bottom, or could not move at all.
+async fn apply_scroll_action(page: &Page, action: ScrollAction) -> Result<bool> {
+    let expression = match action {
+        ScrollAction::Wait => return Ok(false),
+        ScrollAction::ToTop => "window.scrollTo(0, 0), false".to_string(),
+        ScrollAction::ScrollBy(step) => format!(
+            "(() => {{ const before = window.scrollY; window.scrollBy(0, {step}); \
+             const max = document.documentElement.scrollHeight - window.innerHeight; \
+             return window.scrollY >= max - 1 || window.scrollY === before; }})()"
+        ),
+    };
+    let result = page.evaluate(expression).await?;
+    Ok(result.into_value()?)
+}
+
+/// Next lower frame rate to try when the host cannot keep up, if any.
+fn downshift_fps(fps: FrameRate) -> Option<FrameRate> {
+    const LADDER: [u32; 6] = [60, 30, 24, 20, 15, 10];
+    LADDER
+        .into_iter()
+        .find(|step| f64::from(*step) < fps.as_f64())
+        .map(FrameRate::from)
+}
+
+fn extra_http_headers(config: &AppConfig) -> Vec<(&'static str, String)> {
+    let mut headers = Vec::new();
+    if let Some(referer) = config.referer.as_ref() {
+        headers.push(("Referer", referer.clone()));
+    }
+    if let Some(origin) = config.origin.as_ref() {
+        headers.push(("Origin", origin.clone()));
+    }
+    if let Some(accept_language) = config.accept_language.as_ref() {
+        headers.push(("Accept-Language", accept_language.clone()));
+    }
+    headers
+}
+
+// `Network.emulateNetworkConditions` is deprecated upstream in favor of rule-based
+// emulation, which chromiumoxide does not expose yet.
+#[allow(deprecated)]
+async fn apply_network_throttle(page: &Page, throttle: NetworkThrottle) -> Result<()> {
+    use chromiumoxide::cdp::browser_protocol::network::EmulateNetworkConditionsParams;
+
+    page.execute(EmulateNetworkConditionsParams::new(
+        false,
+        f64::from(throttle.latency_ms),
+        kbps_to_bytes_per_sec(throttle.download_kbps),
+        kbps_to_bytes_per_sec(throttle.upload_kbps),
+    ))
+    .await
+    .context("failed to apply network throttle")?;
+
+    info!(
+        download_kbps = throttle.download_kbps,
+        upload_kbps = throttle.upload_kbps,
+        latency_ms = throttle.latency_ms,
+        "applied network throttle"
+    );
+    Ok(())
+}
+
+fn kbps_to_bytes_per_sec(kbps: u32) -> f64 {
+    f64::from(kbps) * 1000.0 / 8.0
+}
+
+/// Picks the first existing page target whose URL or title contains `filter`.
+async fn attach_to_existing_page(browser: &mut Browser, filter: &str) -> Result<Page> {
+    let targets = browser
+        .fetch_targets()
+        .await
+        .context("failed to fetch CDP targets")?;
+
+    let matches = matching_page_targets(&targets, filter);
+    let target = matches
+        .first()
+        .ok_or_else(|| RuntimeError::NoMatchingTarget {
+            filter: filter.to_string(),
+        })?;
+
+    if matches.len() > 1 {
+        warn!(
+            matches = matches.len(),
+            "multiple pages match --target-filter; using the first"
+        );
+    }
+    info!(
+        target_id = target.target_id.as_ref(),
+        url = %target.url,
+        title = %target.title,
+        "attaching to existing page"
+    );
+
+    // `fetch_targets` registers existing targets asynchronously; give the handler a moment.
+    for _ in 0..20 {
+        if let Ok(page) = browser.get_page(target.target_id.clone()).await {
+            return Ok(page);
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+
+    Err(anyhow!(
+        "page target {} did not become available",
+        target.target_id.as_ref()
+    ))
+}
+
+fn matching_page_targets<'a>(targets: &'a [TargetInfo], filter: &str) -> Vec<&'a TargetInfo> {
+    targets
+        .iter()
+        .filter(|target| target.r#type == "page")
+        .filter(|target| target.url.contains(filter) || target.title.contains(filter))
+        .collect()
+}
+
+fn build_browser_config(
+    config: &AppConfig,
+    chromium_path: &Path,
+    viewport: Viewport,
+) -> Result<BrowserConfig> {
+    let mut browser_builder = BrowserConfig::builder()
+        .chrome_executable(chromium_path)
+        .window_size(config.capture_width, config.capture_height)
+        .new_headless_mode()
+        .viewport(viewport)
+        .arg("--autoplay-policy=no-user-gesture-required")
+        .arg("--disable-background-timer-throttling")
+        .arg("--disable-backgrounding-occluded-windows")
+        .arg("--disable-renderer-backgrounding");
+
+    if no_sandbox_from_env() {
+        browser_builder = browser_builder.no_sandbox();
+    }
+
+    if let Some((x, y)) = config.window_position {
+        browser_builder = browser_builder.arg(format!("--window-position={x},{y}"));
+    }
+
+    if let Some(gpu) = config.gpu {
+        browser_builder = browser_builder.args(gpu.chromium_args().iter().copied());
+    }
+
+    if !config.host_resolver_rules.is_empty() {
+        browser_builder = browser_builder.arg(format!(
+            "--host-resolver-rules={}",
+            config.host_resolver_rules.join(", ")
+        ));
+    }
+
+    if config.incognito {
+        browser_builder = browser_builder.incognito();
+    }
+
+    if let Some(lang) = config
+        .accept_language
+        .as_deref()
+        .and_then(|value| value.split([',', ';']).next())
+        .filter(|lang| *lang != "*")
+    {
+        browser_builder = browser_builder.arg(format!("--lang={lang}"));
+    }
+
+    if let Some(disk_cache_size) = config.disk_cache_size {
+        browser_builder = browser_builder.arg(format!("--disk-cache-size={disk_cache_size}"));
+    }
+
+    if let Some(user_data_dir) = config.user_data_dir.as_deref() {
+        browser_builder = browser_builder.user_data_dir(user_data_dir);
+        if config.clear_stale_lock {
+            clear_stale_singleton_lock(user_data_dir);
+        }
+    }
+
+    browser_builder
+        .build()
+        .map_err(|err| anyhow!("failed to build browser config: {err}"))
+}
+
+/// Writes `frame` to `dir` as `frame-<index>.png` on a blocking thread, unless the
+/// previous write in `in_flight` is still running; a slow disk then skips frames
+/// instead of piling up copies of them. Failures are only logged.
+fn dump_frame(
+    in_flight: &mut Option<tokio::task::JoinHandle<()>>,
+    dir: &Path,
+    index: u64,
+    frame: &RgbFrame,
+) {
+    if in_flight.as_ref().is_some_and(|task| !task.is_finished()) {
+        debug!(
+            index,
+            "previous frame dump still running; skipping this one"
+        );
+        return;
+    }
+    let path = dir.join(format!("frame-{index:08}.png"));
+    let frame = frame.clone();
+    *in_flight = Some(tokio::task::spawn_blocking(move || {
+        match save_png(&frame, &path) {
+            Ok(()) => debug!(path = %path.display(), "dumped frame"),
+            Err(err) => warn!("failed to dump frame: {err:#}"),
+        }
+    }));
+}
+
+async fn save_verify_screenshot(page: &Page, path: &Path) -> Result<()> {
+    let params = ScreenshotParams::builder()
+        .format(CaptureScreenshotFormat::Png)
+        .build();
+
+    page.save_screenshot(params, path)
+        .await
+        .with_context(|| format!("failed to save verify screenshot to {}", path.display()))?;
+
+    info!(path = %path.display(), "saved verify screenshot");
+    Ok(())
+}
+
+fn no_sandbox_from_env() -> bool {
+    match std::env::var("BROWSER_STREAM_NO_SANDBOX") {
+        Ok(value) => parse_truthy(&value),
+        Err(_) => false,
+    }
+}
+
+const SINGLETON_FILES: [&str; 3] = ["SingletonLock", "SingletonSocket", "SingletonCookie"];
+
+/// Removes Chromium's profile singleton files when the process that created
+/// them is no longer running. Chromium refuses to start on a profile whose
+/// `SingletonLock` points at another live process, so a hard-killed previous
+/// run would otherwise block every relaunch.
+fn clear_stale_singleton_lock(user_data_dir: &Path) {
+    let lock_path = user_data_dir.join("SingletonLock");
+    let Ok(target) = std::fs::read_link(&lock_path) else {
+        return;
+    };
+
+    let Some(pid) = target.to_str().and_then(parse_singleton_lock_pid) else {
+        warn!(lock = %lock_path.display(), "unrecognized SingletonLock target; leaving it in place");
+        return;
+    };
+
+    if process_is_alive(pid) {
+        warn!(pid, lock = %lock_path.display(), "profile lock is held by a running process");
+        return;
+    }
+
+    for name in SINGLETON_FILES {
+        let path = user_data_dir.join(name);
+        match std::fs::remove_file(&path) {
+            Ok(()) => info!(pid, path = %path.display(), "removed stale chromium singleton file"),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+            Err(err) => {
+                warn!(path = %path.display(), "failed to remove stale singleton file: {err}")
+            }
+        }
+    }
+}
+
+/// `SingletonLock` is a symlink whose target is `<hostname>-<pid>`.
+fn parse_singleton_lock_pid(target: &str) -> Option<u32> {
+    let (_, pid) = target.rsplit_once('-')?;
+    pid.parse().ok()
+}
+
+#[cfg(target_os = "linux")]
+fn process_is_alive(pid: u32) -> bool {
+    Path::new("/proc").join(pid.to_string()).exists()
+}
+
+#[cfg(all(unix, not(target_os = "linux")))]
+fn process_is_alive(pid: u32) -> bool {
+    std::process::Command::new("kill")
+        .args(["-0", &pid.to_string()])
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .status()
+        .is_ok_and(|status| status.success())
+}
+
+#[cfg(not(unix))]
+fn process_is_alive(_pid: u32) -> bool {
+    // Chromium does not use a symlink lock on Windows; never remove anything there.
+    true
+}
+
+/// Resident memory of `root` and all of its descendants, in KiB. Chromium spreads
+/// its memory across renderer and GPU child processes, so the root alone is not enough.
+fn process_tree_rss_kb(root: u32) -> Option<u64> {
+    let processes = list_processes()?;
+    tree_rss_kb(&processes, root)
+}
+
+/// `(pid, ppid, rss_kb)` for every visible process.
+#[cfg(target_os = "linux")]
+fn list_processes() -> Option<Vec<(u32, u32, u64)>> {
+    let mut processes = Vec::new();
+    for entry in std::fs::read_dir("/proc").ok()?.flatten() {
+        let Some(pid) = entry
+            .file_name()
+            .to_str()
+            .and_then(|name| name.parse().ok())
+        else {
+            continue;
+        };
+        let Ok(status) = std::fs::read_to_string(entry.path().join("status")) else {
+            continue;
+        };
+        let field = |name: &str| {
+            status
+                .lines()
+                .find_map(|line| line.strip_prefix(name))
+                .and_then(|value| value.split_whitespace().next())
+                .and_then(|value| value.parse::<u64>().ok())
+        };
+        // Kernel threads have no `VmRSS` line.
+        if let Some(ppid) = field("PPid:") {
+            processes.push((pid, ppid as u32, field("VmRSS:").unwrap_or(0)));
+        }
+    }
+    Some(processes)
+}
+
+#[cfg(all(unix, not(target_os = "linux")))]
+fn list_processes() -> Option<Vec<(u32, u32, u64)>> {
+    let output = std::process::Command::new("ps")
+        .args(["-A", "-o", "pid=,ppid=,rss="])
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    Some(
+        stdout
+            .lines()
+            .filter_map(|line| {
+                let mut fields = line.split_whitespace().map(|field| field.parse().ok());
+                match (fields.next(), fields.next(), fields.next()) {
+                    (Some(Some(pid)), Some(Some(ppid)), Some(Some(rss_kb))) => {
+                        Some((pid as u32, ppid as u32, rss_kb))
+                    }
+                    _ => None,
+                }
+            })
+            .collect(),
+    )
+}
+
+#[cfg(not(unix))]
+fn list_processes() -> Option<Vec<(u32, u32, u64)>> {
+    // Not implemented on Windows; the memory guard never triggers there.
+    None
+}
+
+fn tree_rss_kb(processes: &[(u32, u32, u64)], root: u32) -> Option<u64> {
+    if !processes.iter().any(|(pid, _, _)| *pid == root) {
+        return None;
+    }
+
+    let mut total = 0;
+    let mut pending = vec![root];
+    while let Some(current) = pending.pop() {
+        for (pid, ppid, rss_kb) in processes {
+            if *pid == current {
+                total += rss_kb;
+            }
+            if *ppid == current && *pid != current {
+                pending.push(*pid);
+            }
+        }
+    }
+    Some(total)
+}
+
+pub fn chromium_executable_name() -> &'static str {
+    if cfg!(target_os = "windows") {
+        "headless_shell.exe"
+    } else {
+        "headless_shell"
+    }
+}
+
+pub fn default_chromium_sidecar_path(sidecar_dir: &Path) -> PathBuf {
+    sidecar_dir
+        .join("chromium")
+        .join(chromium_executable_name())
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum ControlCommand {
+    Refresh,
+    Help,
+    /// Current audience size reported by an external watcher.
+    Viewers(u32),
+    /// New video bitrate in kbps.
+    Bitrate(u32),
+    /// Left click at viewport coordinates.
+    Click {
+        x: u32,
+        y: u32,
+    },
+    /// Text inserted at the focused element.
+    Type(String),
+    /// Mouse wheel scroll at the last click position.
+    Scroll {
+        dx: i32,
+        dy: i32,
+    },
+}
+
+/// Parses a stdin line as a runtime command. With a non-empty `prefix`, only lines
+/// starting with it are considered, so stdin can carry other data too.
+fn parse_control_command(input: &str, prefix: &str) -> Option<ControlCommand> {
+    let command = input.trim_start().strip_prefix(prefix)?.trim_start();
+    let (keyword, args) = command
+        .split_once(char::is_whitespace)
+        .unwrap_or((command, ""));
+    match keyword.to_ascii_lowercase().as_str() {
+        "r" | "refresh" if args.trim().is_empty() => Some(ControlCommand::Refresh),
+        "h" | "help" if args.trim().is_empty() => Some(ControlCommand::Help),
+        "viewers" => parse_command_args(args).map(|[count]| ControlCommand::Viewers(count)),
+        "bitrate" => parse_command_args(args).map(|[kbps]| ControlCommand::Bitrate(kbps)),
+        "click" => parse_command_args(args).map(|[x, y]| ControlCommand::Click { x, y }),
+        "scroll" => parse_command_args(args).map(|[dx, dy]| ControlCommand::Scroll { dx, dy }),
+        // The text is taken verbatim, including case and inner spacing.
+        "type" if !args.is_empty() => Some(ControlCommand::Type(args.to_string())),
+        _ => None,
+    }
+}
+
+/// Parses exactly `N` whitespace-separated numbers.
+fn parse_command_args<T: std::str::FromStr, const N: usize>(args: &str) -> Option<[T; N]> {
+    let values: Vec<T> = args
+        .split_whitespace()
+        .map(|arg| arg.parse().ok())
+        .collect::<Option<_>>()?;
+    values.try_into().ok()
+}
+
+/// Sends `click`/`type`/`scroll` commands to the page as CDP input events, in
+/// order, on a separate task so a slow page never stalls the frame loop. The task
+/// ends when the returned sender is dropped.
+fn spawn_input_dispatcher(page: Page, config: &AppConfig) -> mpsc::UnboundedSender<ControlCommand> {
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    // Scrolls happen under the pointer; before the first click that is the viewport center.
+    let mut pointer = (
+        f64::from(config.capture_width / 2),
+        f64::from(config.capture_height / 2),
+    );
+
+    let dispatch = async move {
+        while let Some(command) = rx.recv().await {
+            let result = match command {
+                ControlCommand::Click { x, y } => {
+                    pointer = (f64::from(x), f64::from(y));
+                    dispatch_click(&page, pointer).await
+                }
+                ControlCommand::Type(text) => page
+                    .execute(InsertTextParams::new(text))
+                    .await
+                    .map(|_| ())
+                    .context("failed to type text"),
+                ControlCommand::Scroll { dx, dy } => {
+                    let mut wheel = DispatchMouseEventParams::new(
+                        DispatchMouseEventType::MouseWheel,
+                        pointer.0,
+                        pointer.1,
+                    );
+                    wheel.delta_x = Some(f64::from(dx));
+                    wheel.delta_y = Some(f64::from(dy));
+                    page.execute(wheel)
+                        .await
+                        .map(|_| ())
+                        .context("failed to scroll")
+                }
+                _ => Ok(()),
+            };
+            if let Err(err) = result {
+                warn!("input command failed: {err:#}");
+            }
+        }
+    };
+    tokio::spawn(dispatch.in_current_span());
+
+    tx
+}
+
+/// Moves the mouse to `(x, y)`, then presses and releases the left button.
+async fn dispatch_click(page: &Page, (x, y): (f64, f64)) -> Result<()> {
+    page.execute(DispatchMouseEventParams::new(
+        DispatchMouseEventType::MouseMoved,
+        x,
+        y,
+    ))
+    .await
+    .context("failed to move mouse")?;
+    for kind in [
+        DispatchMouseEventType::MousePressed,
+        DispatchMouseEventType::MouseReleased,
+    ] {
+        let mut event = DispatchMouseEventParams::new(kind, x, y);
+        event.button = Some(MouseButton::Left);
+        event.click_count = Some(1);
+        page.execute(event).await.context("failed to click")?;
+    }
+    Ok(())
+}
+
+fn spawn_control_listener(prefix: String) -> mpsc::UnboundedReceiver<ControlCommand> {
+    let (tx, rx) = mpsc::unbounded_channel();
+
+    let listen = async move {
+        let mut lines = BufReader::new(tokio::io::stdin()).lines();
+        while let Ok(Some(line)) = lines.next_line().await {
+            if let Some(command) = parse_control_command(&line, &prefix)
+                && tx.send(command).is_err()
+            {
+                break;
+            }
+        }
+    };
+    tokio::spawn(listen.in_current_span());
+
+    rx
+}
+
+#[cfg(test)]
+mod tests {
+    use chromiumoxide::cdp::browser_protocol::target::TargetInfo;
+
+    use std::time::{Duration, Instant};
+
+    use crate::error::RuntimeError;
+
+    use super::{
+        AutoScroller, ConsoleRateLimiter, ControlCommand, PacingMonitor, ScrollAction,
+        browser_always_kept, browser_survives, fit_content_size, frames_over_buffer_limit,
+        handler_error_is_fatal, matching_page_targets, navigation_allowed, parse_control_command,
+        parse_singleton_lock_pid, parse_truthy, tree_rss_kb,
+    };
+
+    #[test]
+    fn parses_refresh_shortcut() {
+        assert!(matches!(
+            parse_control_command("r", ""),
+            Some(ControlCommand::Refresh)
+        ));
+    }
+
+    #[test]
+    fn parses_refresh_word() {
+        assert!(matches!(
+            parse_control_command(" refresh ", ""),
+            Some(ControlCommand::Refresh)
+        ));
+    }
+
+    #[test]
+    fn ignores_unknown_commands() {
+        assert!(parse_control_command("noop", "").is_none());
+    }
+
+    #[test]
+    fn requires_control_prefix_when_configured() {
+        assert!(matches!(
+            parse_control_command(":: refresh", "::"),
+            Some(ControlCommand::Refresh)
+        ));
+        assert!(parse_control_command("r", "::").is_none());
+        assert!(parse_control_command("{\"r\": 1}", "::").is_none());
+    }
+
+    #[test]
+    fn fits_capture_to_content_within_limits() {
+        assert_eq!(fit_content_size(1280, 3001, 3840, 2160), (1280, 2160));
+        assert_eq!(fit_content_size(5000, 721, 3840, 2160), (3840, 720));
+        assert_eq!(fit_content_size(0, 9, 3840, 2160), (16, 16));
+    }
+
+    #[test]
+    fn classifies_handler_errors() {
+        use chromiumoxide::error::CdpError;
+
+        let closed = CdpError::Ws(async_tungstenite::tungstenite::Error::ConnectionClosed);
+        let io = CdpError::Io(std::io::Error::from(std::io::ErrorKind::ConnectionReset));
+        let unparsed = CdpError::Serde(
+            serde_json::from_str::<u32>("{").expect_err("invalid JSON should fail"),
+        );
+
+        assert!(handler_error_is_fatal(&closed));
+        assert!(handler_error_is_fatal(&io));
+        assert!(!handler_error_is_fatal(&unparsed));
+        assert!(!handler_error_is_fatal(&CdpError::Timeout));
+        assert!(!handler_error_is_fatal(&CdpError::msg("no such node")));
+    }
+
+    #[test]
+    fn keeps_browser_only_for_encoder_failures() {
+        let downshift = anyhow::Error::from(RuntimeError::FpsDownshift {
+            from: 30.into(),
+            to: 24.into(),
+        });
+        let bitrate = anyhow::Error::from(RuntimeError::BitrateChange {
+            from: 4500,
+            to: 3000,
+        });
+        let broken_pipe = anyhow::Error::new(std::io::Error::from(std::io::ErrorKind::BrokenPipe))
+            .context("failed writing frame to ffmpeg stdin");
+        let disconnected = anyhow::Error::from(RuntimeError::BrowserDisconnected {
+            reason: "CDP event stream ended".to_string(),
+        });
+
+        assert!(browser_survives(&downshift));
+        assert!(browser_survives(&bitrate));
+        assert!(browser_always_kept(&bitrate));
+        assert!(!browser_always_kept(&downshift));
+        assert!(browser_survives(&broken_pipe));
+        assert!(!browser_survives(&disconnected));
+        assert!(!browser_survives(&anyhow::Error::from(
+            RuntimeError::ScreencastTimeout
+        )));
+    }
+
+    #[test]
+    fn parses_viewer_count() {
+        assert!(matches!(
+            parse_control_command("viewers 0", ""),
+            Some(ControlCommand::Viewers(0))
+        ));
+        assert!(matches!(
+            parse_control_command(" Viewers  12 ", ""),
+            Some(ControlCommand::Viewers(12))
+        ));
+        assert!(parse_control_command("viewers many", "").is_none());
+        assert!(parse_control_command("viewers", "").is_none());
+    }
+
+    #[test]
+    fn parses_bitrate() {
+        assert_eq!(
+            parse_control_command("bitrate 3000", ""),
+            Some(ControlCommand::Bitrate(3000))
+        );
+        assert!(parse_control_command("bitrate -1", "").is_none());
+        assert!(parse_control_command("bitrate 3000 4000", "").is_none());
+        assert!(parse_control_command("bitrate", "").is_none());
+    }
+
+    #[test]
+    fn parses_input_commands() {
+        assert_eq!(
+            parse_control_command("click 100 200", ""),
+            Some(ControlCommand::Click { x: 100, y: 200 })
+        );
+        assert_eq!(
+            parse_control_command("SCROLL 0 -300", ""),
+            Some(ControlCommand::Scroll { dx: 0, dy: -300 })
+        );
+        assert_eq!(
+            parse_control_command("type Hello,  World", ""),
+            Some(ControlCommand::Type("Hello,  World".to_string()))
+        );
+        assert!(parse_control_command("click 100", "").is_none());
+        assert!(parse_control_command("click -1 5", "").is_none());
+        assert!(parse_control_command("scroll 1 2 3", "").is_none());
+        assert!(parse_control_command("type", "").is_none());
+    }
+
+    #[test]
+    fn truthy_parser() {
+        assert!(parse_truthy("true"));
+        assert!(parse_truthy("1"));
+        assert!(parse_truthy("YES"));
+        assert!(!parse_truthy("0"));
+        assert!(!parse_truthy("false"));
+    }
+
+    #[test]
+    fn parses_singleton_lock_pid() {
+        assert_eq!(parse_singleton_lock_pid("build-host-01-4242"), Some(4242));
+        assert_eq!(parse_singleton_lock_pid("no-pid-here"), None);
+        assert_eq!(parse_singleton_lock_pid("4242"), None);
+    }
+
+    fn target(id: &str, kind: &str, url: &str, title: &str) -> TargetInfo {
+        TargetInfo::builder()
+            .target_id(id.to_string())
+            .r#type(kind)
+            .url(url)
+            .title(title)
+            .attached(false)
+            .can_access_opener(false)
+            .build()
+            .expect("target info should build")
+    }
+
+    #[test]
+    fn matches_page_targets_by_url_or_title() {
+        let targets = [
+            target(
+                "1",
+                "service_worker",
+                "https://grafana.example.com/sw.js",
+                "",
+            ),
+            target("2", "page", "https://example.com/", "Grafana - Overview"),
+            target(
+                "3",
+                "page",
+                "https://grafana.example.com/d/abc",
+                "Dashboard",
+            ),
+            target("4", "page", "https://other.example.com/", "Other"),
+        ];
+
+        let by_title: Vec<_> = matching_page_targets(&targets, "Grafana")
+            .into_iter()
+            .map(|target| target.target_id.as_ref().to_string())
+            .collect();
+        assert_eq!(by_title, ["2"]);
+
+        let by_url: Vec<_> = matching_page_targets(&targets, "grafana.example.com")
+            .into_iter()
+            .map(|target| target.target_id.as_ref().to_string())
+            .collect();
+        assert_eq!(by_url, ["3"]);
+
+        assert!(matching_page_targets(&targets, "missing").is_empty());
+    }
+
+    #[test]
+    fn checks_navigation_against_allowed_domains() {
+        let allowed = vec!["example.com".to_string()];
+
+        assert!(navigation_allowed("https://www.example.com/page", &allowed));
+        assert!(navigation_allowed("about:blank", &allowed));
+        assert!(!navigation_allowed("https://evil.test/", &allowed));
+        assert!(!navigation_allowed("http://notexample.com/", &allowed));
+    }
+
+    #[test]
+    fn rate_limits_console_messages_per_window() {
+        let start = Instant::now();
+        let mut limiter = ConsoleRateLimiter::new(start);
+
+        for _ in 0..ConsoleRateLimiter::MAX_PER_WINDOW {
+            assert_eq!(limiter.admit(start), (true, 0));
+        }
+        assert_eq!(limiter.admit(start), (false, 0));
+        assert_eq!(limiter.admit(start), (false, 0));
+
+        let next_window = start + ConsoleRateLimiter::WINDOW + Duration::from_millis(1);
+        assert_eq!(limiter.admit(next_window), (true, 2));
+        assert_eq!(limiter.admit(next_window), (true, 0));
+    }
+
+    #[test]
+    fn sums_rss_across_process_tree() {
+        let processes = [
+            (1, 0, 10),
+            (100, 1, 1000),
+            (101, 100, 200),
+            (102, 100, 300),
+            (103, 102, 50),
+            (200, 1, 9999),
+        ];
+
+        assert_eq!(tree_rss_kb(&processes, 100), Some(1550));
+        assert_eq!(tree_rss_kb(&processes, 103), Some(50));
+        assert_eq!(tree_rss_kb(&processes, 999), None);
+    }
+
+    #[test]
+    fn drops_oldest_frames_over_buffer_limit() {
+        assert_eq!(frames_over_buffer_limit(&[100, 100, 100], 500, 1000), 0);
+        assert_eq!(frames_over_buffer_limit(&[300, 200, 100], 500, 900), 1);
+        assert_eq!(frames_over_buffer_limit(&[300, 200, 100], 500, 650), 2);
+        // The newest queued frame survives even when the held frames alone exceed the limit.
+        assert_eq!(frames_over_buffer_limit(&[300, 200, 100], 5000, 1000), 2);
+        assert_eq!(frames_over_buffer_limit(&[], 5000, 1000), 0);
+    }
+
+    #[test]
+    fn detects_sustained_late_ticks() {
+        let mut pacing = PacingMonitor::default();
+        for window in 1..=PacingMonitor::SUSTAINED_WINDOWS {
+            for tick in 0..10 {
+                pacing.record_tick(tick < 3);
+            }
+            let (late_ticks, sustained) = pacing.end_window();
+            assert_eq!(late_ticks, 3);
+            assert_eq!(sustained, window == PacingMonitor::SUSTAINED_WINDOWS);
+        }
+
+        for _ in 0..10 {
+            pacing.record_tick(false);
+        }
+        assert_eq!(pacing.end_window(), (0, false));
+    }
+
+    #[test]
+    fn auto_scroll_pauses_at_both_ends() {
+        let start = Instant::now();
+        let pause = Duration::from_secs(3);
+        let mut scroller = AutoScroller::new(60, pause, start);
+        assert_eq!(scroller.interval, Duration::from_millis(50));
+        assert_eq!(scroller.next(start), ScrollAction::Wait);
+
+        let scrolling = start + pause;
+        assert_eq!(scroller.next(scrolling), ScrollAction::ScrollBy(3.0));
+        scroller.reached_bottom(scrolling);
+        assert_eq!(scroller.next(scrolling + pause / 2), ScrollAction::Wait);
+
+        let back_up = scrolling + pause;
+        assert_eq!(scroller.next(back_up), ScrollAction::ToTop);
+        assert_eq!(scroller.next(back_up), ScrollAction::Wait);
+        assert_eq!(scroller.next(back_up + pause), ScrollAction::ScrollBy(3.0));
+
+        // Slow speeds stretch the interval instead of scrolling fractions of a pixel.
+        let slow = AutoScroller::new(4, pause, start);
+        assert_eq!(slow.interval, Duration::from_millis(250));
+        assert_eq!(slow.step_px, 1.0);
     }
 }
@@ -1,6 +1,14 @@
-use std::path::PathBuf;
+use std::ffi::OsString;
+use std::fmt;
+use std::io::BufRead;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
-use clap::Parser;
+use clap::error::ErrorKind;
+use clap::parser::ValueSource;
+use clap::{Arg, ArgAction, Command, CommandFactory, Parser, Subcommand};
+use serde::{Serialize, Serializer};
+use tracing::{info, warn};
 use url::Url;
 
 use crate::error::ConfigError;
@@ -9,11 +17,18 @@ use crate::error::ConfigError;
 #[command(
     name = "browser-stream",
     version,
-    about = "Stream a browser page to RTMP"
+    about = "Stream a browser page to RTMP",
+    args_override_self = true,
+    subcommand_negates_reqs = true
 )]
 pub struct CliArgs {
-    #[arg(long)]
-    pub url: String,
+    #[command(subcommand)]
+    pub command: Option<CliCommand>,
+
+    /// Required unless a subcommand, `--grid`, `--test-pattern` or `--frame-source`
+    /// is given. `-` reads it from the first line of stdin.
+    #[arg(long, required_unless_present_any = ["grid", "test_pattern", "frame_source"])]
+    pub url: Option<String>,
 
     #[arg(long, default_value_t = 1920)]
     pub width: u32,
@@ -21,11 +36,42 @@ pub struct CliArgs {
     #[arg(long, default_value_t = 1080)]
     pub height: u32,
 
-    #[arg(long, default_value_t = 30)]
-    pub fps: u32,
+    #[arg(long)]
+    pub capture_width: Option<u32>,
 
-    #[arg(long, default_value_t = 4500)]
-    pub bitrate_kbps: u32,
+    #[arg(long)]
+    pub capture_height: Option<u32>,
+
+    #[arg(long, conflicts_with_all = ["width", "height"])]
+    pub scale: Option<f64>,
+
+    #[arg(long, default_value_t = false)]
+    pub round_dimensions: bool,
+
+    #[arg(
+        long,
+        default_value_t = false,
+        conflicts_with_all = ["scale", "grid", "frame_source", "test_pattern"]
+    )]
+    pub fit_content: bool,
+
+    #[arg(long)]
+    pub fit_content_max_width: Option<u32>,
+
+    #[arg(long)]
+    pub fit_content_max_height: Option<u32>,
+
+    #[arg(long, default_value = "30")]
+    pub fps: String,
+
+    #[arg(long)]
+    pub max_encode_fps: Option<u32>,
+
+    #[arg(long, default_value_t = false)]
+    pub adaptive_fps: bool,
+
+    #[arg(long, visible_alias = "bitrate", default_value = "4500")]
+    pub bitrate_kbps: String,
 
     #[arg(long, default_value_t = 1)]
     pub keyint_sec: u32,
@@ -33,6 +79,27 @@ pub struct CliArgs {
     #[arg(long, default_value = "bframes=0")]
     pub x264_opts: String,
 
+    #[arg(long, default_value_t = false)]
+    pub strict: bool,
+
+    #[arg(long)]
+    pub tune: Option<String>,
+
+    #[arg(long, default_value_t = false)]
+    pub keyframe_on_refresh: bool,
+
+    #[arg(long)]
+    pub force_keyframe_sec: Option<u32>,
+
+    #[arg(long, default_value = "yuv420p")]
+    pub output_pix_fmt: String,
+
+    #[arg(long, default_value = "limited")]
+    pub color_range: String,
+
+    #[arg(long)]
+    pub video_filter: Option<String>,
+
     #[arg(long)]
     pub rtmp_url: Option<String>,
 
@@ -42,95 +109,2245 @@ pub struct CliArgs {
     #[arg(long)]
     pub output: Option<String>,
 
+    #[arg(
+        long,
+        conflicts_with_all = ["segment_time_sec", "segment_pattern", "output_to_null", "record"]
+    )]
+    pub simulcast: Vec<String>,
+
+    #[arg(long)]
+    pub segment_time_sec: Option<u32>,
+
+    #[arg(long)]
+    pub segment_pattern: Option<String>,
+
+    #[arg(
+        long,
+        default_value_t = false,
+        conflicts_with_all = ["rtmp_url", "stream_key", "output", "segment_time_sec", "segment_pattern"]
+    )]
+    pub output_to_null: bool,
+
+    #[arg(
+        long,
+        conflicts_with_all = [
+            "simulcast",
+            "segment_time_sec",
+            "segment_pattern",
+            "output_to_null",
+            "rtmp_live",
+            "flush_packets",
+        ]
+    )]
+    pub hls_dir: Option<PathBuf>,
+
+    #[arg(long, default_value_t = false)]
+    pub rtmp_live: bool,
+
+    #[arg(long, default_value_t = false)]
+    pub flush_packets: bool,
+
+    #[arg(long, default_value_t = true, action = ArgAction::Set, conflicts_with = "hls_dir")]
+    pub tls_verify: bool,
+
+    #[arg(long, conflicts_with = "hls_dir")]
+    pub ca_file: Option<PathBuf>,
+
+    #[arg(long)]
+    pub on_backpressure: Option<String>,
+
+    #[arg(long)]
+    pub backpressure_threshold_ms: Option<u64>,
+
+    #[arg(long)]
+    pub record: Option<String>,
+
+    #[arg(long)]
+    pub record_format: Option<String>,
+
+    #[arg(long)]
+    pub record_codec: Option<String>,
+
+    #[arg(long, default_value_t = false)]
+    pub preflight: bool,
+
     #[arg(long, default_value_t = 5)]
     pub retries: u32,
 
     #[arg(long, default_value_t = 1000)]
     pub retry_backoff_ms: u64,
 
+    #[arg(long, default_value_t = false, conflicts_with_all = ["retries", "retry_backoff_ms"])]
+    pub once: bool,
+
+    #[arg(long)]
+    pub max_duration_sec: Option<u64>,
+
+    #[arg(
+        long,
+        conflicts_with_all = [
+            "rtmp_url",
+            "stream_key",
+            "simulcast",
+            "segment_time_sec",
+            "segment_pattern",
+            "output_to_null",
+            "hls_dir",
+            "max_duration_sec",
+            "restart_interval_sec",
+            "retries",
+            "retry_backoff_ms",
+        ]
+    )]
+    pub preview_sec: Option<u64>,
+
+    #[arg(long)]
+    pub restart_interval_sec: Option<u64>,
+
+    #[arg(long, default_value_t = false)]
+    pub persistent_encoder: bool,
+
+    #[arg(long, default_value_t = false, conflicts_with_all = ["frame_source", "test_pattern"])]
+    pub reuse_browser: bool,
+
+    #[arg(long, default_value_t = false, conflicts_with_all = ["frame_source", "test_pattern"])]
+    pub warm_start: bool,
+
+    #[arg(long)]
+    pub stall_image: Option<PathBuf>,
+
+    #[arg(long, conflicts_with = "once")]
+    pub fallback_video: Option<PathBuf>,
+
+    #[arg(long)]
+    pub fallback_restore_sec: Option<u64>,
+
     #[arg(long, default_value_t = 2000)]
     pub startup_delay_ms: u64,
 
+    #[arg(long, default_value_t = false)]
+    pub reload_wait: bool,
+
+    #[arg(long, default_value = "")]
+    pub control_prefix: String,
+
     #[arg(long, default_value_t = 30000)]
     pub frame_timeout_ms: u64,
 
+    #[arg(long, default_value_t = 20000)]
+    pub browser_launch_timeout_ms: u64,
+
+    #[arg(long, default_value_t = 15000)]
+    pub encoder_init_timeout_ms: u64,
+
+    #[arg(long, default_value_t = 10)]
+    pub max_decode_errors: u32,
+
+    #[arg(long, default_value_t = 5)]
+    pub max_handler_errors: u32,
+
+    #[arg(long, default_value_t = 0)]
+    pub skip_frames: u32,
+
+    #[arg(long)]
+    pub max_browser_memory_mb: Option<u64>,
+
+    #[arg(long, conflicts_with_all = ["frame_source", "test_pattern"])]
+    pub max_frame_buffer_bytes: Option<u64>,
+
     #[arg(long, default_value_t = false)]
     pub no_audio: bool,
 
+    #[arg(long, conflicts_with = "no_audio")]
+    pub audio_filter: Option<String>,
+
+    #[arg(long)]
+    pub stream_title: Option<String>,
+
+    #[arg(long)]
+    pub stream_description: Option<String>,
+
     #[arg(long)]
     pub ffmpeg_path: Option<PathBuf>,
 
+    #[arg(long, default_value = "auto")]
+    pub ffmpeg_source: String,
+
     #[arg(long)]
     pub chromium_path: Option<PathBuf>,
 
+    #[arg(long, global = true)]
+    pub sidecar_dir: Option<PathBuf>,
+
+    #[arg(
+        long,
+        conflicts_with_all = ["chromium_path",
+            "user_data_dir",
+            "gpu",
+            "webgl_software",
+            "incognito",
+            "disk_cache_size",
+            "window_position",
+            "max_browser_memory_mb",
+            "host_resolver_rules"]
+    )]
+    pub connect_url: Option<String>,
+
+    #[arg(
+        long,
+        conflicts_with_all = [
+            "connect_url",
+            "chromium_path",
+            "user_data_dir",
+            "target_filter",
+            "verify_screenshot",
+            "list_cdp_targets",
+            "allow_domains",
+            "skip_frames",
+        ]
+    )]
+    pub frame_source: Option<String>,
+
+    #[arg(
+        long,
+        conflicts_with_all = [
+            "connect_url",
+            "frame_source",
+            "chromium_path",
+            "user_data_dir",
+            "target_filter",
+            "verify_screenshot",
+            "list_cdp_targets",
+            "allow_domains",
+            "skip_frames",
+        ]
+    )]
+    pub test_pattern: Option<String>,
+
+    #[arg(
+        long,
+        value_delimiter = ',',
+        conflicts_with_all = [
+            "url",
+            "connect_url",
+            "frame_source",
+            "test_pattern",
+            "target_filter",
+            "warm_start",
+            "reuse_browser",
+            "verify_screenshot",
+            "list_cdp_targets",
+            "auto_scroll",
+            "blank_timeout_sec",
+            "diff_alert",
+            "max_frame_buffer_bytes",
+            "dump_frames",
+            "reload_wait",
+            "control_prefix",
+            "skip_frames",
+        ]
+    )]
+    pub grid: Vec<String>,
+
+    #[arg(long, default_value = "about:blank")]
+    pub splash_url: String,
+
+    #[arg(long)]
+    pub target_filter: Option<String>,
+
+    #[arg(long = "allow-domain", value_delimiter = ',')]
+    pub allow_domains: Vec<String>,
+
+    #[arg(long)]
+    pub network_throttle: Option<String>,
+
+    #[arg(long)]
+    pub referer: Option<String>,
+
+    #[arg(long)]
+    pub origin: Option<String>,
+
+    #[arg(long)]
+    pub accept_language: Option<String>,
+
+    #[arg(long)]
+    pub gpu: Option<String>,
+
+    #[arg(long, default_value_t = false, conflicts_with = "gpu")]
+    pub webgl_software: bool,
+
+    #[arg(long)]
+    pub window_position: Option<String>,
+
+    #[arg(long = "host-resolver-rule", value_delimiter = ',')]
+    pub host_resolver_rules: Vec<String>,
+
+    #[arg(long)]
+    pub emulate_media: Option<String>,
+
+    #[arg(long, default_value_t = false, conflicts_with_all = ["frame_source", "test_pattern"])]
+    pub capture_cursor: bool,
+
+    #[arg(long, conflicts_with_all = ["frame_source", "test_pattern"])]
+    pub auto_scroll: Option<u32>,
+
+    #[arg(long)]
+    pub scroll_pause_sec: Option<u64>,
+
+    #[arg(long, conflicts_with_all = ["frame_source", "test_pattern"])]
+    pub blank_timeout_sec: Option<u64>,
+
+    #[arg(long)]
+    pub on_blank: Option<String>,
+
+    #[arg(long, default_value_t = false, conflicts_with_all = ["frame_source", "test_pattern"])]
+    pub diff_alert: bool,
+
+    #[arg(long)]
+    pub baseline: Option<PathBuf>,
+
+    #[arg(long)]
+    pub diff_threshold: Option<u64>,
+
+    #[arg(long, conflicts_with_all = ["frame_source", "test_pattern"])]
+    pub dump_frames: Option<PathBuf>,
+
+    #[arg(long)]
+    pub dump_every: Option<u64>,
+
+    #[arg(
+        long,
+        default_value_t = false,
+        conflicts_with_all = [
+            "grid",
+            "frame_source",
+            "test_pattern",
+            "warm_start",
+            "reuse_browser",
+            "fit_content",
+            "adaptive_fps",
+            "auto_scroll",
+            "blank_timeout_sec",
+            "diff_alert",
+            "max_frame_buffer_bytes",
+            "dump_frames",
+        ]
+    )]
+    pub screenshot_mode: bool,
+
+    #[arg(long)]
+    pub screenshot_interval_sec: Option<u64>,
+
+    #[arg(long, default_value_t = false, conflicts_with_all = ["frame_source", "test_pattern"])]
+    pub fail_on_http_error: bool,
+
+    #[arg(long, default_value_t = false)]
+    pub log_console: bool,
+
+    #[arg(long)]
+    pub user_data_dir: Option<PathBuf>,
+
+    #[arg(long, default_value_t = false)]
+    pub clear_stale_lock: bool,
+
+    #[arg(long, default_value_t = false)]
+    pub incognito: bool,
+
+    #[arg(long)]
+    pub disk_cache_size: Option<u64>,
+
+    #[arg(long)]
+    pub verify_screenshot: Option<PathBuf>,
+
+    #[arg(long, default_value_t = false)]
+    pub verify_only: bool,
+
+    #[arg(long, default_value_t = false)]
+    pub list_cdp_targets: bool,
+
+    #[arg(long)]
+    pub config: Option<PathBuf>,
+
+    #[arg(long, default_value_t = false)]
+    pub print_config: bool,
+
+    #[arg(long, default_value_t = false)]
+    pub dry_run: bool,
+
+    #[arg(long, allow_negative_numbers = true)]
+    pub nice: Option<i32>,
+
+    #[arg(long)]
+    pub ready_file: Option<PathBuf>,
+
     #[arg(long, default_value_t = false)]
     pub verbose: bool,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct AppConfig {
     pub website_url: Url,
     pub width: u32,
     pub height: u32,
-    pub fps: u32,
+    pub capture_width: u32,
+    pub capture_height: u32,
+    /// Replace the capture and encode size with the loaded page's scroll size,
+    /// capped at `fit_content_max_width`x`fit_content_max_height`.
+    pub fit_content: bool,
+    pub fit_content_max_width: u32,
+    pub fit_content_max_height: u32,
+    /// Effective frame rate after `--max-encode-fps` is applied.
+    pub fps: FrameRate,
+    pub adaptive_fps: bool,
     pub bitrate_kbps: u32,
+    /// `--bitrate auto`: `bitrate_kbps` follows the size, so `--fit-content`
+    /// recomputes it once the page is measured.
+    pub bitrate_auto: bool,
     pub keyint_sec: u32,
     pub x264_opts: String,
+    pub tune: Option<String>,
+    pub keyframe_on_refresh: bool,
+    pub force_keyframe_sec: Option<u32>,
+    pub output_pix_fmt: String,
+    /// YUV range the output is encoded and tagged with, alongside BT.709 colors.
+    pub color_range: ColorRange,
+    /// ffmpeg video filtergraph applied to the captured frames (`-vf`).
+    pub video_filter: Option<String>,
     pub output: String,
+    /// One encode per destination when `--output` has a rendition suffix or
+    /// `--simulcast` is used; the first entry is `output`. Empty otherwise.
+    pub renditions: Vec<Rendition>,
+    pub segment_time_sec: Option<u32>,
+    /// Encode but discard the output (`-f null -`), for benchmarking.
+    pub output_to_null: bool,
+    /// Directory receiving an HLS copy of the main output through ffmpeg's tee muxer.
+    pub hls_dir: Option<PathBuf>,
+    /// Mark RTMP outputs as live streams (`-rtmp_live live`).
+    pub rtmp_live: bool,
+    /// Flush every packet to RTMP outputs as soon as it is muxed.
+    pub flush_packets: bool,
+    /// Verify the ingest's certificate on rtmps outputs.
+    pub tls_verify: bool,
+    /// CA bundle used to verify rtmps outputs instead of the system trust store.
+    pub ca_file: Option<PathBuf>,
+    /// What a frame write does when ffmpeg stops draining its input.
+    pub on_backpressure: BackpressurePolicy,
+    /// How long a write may block before `BackpressurePolicy::Drop` skips frames.
+    pub backpressure_threshold_ms: u64,
+    /// Local archive written alongside the main output.
+    pub record: Option<RecordConfig>,
+    pub preflight: bool,
     pub retries: u32,
     pub retry_backoff_ms: u64,
+    pub once: bool,
+    /// Stop each capture cleanly after this long.
+    pub max_duration_sec: Option<u64>,
+    /// `--preview-sec`: record this long to a local file, then exit. Sets
+    /// `max_duration_sec` and `once`.
+    pub preview_sec: Option<u64>,
+    /// After a `max_duration_sec` stop, start a new capture after this delay instead of exiting.
+    pub restart_interval_sec: Option<u64>,
+    /// Keep ffmpeg running across browser relaunches, holding the last frame in between.
+    pub persistent_encoder: bool,
+    /// Keep the browser running across retries caused by encoder-side failures.
+    pub reuse_browser: bool,
+    /// Load and settle the page before spawning ffmpeg for an attempt, so ffmpeg
+    /// spends less time connected without frames.
+    pub warm_start: bool,
+    /// Image shown instead of a frozen frame before the first frame and during restarts.
+    pub stall_image: Option<PathBuf>,
+    /// Video looped to the output once retries are exhausted.
+    pub fallback_video: Option<PathBuf>,
+    /// How long the fallback video plays before the live page is tried again.
+    pub fallback_restore_sec: u64,
     pub startup_delay_ms: u64,
+    /// After a manual refresh, keep streaming the previous frame for `startup_delay_ms`.
+    pub reload_wait: bool,
+    /// Only stdin lines starting with this are read as runtime commands.
+    pub control_prefix: String,
     pub frame_timeout_ms: u64,
+    pub browser_launch_timeout_ms: u64,
+    /// How long ffmpeg may take to open its output before the attempt fails.
+    pub encoder_init_timeout_ms: u64,
+    pub max_decode_errors: u32,
+    /// Consecutive recoverable CDP handler errors tolerated before the browser
+    /// counts as disconnected.
+    pub max_handler_errors: u32,
+    /// Decoded screencast frames to discard before streaming starts.
+    pub skip_frames: u32,
+    pub max_browser_memory_mb: Option<u64>,
+    /// Ceiling on screencast frames held in memory, queued and decoded together.
+    pub max_frame_buffer_bytes: Option<u64>,
     pub no_audio: bool,
+    /// ffmpeg audio filtergraph applied to the silent track (`-af`).
+    pub audio_filter: Option<String>,
+    /// `title` metadata of the streamed outputs, shown by ingests that read FLV `onMetaData`.
+    pub stream_title: Option<String>,
+    /// `description` metadata of the streamed outputs.
+    pub stream_description: Option<String>,
     pub ffmpeg_path: Option<PathBuf>,
+    /// Where ffmpeg is taken from when `ffmpeg_path` is not set.
+    pub ffmpeg_source: FfmpegSource,
     pub chromium_path: Option<PathBuf>,
+    /// Holds `ffmpeg/` and `chromium/` sidecars, instead of `../sidecar` next to
+    /// the executable.
+    pub sidecar_dir: Option<PathBuf>,
+    pub connect_url: Option<String>,
+    pub frame_source: Option<String>,
+    /// Synthesized frames instead of a browser, for testing the encoder and output.
+    pub test_pattern: Option<TestPattern>,
+    /// Pages composited 2x2 into each frame, in reading order. `website_url` is the
+    /// first of them. Empty for a single page.
+    pub grid: Vec<Url>,
+    pub splash_url: Url,
+    pub target_filter: Option<String>,
+    /// Domains the page may navigate its main frame to; empty allows everything.
+    /// Includes the host of `--url`.
+    pub allow_domains: Vec<String>,
+    pub network_throttle: Option<NetworkThrottle>,
+    pub referer: Option<String>,
+    pub origin: Option<String>,
+    /// `Accept-Language` header value; its first language also becomes Chromium's `--lang`.
+    pub accept_language: Option<String>,
+    pub gpu: Option<GpuMode>,
+    pub window_position: Option<(i32, i32)>,
+    /// Chromium `--host-resolver-rules` entries, e.g. `MAP dashboard.internal 10.0.0.5`.
+    pub host_resolver_rules: Vec<String>,
+    pub emulate_media: Option<EmulatedMedia>,
+    /// Draw a cursor overlay in the page that follows mouse events.
+    pub capture_cursor: bool,
+    /// Scroll the page top to bottom on a loop at this many pixels per second.
+    pub auto_scroll: Option<u32>,
+    /// Pause at the top and bottom of the page between `auto_scroll` passes.
+    pub scroll_pause_sec: u64,
+    /// How long the page may show a single flat color before `on_blank` kicks in.
+    pub blank_timeout_sec: Option<u64>,
+    pub on_blank: BlankAction,
+    /// With `--diff-alert`, the image the page's frames are compared against.
+    pub baseline: Option<PathBuf>,
+    /// Mean absolute difference per color channel, 1 to 255, above which a frame
+    /// counts as changed from `baseline`.
+    pub diff_threshold: u8,
+    /// Directory that every `dump_every`th decoded frame is written to as a PNG.
+    pub dump_frames: Option<PathBuf>,
+    pub dump_every: u64,
+    /// Capture the page with a screenshot every `screenshot_interval_sec` instead
+    /// of a continuous screencast.
+    pub screenshot_mode: bool,
+    pub screenshot_interval_sec: u64,
+    /// Fail the attempt when the page's document loads with a 4xx/5xx status.
+    pub fail_on_http_error: bool,
+    pub log_console: bool,
+    pub user_data_dir: Option<PathBuf>,
+    pub clear_stale_lock: bool,
+    /// Launch Chromium in incognito mode so no state carries over between runs.
+    pub incognito: bool,
+    /// Chromium disk cache limit in bytes.
+    pub disk_cache_size: Option<u64>,
+    pub verify_screenshot: Option<PathBuf>,
+    pub verify_only: bool,
+    pub list_cdp_targets: bool,
+    pub print_config: bool,
+    /// Print the ffmpeg command of the first attempt, quoted for the host shell, and exit.
+    pub dry_run: bool,
+    /// Process niceness applied at startup (unix only); ffmpeg and Chromium inherit it.
+    pub nice: Option<i32>,
+    /// Created once the first frame reaches ffmpeg, and removed on exit.
+    pub ready_file: Option<PathBuf>,
     pub verbose: bool,
 }
 
+impl AppConfig {
+    /// Pretty JSON of the resolved configuration with the stream key redacted,
+    /// for `--print-config`.
+    pub fn to_redacted_json(&self) -> serde_json::Result<String> {
+        let mut redacted = self.clone();
+        redacted.output = crate::rtmp::redact_output(&self.output);
+        for rendition in &mut redacted.renditions {
+            rendition.output = crate::rtmp::redact_output(&rendition.output);
+        }
+        serde_json::to_string_pretty(&redacted)
+    }
+
+    /// How long each capture runs before stopping cleanly, if limited.
+    pub fn max_duration(&self) -> Option<Duration> {
+        self.max_duration_sec.map(Duration::from_secs)
+    }
+}
+
+pub const SUPPORTED_OUTPUT_PIX_FMTS: &[&str] = &["yuv420p", "yuv422p", "yuv444p", "nv12"];
+
+/// ffmpeg filters that change the frame geometry set by `--width`/`--height`.
+const GEOMETRY_FILTERS: &[&str] = &["scale", "pad", "crop", "transpose", "rotate"];
+
+const X264_PSY_TUNES: &[&str] = &["film", "animation", "grain", "stillimage", "psnr", "ssim"];
+const X264_EXTRA_TUNES: &[&str] = &["fastdecode", "zerolatency"];
+
+/// Returns the `:`-separated entries of an `--x264-opts` string that are not shaped
+/// like `key=value`. Keys are not checked against x264's parameter list.
+pub fn malformed_x264_opts(raw: &str) -> Vec<String> {
+    if raw.trim().is_empty() {
+        return Vec::new();
+    }
+
+    raw.split(':')
+        .filter(|entry| match entry.split_once('=') {
+            Some((key, value)) => {
+                key.is_empty()
+                    || value.is_empty()
+                    || !key
+                        .chars()
+                        .all(|ch| ch.is_ascii_alphanumeric() || ch == '-' || ch == '_')
+            }
+            None => true,
+        })
+        .map(str::to_string)
+        .collect()
+}
+
+/// Returns the `bframes` value set in an `--x264-opts` string. When the key is
+/// repeated, the last entry wins, as in x264.
+pub fn x264_bframes(raw: &str) -> Option<u32> {
+    raw.rsplit(':')
+        .filter_map(|entry| entry.split_once('='))
+        .filter(|(key, _)| key.trim() == "bframes")
+        .find_map(|(_, value)| value.trim().parse().ok())
+}
+
+/// Maintenance tasks run instead of streaming.
+#[derive(Debug, Clone, Subcommand)]
+pub enum CliCommand {
+    /// Check that ffmpeg starts and that the headless_shell sidecar exists, then exit.
+    /// `--ffmpeg-path`, `--ffmpeg-source` and `--chromium-path` are honored.
+    VerifySidecars,
+    /// Download the ffmpeg and headless_shell sidecars pinned for this platform,
+    /// check their SHA-256 checksums and install them where the binary looks for
+    /// them: `--sidecar-dir`, or `../sidecar` next to the binary.
+    FetchSidecars {
+        /// Read the pinned downloads from this TOML file instead of the manifest
+        /// built into the binary.
+        #[arg(long)]
+        manifest: Option<PathBuf>,
+    },
+    /// Print a shell completion script to stdout, e.g.
+    /// `browser-stream completions bash > /etc/bash_completion.d/browser-stream`.
+    Completions {
+        #[arg(value_enum)]
+        shell: clap_complete::Shell,
+    },
+}
+
+/// Validates a comma-separated x264 tune list such as `animation,zerolatency`.
+/// x264 accepts at most one psy tune combined with any of `fastdecode`/`zerolatency`.
+pub fn parse_tune(raw: &str) -> Result<String, ConfigError> {
+    let invalid = || ConfigError::InvalidTune(raw.to_string());
+
+    let tunes: Vec<String> = raw
+        .split(',')
+        .map(|tune| tune.trim().to_ascii_lowercase())
+        .collect();
+
+    let mut psy_tunes = 0;
+    for tune in &tunes {
+        if X264_PSY_TUNES.contains(&tune.as_str()) {
+            psy_tunes += 1;
+        } else if !X264_EXTRA_TUNES.contains(&tune.as_str()) {
+            return Err(invalid());
+        }
+    }
+
+    if psy_tunes > 1 {
+        return Err(invalid());
+    }
+
+    Ok(tunes.join(","))
+}
+
+/// Network conditions emulated through CDP `Network.emulateNetworkConditions`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct NetworkThrottle {
+    pub download_kbps: u32,
+    pub upload_kbps: u32,
+    pub latency_ms: u32,
+}
+
+impl NetworkThrottle {
+    /// Matches the Chrome DevTools "Slow 3G" preset.
+    pub const SLOW_3G: Self = Self {
+        download_kbps: 400,
+        upload_kbps: 400,
+        latency_ms: 2000,
+    };
+
+    /// Matches the Chrome DevTools "Fast 3G" preset.
+    pub const FAST_3G: Self = Self {
+        download_kbps: 1440,
+        upload_kbps: 675,
+        latency_ms: 563,
+    };
+}
+
+/// Parses a preset name (`slow-3g`, `fast-3g`) or an explicit
+/// `download_kbps:upload_kbps:latency_ms` triple.
+pub fn parse_network_throttle(raw: &str) -> Result<NetworkThrottle, ConfigError> {
+    let invalid = || ConfigError::InvalidNetworkThrottle(raw.to_string());
+
+    match raw.trim().to_ascii_lowercase().as_str() {
+        "slow-3g" => return Ok(NetworkThrottle::SLOW_3G),
+        "fast-3g" => return Ok(NetworkThrottle::FAST_3G),
+        _ => {}
+    }
+
+    let mut parts = raw.trim().split(':').map(|part| part.trim().parse::<u32>());
+    match (parts.next(), parts.next(), parts.next(), parts.next()) {
+        (Some(Ok(download_kbps)), Some(Ok(upload_kbps)), Some(Ok(latency_ms)), None) => {
+            Ok(NetworkThrottle {
+                download_kbps,
+                upload_kbps,
+                latency_ms,
+            })
+        }
+        _ => Err(invalid()),
+    }
+}
+
+/// Frame rate as an exact fraction, so broadcast rates such as 29.97 (`30000/1001`)
+/// are passed to ffmpeg without rounding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameRate {
+    pub num: u32,
+    pub den: u32,
+}
+
+impl FrameRate {
+    pub fn as_f64(self) -> f64 {
+        f64::from(self.num) / f64::from(self.den)
+    }
+
+    /// Time between frames.
+    pub fn interval(self) -> Duration {
+        Duration::from_secs_f64(f64::from(self.den) / f64::from(self.num))
+    }
+
+    fn reduced(num: u32, den: u32) -> Self {
+        let divisor = gcd(num, den).max(1);
+        Self {
+            num: num / divisor,
+            den: den / divisor,
+        }
+    }
+}
+
+impl Serialize for FrameRate {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl From<u32> for FrameRate {
+    fn from(fps: u32) -> Self {
+        Self { num: fps, den: 1 }
+    }
+}
+
+/// Formats as ffmpeg expects for `-r`: `30` or `30000/1001`.
+impl fmt::Display for FrameRate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.den == 1 {
+            write!(f, "{}", self.num)
+        } else {
+            write!(f, "{}/{}", self.num, self.den)
+        }
+    }
+}
+
+fn gcd(mut a: u32, mut b: u32) -> u32 {
+    while b != 0 {
+        (a, b) = (b, a % b);
+    }
+    a
+}
+
+/// Smallest `--bitrate-kbps` accepted, and the floor for the `bitrate` command.
+pub const MIN_BITRATE_KBPS: u32 = 100;
+
+/// Bits per pixel per frame used by `--bitrate auto`.
+pub const AUTO_BITRATE_BPP: f64 = 0.1;
+
+/// Seconds of `--fallback-video` between attempts to restore the live page.
+pub const DEFAULT_FALLBACK_RESTORE_SEC: u64 = 60;
+
+/// `--backpressure-threshold-ms` when not given.
+pub const DEFAULT_BACKPRESSURE_THRESHOLD_MS: u64 = 100;
+
+/// `--diff-threshold` when not given: a visible change such as a shifted panel,
+/// well above JPEG noise.
+pub const DEFAULT_DIFF_THRESHOLD: u8 = 8;
+
+/// `--dump-every` when not given: about one frame a second at 30fps.
+pub const DEFAULT_DUMP_EVERY: u64 = 30;
+
+/// `--screenshot-interval-sec` when not given.
+pub const DEFAULT_SCREENSHOT_INTERVAL_SEC: u64 = 10;
+
+/// Pages in a `--grid`, laid out 2x2.
+pub const GRID_PAGES: usize = 4;
+
+/// Smallest `--max-frame-buffer-bytes` accepted at any size: 1 MiB. Larger
+/// outputs need room for their decoded frames on top, see `into_config`.
+pub const MIN_FRAME_BUFFER_BYTES: u64 = 1 << 20;
+
+/// File `--preview-sec` records to when `--output` is not given.
+pub const DEFAULT_PREVIEW_OUTPUT: &str = "preview.flv";
+
+/// Largest page size `--fit-content` captures unless told otherwise: 4K UHD.
+pub const DEFAULT_FIT_CONTENT_MAX_WIDTH: u32 = 3840;
+pub const DEFAULT_FIT_CONTENT_MAX_HEIGHT: u32 = 2160;
+
+/// Seconds `--auto-scroll` rests at the top and bottom of the page.
+pub const DEFAULT_SCROLL_PAUSE_SEC: u64 = 3;
+
+/// One encode dimension derived by `--scale`, rounded to the nearest even number
+/// so every output pixel format accepts it.
+pub fn scale_dimension(capture: u32, scale: f64) -> u32 {
+    (f64::from(capture) * scale / 2.0).round() as u32 * 2
+}
+
+/// Checks that an encode dimension is even when `must_be_even`, or with
+/// `--round-dimensions` rounds it down to the next even number with a warning.
+fn even_dimension(
+    field: &'static str,
+    value: u32,
+    must_be_even: bool,
+    round_down: bool,
+    pix_fmt: &str,
+) -> Result<u32, ConfigError> {
+    if !must_be_even || value.is_multiple_of(2) {
+        return Ok(value);
+    }
+    if !round_down {
+        return Err(ConfigError::OddDimension {
+            field,
+            value,
+            pix_fmt: pix_fmt.to_string(),
+        });
+    }
+    let rounded = value - 1;
+    warn!(
+        field,
+        value, rounded, pix_fmt, "odd encode dimension rounded down to an even number"
+    );
+    Ok(rounded)
+}
+
+fn parse_website_url(raw: &str) -> Result<Url, ConfigError> {
+    let url = Url::parse(raw).map_err(|_| ConfigError::InvalidWebsiteUrl(raw.to_string()))?;
+    match url.scheme() {
+        "http" | "https" => Ok(url),
+        other => Err(ConfigError::UnsupportedWebsiteScheme(other.to_string())),
+    }
+}
+
+/// Bitrate picked by `--bitrate auto`: `width * height * fps * AUTO_BITRATE_BPP`,
+/// rounded to the nearest 100 kbps.
+pub fn auto_bitrate_kbps(width: u32, height: u32, fps: FrameRate) -> u32 {
+    let bits_per_sec = f64::from(width) * f64::from(height) * fps.as_f64() * AUTO_BITRATE_BPP;
+    let kbps = (bits_per_sec / 1000.0 / 100.0).round() * 100.0;
+    kbps.min(f64::from(u32::MAX)) as u32
+}
+
+/// Parses an integer (`30`), a fraction (`30000/1001`) or a decimal (`29.97`, `0.5`)
+/// frame rate. The NTSC decimals map to their exact `/1001` fractions.
+pub fn parse_frame_rate(raw: &str) -> Result<FrameRate, ConfigError> {
+    let invalid = || ConfigError::InvalidFrameRate(raw.to_string());
+    let trimmed = raw.trim();
+
+    let rate = match trimmed {
+        "23.976" | "23.98" => FrameRate::reduced(24000, 1001),
+        "29.97" => FrameRate::reduced(30000, 1001),
+        "59.94" => FrameRate::reduced(60000, 1001),
+        "119.88" => FrameRate::reduced(120000, 1001),
+        _ => {
+            if let Some((num, den)) = trimmed.split_once('/') {
+                let num = num.trim().parse().map_err(|_| invalid())?;
+                let den = den.trim().parse().map_err(|_| invalid())?;
+                if den == 0 {
+                    return Err(invalid());
+                }
+                FrameRate::reduced(num, den)
+            } else if trimmed.contains('.') {
+                let value: f64 = trimmed.parse().map_err(|_| invalid())?;
+                if !value.is_finite() || !(0.0..=u32::MAX as f64 / 1000.0).contains(&value) {
+                    return Err(invalid());
+                }
+                FrameRate::reduced((value * 1000.0).round() as u32, 1000)
+            } else {
+                FrameRate::from(trimmed.parse::<u32>().map_err(|_| invalid())?)
+            }
+        }
+    };
+
+    if rate.num == 0 {
+        return Err(invalid());
+    }
+    Ok(rate)
+}
+
+/// Chromium GPU/GL backend selection for launched browsers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum GpuMode {
+    On,
+    Off,
+    SwiftShader,
+}
+
+impl GpuMode {
+    /// Chromium switches that select this mode.
+    pub fn chromium_args(self) -> &'static [&'static str] {
+        match self {
+            Self::On => &["--enable-gpu", "--ignore-gpu-blocklist"],
+            Self::Off => &["--disable-gpu"],
+            Self::SwiftShader => &[
+                "--use-gl=angle",
+                "--use-angle=swiftshader",
+                "--enable-unsafe-swiftshader",
+            ],
+        }
+    }
+}
+
+pub fn parse_gpu_mode(raw: &str) -> Result<GpuMode, ConfigError> {
+    match raw.trim().to_ascii_lowercase().as_str() {
+        "on" => Ok(GpuMode::On),
+        "off" => Ok(GpuMode::Off),
+        "swiftshader" => Ok(GpuMode::SwiftShader),
+        _ => Err(ConfigError::InvalidGpuMode(raw.to_string())),
+    }
+}
+
+/// One encoded copy of the stream for a destination.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Rendition {
+    pub output: String,
+    /// Output height with the width following the aspect ratio; `None` keeps `--height`.
+    pub height: Option<u32>,
+    pub bitrate_kbps: u32,
+}
+
+/// Splits an optional `@<height>p:<kbps>k` rendition suffix off an output URL, as in
+/// `rtmp://host/app/key@720p:2500k`. Either part may be given alone (`@720p`, `@2500k`).
+pub fn split_rendition_suffix(
+    raw: &str,
+) -> Result<(String, Option<u32>, Option<u32>), ConfigError> {
+    let raw = raw.trim();
+    let Some((output, spec)) = raw.rsplit_once('@') else {
+        return Ok((raw.to_string(), None, None));
+    };
+    // `@` also separates credentials in URLs; only a suffix made of rendition characters counts.
+    if spec.is_empty()
+        || !spec
+            .chars()
+            .all(|ch| ch.is_ascii_digit() || matches!(ch, 'p' | 'k' | ':'))
+    {
+        return Ok((raw.to_string(), None, None));
+    }
+
+    let invalid = || ConfigError::InvalidRendition(raw.to_string());
+    let mut height = None;
+    let mut bitrate_kbps = None;
+    for part in spec.split(':') {
+        if let Some(value) = part.strip_suffix('p')
+            && height.is_none()
+        {
+            height = Some(value.parse::<u32>().map_err(|_| invalid())?);
+        } else if let Some(value) = part.strip_suffix('k')
+            && bitrate_kbps.is_none()
+        {
+            bitrate_kbps = Some(value.parse::<u32>().map_err(|_| invalid())?);
+        } else {
+            return Err(invalid());
+        }
+    }
+    if let Some(height) = height
+        && (height < 16 || height % 2 != 0)
+    {
+        return Err(invalid());
+    }
+    Ok((output.to_string(), height, bitrate_kbps))
+}
+
+/// Local archive written by a second ffmpeg output next to the main one.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RecordConfig {
+    pub path: String,
+    pub format: RecordFormat,
+    pub codec: RecordCodec,
+}
+
+/// Container for `--record`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RecordFormat {
+    Mkv,
+    Webm,
+}
+
+impl RecordFormat {
+    /// ffmpeg muxer name.
+    pub fn muxer(self) -> &'static str {
+        match self {
+            Self::Mkv => "matroska",
+            Self::Webm => "webm",
+        }
+    }
+}
+
+/// Video codec for `--record`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RecordCodec {
+    Vp9,
+    H264,
+}
+
+pub fn parse_record_format(raw: &str) -> Result<RecordFormat, ConfigError> {
+    match raw.trim().to_ascii_lowercase().as_str() {
+        "mkv" | "matroska" => Ok(RecordFormat::Mkv),
+        "webm" => Ok(RecordFormat::Webm),
+        _ => Err(ConfigError::InvalidRecordFormat(raw.to_string())),
+    }
+}
+
+pub fn parse_record_codec(raw: &str) -> Result<RecordCodec, ConfigError> {
+    match raw.trim().to_ascii_lowercase().as_str() {
+        "vp9" => Ok(RecordCodec::Vp9),
+        "h264" | "x264" => Ok(RecordCodec::H264),
+        _ => Err(ConfigError::InvalidRecordCodec(raw.to_string())),
+    }
+}
+
+/// CSS media type the page is rendered with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EmulatedMedia {
+    Print,
+    Screen,
+}
+
+impl EmulatedMedia {
+    /// Value for CDP `Emulation.setEmulatedMedia`.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Print => "print",
+            Self::Screen => "screen",
+        }
+    }
+}
+
+pub fn parse_emulated_media(raw: &str) -> Result<EmulatedMedia, ConfigError> {
+    match raw.trim().to_ascii_lowercase().as_str() {
+        "print" => Ok(EmulatedMedia::Print),
+        "screen" => Ok(EmulatedMedia::Screen),
+        _ => Err(ConfigError::InvalidEmulatedMedia(raw.to_string())),
+    }
+}
+
+/// What to do when the page has been a single flat color for `--blank-timeout-sec`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BlankAction {
+    Warn,
+    Reload,
+    /// End the attempt so the retry loop relaunches the browser.
+    Fail,
+}
+
+/// What to do when ffmpeg, usually held up by a slow uplink, stops taking frames.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BackpressurePolicy {
+    /// Wait for ffmpeg, letting latency build up.
+    Block,
+    /// Skip frames while a write is stuck, keeping latency bounded.
+    Drop,
+}
+
+pub fn parse_backpressure_policy(raw: &str) -> Result<BackpressurePolicy, ConfigError> {
+    match raw.trim().to_ascii_lowercase().as_str() {
+        "block" => Ok(BackpressurePolicy::Block),
+        "drop" => Ok(BackpressurePolicy::Drop),
+        _ => Err(ConfigError::InvalidBackpressurePolicy(raw.to_string())),
+    }
+}
+
+pub fn parse_blank_action(raw: &str) -> Result<BlankAction, ConfigError> {
+    match raw.trim().to_ascii_lowercase().as_str() {
+        "warn" => Ok(BlankAction::Warn),
+        "reload" => Ok(BlankAction::Reload),
+        "fail" => Ok(BlankAction::Fail),
+        _ => Err(ConfigError::InvalidBlankAction(raw.to_string())),
+    }
+}
+
+/// YUV range of the encoded output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ColorRange {
+    /// 16-235 luma, what web players expect.
+    Limited,
+    /// 0-255 luma.
+    Full,
+}
+
+impl ColorRange {
+    /// Value for ffmpeg's `-color_range`.
+    pub fn as_ffmpeg(self) -> &'static str {
+        match self {
+            Self::Limited => "tv",
+            Self::Full => "pc",
+        }
+    }
+}
+
+/// Where ffmpeg comes from when `--ffmpeg-path` is not given.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FfmpegSource {
+    /// The sidecar, falling back to `PATH`; on macOS `PATH` is preferred.
+    Auto,
+    /// Only the bundled sidecar.
+    Sidecar,
+    /// Only ffmpeg on `PATH`.
+    System,
+}
+
+pub fn parse_ffmpeg_source(raw: &str) -> Result<FfmpegSource, ConfigError> {
+    match raw.trim().to_ascii_lowercase().as_str() {
+        "auto" => Ok(FfmpegSource::Auto),
+        "sidecar" => Ok(FfmpegSource::Sidecar),
+        "system" => Ok(FfmpegSource::System),
+        _ => Err(ConfigError::InvalidFfmpegSource(raw.to_string())),
+    }
+}
+
+pub fn parse_color_range(raw: &str) -> Result<ColorRange, ConfigError> {
+    match raw.trim().to_ascii_lowercase().as_str() {
+        "limited" | "tv" => Ok(ColorRange::Limited),
+        "full" | "pc" => Ok(ColorRange::Full),
+        _ => Err(ConfigError::InvalidColorRange(raw.to_string())),
+    }
+}
+
+/// Synthetic source for `--test-pattern`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TestPattern {
+    /// SMPTE color bars.
+    Bars,
+    /// A single color.
+    Solid([u8; 3]),
+    /// A box bouncing across a dark background, moved every frame so dropped or
+    /// repeated frames are visible.
+    MovingBox,
+}
+
+impl Serialize for TestPattern {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+/// Formats as accepted by `--test-pattern`.
+impl fmt::Display for TestPattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Bars => write!(f, "bars"),
+            Self::Solid([r, g, b]) => write!(f, "solid:{r:02x}{g:02x}{b:02x}"),
+            Self::MovingBox => write!(f, "box"),
+        }
+    }
+}
+
+/// Parses `bars`, `box`, `solid` (mid grey) or `solid:RRGGBB`.
+pub fn parse_test_pattern(raw: &str) -> Result<TestPattern, ConfigError> {
+    let invalid = || ConfigError::InvalidTestPattern(raw.to_string());
+    let normalized = raw.trim().to_ascii_lowercase();
+    match normalized.as_str() {
+        "bars" | "smpte" => return Ok(TestPattern::Bars),
+        "box" => return Ok(TestPattern::MovingBox),
+        "solid" => return Ok(TestPattern::Solid([128, 128, 128])),
+        _ => {}
+    }
+
+    let hex = normalized
+        .strip_prefix("solid:")
+        .map(|color| color.trim_start_matches('#'))
+        .filter(|hex| hex.len() == 6)
+        .ok_or_else(invalid)?;
+    let channel = |index: usize| {
+        hex.get(index..index + 2)
+            .and_then(|pair| u8::from_str_radix(pair, 16).ok())
+            .ok_or_else(invalid)
+    };
+    Ok(TestPattern::Solid([channel(0)?, channel(2)?, channel(4)?]))
+}
+
+/// Loosely validates a Chromium host resolver rule, `MAP <host pattern> <replacement>[:port]`
+/// or `EXCLUDE <host pattern>`, and normalizes its spacing and keyword case.
+pub fn parse_host_resolver_rule(raw: &str) -> Result<String, ConfigError> {
+    let parts: Vec<&str> = raw.split_whitespace().collect();
+    match parts.as_slice() {
+        [keyword, pattern, replacement] if keyword.eq_ignore_ascii_case("map") => {
+            Ok(format!("MAP {pattern} {replacement}"))
+        }
+        [keyword, pattern] if keyword.eq_ignore_ascii_case("exclude") => {
+            Ok(format!("EXCLUDE {pattern}"))
+        }
+        _ => Err(ConfigError::InvalidHostResolverRule(raw.to_string())),
+    }
+}
+
+/// Trims an output metadata value. ffmpeg receives it as a single argument, so
+/// only control characters, which FLV `onMetaData` strings cannot carry, are rejected.
+fn validate_metadata(flag: &'static str, value: String) -> Result<String, ConfigError> {
+    let value = value.trim();
+    if value.is_empty() || value.chars().any(char::is_control) {
+        return Err(ConfigError::InvalidMetadata {
+            flag,
+            value: value.to_string(),
+        });
+    }
+    Ok(value.to_string())
+}
+
+/// Loosely validates an `Accept-Language` value such as `fr-FR,fr;q=0.9`: each
+/// entry is a language tag or `*` with an optional `q` weight in `0..=1`.
+/// Normalizes spacing around separators.
+pub fn parse_accept_language(raw: &str) -> Result<String, ConfigError> {
+    let invalid = || ConfigError::InvalidAcceptLanguage(raw.to_string());
+    let entries = raw
+        .split(',')
+        .map(|entry| {
+            let mut parts = entry.split(';').map(str::trim);
+            let tag = parts.next().unwrap_or_default();
+            let valid_tag = tag == "*"
+                || (!tag.is_empty()
+                    && !tag.starts_with('-')
+                    && !tag.ends_with('-')
+                    && tag
+                        .chars()
+                        .all(|ch| ch.is_ascii_alphanumeric() || ch == '-'));
+            if !valid_tag {
+                return Err(invalid());
+            }
+            match (parts.next(), parts.next()) {
+                (None, _) => Ok(tag.to_string()),
+                (Some(weight), None) => {
+                    let q = weight
+                        .strip_prefix("q=")
+                        .filter(|q| q.parse::<f32>().is_ok_and(|q| (0.0..=1.0).contains(&q)))
+                        .ok_or_else(invalid)?;
+                    Ok(format!("{tag};q={q}"))
+                }
+                _ => Err(invalid()),
+            }
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(entries.join(","))
+}
+
+/// Parses an `x,y` screen position for the browser window.
+pub fn parse_window_position(raw: &str) -> Result<(i32, i32), ConfigError> {
+    let invalid = || ConfigError::InvalidWindowPosition(raw.to_string());
+    let (x, y) = raw.split_once(',').ok_or_else(invalid)?;
+    let x = x.trim().parse().map_err(|_| invalid())?;
+    let y = y.trim().parse().map_err(|_| invalid())?;
+    Ok((x, y))
+}
+
+/// Normalizes an `--allow-domain` entry: lowercase, without a leading `*.` or
+/// trailing dot.
+pub fn parse_allow_domain(raw: &str) -> Result<String, ConfigError> {
+    let domain = raw.trim().to_ascii_lowercase();
+    let domain = domain.strip_prefix("*.").unwrap_or(&domain);
+    let domain = domain.strip_suffix('.').unwrap_or(domain);
+    if domain.is_empty()
+        || domain.starts_with('.')
+        || domain
+            .chars()
+            .any(|ch| !(ch.is_ascii_alphanumeric() || ch == '-' || ch == '.'))
+    {
+        return Err(ConfigError::InvalidAllowDomain(raw.to_string()));
+    }
+    Ok(domain.to_string())
+}
+
+/// Returns whether `host` is one of `allowed` or a subdomain of one.
+pub fn domain_allowed(host: &str, allowed: &[String]) -> bool {
+    let host = host.trim_end_matches('.').to_ascii_lowercase();
+    allowed.iter().any(|domain| {
+        host == *domain
+            || host
+                .strip_suffix(domain.as_str())
+                .is_some_and(|prefix| prefix.ends_with('.'))
+    })
+}
+
+/// Prefix of environment variables that set flags, e.g. `BROWSER_STREAM_BITRATE_KBPS`.
+pub const ENV_PREFIX: &str = "BROWSER_STREAM_";
+
+/// Environment variable consulted for `--long`, e.g. `bitrate-kbps` -> `BROWSER_STREAM_BITRATE_KBPS`.
+pub fn env_var_name(long: &str) -> String {
+    format!(
+        "{ENV_PREFIX}{}",
+        long.replace('-', "_").to_ascii_uppercase()
+    )
+}
+
+impl CliArgs {
+    /// Like [`Parser::parse`], but with configuration layers applied; see
+    /// [`CliArgs::try_parse_layered_from`].
+    pub fn parse_layered() -> Self {
+        Self::try_parse_layered_from(std::env::args_os(), |name| std::env::var(name).ok())
+            .unwrap_or_else(|err| err.exit())
+    }
+
+    /// Parses `args` with the precedence built-in defaults < `--config` TOML file <
+    /// `BROWSER_STREAM_*` environment variables < command-line flags.
+    ///
+    /// Lower layers are turned into flags placed before the real arguments; clap's
+    /// `args_override_self` then lets the later occurrence of a flag win. A lower
+    /// layer's value is dropped when it conflicts with a flag a higher layer set,
+    /// so `retries = 3` in the file does not make `--once` an error.
+    pub fn try_parse_layered_from<I, T>(
+        args: I,
+        env: impl Fn(&str) -> Option<String>,
+    ) -> Result<Self, clap::Error>
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<OsString>,
+    {
+        let mut args: Vec<OsString> = args.into_iter().map(Into::into).collect();
+        if args.is_empty() {
+            args.push(OsString::from("browser-stream"));
+        }
+        let program = args.remove(0);
+
+        let mut command = Self::command();
+        let switches: Vec<String> = command
+            .get_arguments()
+            .filter(|arg| matches!(arg.get_action(), ArgAction::SetTrue))
+            .filter_map(|arg| arg.get_long().map(str::to_string))
+            .collect();
+        let layered_flags: Vec<String> = command
+            .get_arguments()
+            .filter_map(|arg| arg.get_long())
+            .filter(|long| !matches!(*long, "config" | "help" | "version"))
+            .map(str::to_string)
+            .collect();
+
+        let env_layer: Vec<(String, String)> = layered_flags
+            .iter()
+            .filter_map(|long| env(&env_var_name(long)).map(|value| (long.clone(), value)))
+            .collect();
+
+        let config_path = config_path_from_args(&args)
+            .or_else(|| env(&env_var_name("config")).map(PathBuf::from));
+        let file_layer = match config_path {
+            Some(path) => read_config_file(&path, &layered_flags).map_err(|message| {
+                command.error(
+                    ErrorKind::InvalidValue,
+                    format!("{}: {message}", path.display()),
+                )
+            })?,
+            None => Vec::new(),
+        };
+
+        // Flags given on the command line. Errors such as a missing `--url` that a
+        // lower layer supplies are reported by the real parse below.
+        let explicit = command
+            .clone()
+            .ignore_errors(true)
+            .try_get_matches_from(std::iter::once(program.clone()).chain(args.iter().cloned()))
+            .ok();
+        let mut set_above: Vec<&Arg> = command
+            .get_arguments()
+            .filter(|arg| {
+                explicit.as_ref().is_some_and(|matches| {
+                    matches.value_source(arg.get_id().as_str()) == Some(ValueSource::CommandLine)
+                })
+            })
+            .collect();
+        let env_layer = drop_conflicting(&command, env_layer, &mut set_above);
+        let file_layer = drop_conflicting(&command, file_layer, &mut set_above);
+
+        let mut layered = vec![program];
+        for (long, value) in file_layer.into_iter().chain(env_layer) {
+            if switches.contains(&long) {
+                if parse_truthy(&value) {
+                    layered.push(OsString::from(format!("--{long}")));
+                }
+            } else {
+                layered.push(OsString::from(format!("--{long}={value}")));
+            }
+        }
+        layered.extend(args);
+
+        Self::try_parse_from(layered)
+    }
+}
+
+/// Keeps the values of `layer` whose flag is not in `set_above` and does not
+/// conflict, in either direction, with one there, then adds the kept flags to
+/// `set_above` for the next layer down. Dropping flags a higher layer sets keeps
+/// list flags such as `--allow-domain` from merging across layers, and lets a
+/// falsy env switch unset a file `true`.
+fn drop_conflicting<'a>(
+    command: &'a Command,
+    layer: Vec<(String, String)>,
+    set_above: &mut Vec<&'a Arg>,
+) -> Vec<(String, String)> {
+    let conflict = |a: &Arg, b: &Arg| {
+        command
+            .get_arg_conflicts_with(a)
+            .iter()
+            .any(|other| other.get_id() == b.get_id())
+    };
+    let mut kept_args = Vec::new();
+    let kept = layer
+        .into_iter()
+        .filter(|(long, _)| {
+            let Some(arg) = command
+                .get_arguments()
+                .find(|arg| arg.get_long() == Some(long.as_str()))
+            else {
+                return true;
+            };
+            if set_above.iter().any(|above| {
+                above.get_id() == arg.get_id() || conflict(arg, above) || conflict(above, arg)
+            }) {
+                return false;
+            }
+            kept_args.push(arg);
+            true
+        })
+        .collect();
+    set_above.extend(kept_args);
+    kept
+}
+
+fn config_path_from_args(args: &[OsString]) -> Option<PathBuf> {
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let arg = arg.to_str()?;
+        if arg == "--config" {
+            return args.next().map(PathBuf::from);
+        }
+        if let Some(path) = arg.strip_prefix("--config=") {
+            return Some(PathBuf::from(path));
+        }
+    }
+    None
+}
+
+/// Reads a flat TOML table whose keys are flag names (`bitrate-kbps` or `bitrate_kbps`).
+fn read_config_file(path: &Path, known_flags: &[String]) -> Result<Vec<(String, String)>, String> {
+    let contents = std::fs::read_to_string(path).map_err(|err| err.to_string())?;
+    let table: toml::Table = toml::from_str(&contents).map_err(|err| err.to_string())?;
+
+    table
+        .into_iter()
+        .map(|(key, value)| {
+            let long = key.replace('_', "-");
+            if !known_flags.contains(&long) {
+                return Err(format!("unknown config key `{key}`"));
+            }
+            let value = match value {
+                toml::Value::String(value) => value,
+                toml::Value::Integer(value) => value.to_string(),
+                toml::Value::Float(value) => value.to_string(),
+                toml::Value::Boolean(value) => value.to_string(),
+                _ => {
+                    return Err(format!(
+                        "config key `{key}` must be a string, number or boolean"
+                    ));
+                }
+            };
+            Ok((long, value))
+        })
+        .collect()
+}
+
+pub(crate) fn parse_truthy(value: &str) -> bool {
+    matches!(
+        value.trim().to_ascii_lowercase().as_str(),
+        "1" | "true" | "yes"
+    )
+}
+
 impl CliArgs {
+    /// Resolves `--url -` by reading the first line of `input`. Only that line is
+    /// consumed, so later lines remain for the runtime control listener. Does
+    /// nothing for any other `--url`.
+    pub fn read_url_from(&mut self, input: &mut impl BufRead) -> Result<(), ConfigError> {
+        if self.url.as_deref() != Some("-") {
+            return Ok(());
+        }
+        let mut line = String::new();
+        input
+            .read_line(&mut line)
+            .map_err(|_| ConfigError::MissingStdinUrl)?;
+        let url = line.trim();
+        if url.is_empty() {
+            return Err(ConfigError::MissingStdinUrl);
+        }
+        self.url = Some(url.to_string());
+        Ok(())
+    }
+
     pub fn into_config(self) -> Result<AppConfig, ConfigError> {
-        validate_range("width", self.width as u64, 16, u32::MAX as u64)?;
-        validate_range("height", self.height as u64, 16, u32::MAX as u64)?;
-        validate_range("fps", self.fps as u64, 1, 120)?;
+        let capture_width = self.capture_width.unwrap_or(self.width);
+        let capture_height = self.capture_height.unwrap_or(self.height);
+        let (width, height) = match self.scale {
+            Some(scale) if !(scale > 0.0 && scale <= 1.0) => {
+                return Err(ConfigError::InvalidScale(scale));
+            }
+            Some(scale) => (
+                scale_dimension(capture_width, scale),
+                scale_dimension(capture_height, scale),
+            ),
+            None => (self.width, self.height),
+        };
+        validate_range("width", width as u64, 16, u32::MAX as u64)?;
+        validate_range("height", height as u64, 16, u32::MAX as u64)?;
+        let output_pix_fmt = self.output_pix_fmt.trim().to_ascii_lowercase();
+        if !SUPPORTED_OUTPUT_PIX_FMTS.contains(&output_pix_fmt.as_str()) {
+            return Err(ConfigError::UnsupportedPixelFormat(self.output_pix_fmt));
+        }
+        // Chroma subsampling halves the width (4:2:2) or both dimensions (4:2:0).
+        let (even_width, even_height) = match output_pix_fmt.as_str() {
+            "yuv420p" | "nv12" => (true, true),
+            "yuv422p" => (true, false),
+            _ => (false, false),
+        };
+        let width = even_dimension(
+            "width",
+            width,
+            even_width,
+            self.round_dimensions,
+            &output_pix_fmt,
+        )?;
+        let height = even_dimension(
+            "height",
+            height,
+            even_height,
+            self.round_dimensions,
+            &output_pix_fmt,
+        )?;
+        for (flag, max) in [
+            ("fit-content-max-width", self.fit_content_max_width),
+            ("fit-content-max-height", self.fit_content_max_height),
+        ] {
+            if let Some(max) = max {
+                if !self.fit_content {
+                    return Err(ConfigError::RequiresFlag {
+                        flag,
+                        requires: "fit-content",
+                    });
+                }
+                validate_range(flag, max as u64, 16, 16384)?;
+            }
+        }
+        validate_range("capture-width", capture_width as u64, 16, u32::MAX as u64)?;
+        validate_range("capture-height", capture_height as u64, 16, u32::MAX as u64)?;
+        if capture_width < width || capture_height < height {
+            warn!(
+                capture_width,
+                capture_height,
+                width,
+                height,
+                "capture size is smaller than encode size; frames will be upscaled"
+            );
+        }
+        let mut fps = parse_frame_rate(&self.fps)?;
+        validate_range("fps", fps.as_f64().ceil() as u64, 1, 120)?;
+        if let Some(max_encode_fps) = self.max_encode_fps {
+            validate_range("max-encode-fps", max_encode_fps as u64, 1, 120)?;
+            if f64::from(max_encode_fps) < fps.as_f64() {
+                info!(fps = %fps, max_encode_fps, "capping encode frame rate");
+                fps = FrameRate::from(max_encode_fps);
+            }
+        }
+        let bitrate_auto = self.bitrate_kbps.trim().eq_ignore_ascii_case("auto");
+        let bitrate_kbps = if bitrate_auto {
+            let bitrate_kbps = auto_bitrate_kbps(width, height, fps);
+            info!(
+                bitrate_kbps,
+                width,
+                height,
+                fps = %fps,
+                "selected bitrate automatically"
+            );
+            bitrate_kbps
+        } else {
+            self.bitrate_kbps
+                .trim()
+                .parse::<u32>()
+                .map_err(|_| ConfigError::InvalidBitrate(self.bitrate_kbps.clone()))?
+        };
         validate_range(
             "bitrate-kbps",
-            self.bitrate_kbps as u64,
-            100,
+            bitrate_kbps as u64,
+            MIN_BITRATE_KBPS as u64,
             u32::MAX as u64,
         )?;
         validate_range("keyint-sec", self.keyint_sec as u64, 1, 60)?;
+        if let Some(force_keyframe_sec) = self.force_keyframe_sec {
+            validate_range("force-keyframe-sec", force_keyframe_sec as u64, 1, 3600)?;
+        }
         validate_range("frame-timeout-ms", self.frame_timeout_ms, 1000, u64::MAX)?;
+        validate_range(
+            "browser-launch-timeout-ms",
+            self.browser_launch_timeout_ms,
+            1000,
+            u64::MAX,
+        )?;
+        validate_range(
+            "encoder-init-timeout-ms",
+            self.encoder_init_timeout_ms,
+            1000,
+            u64::MAX,
+        )?;
+        validate_range(
+            "max-decode-errors",
+            self.max_decode_errors as u64,
+            1,
+            u32::MAX as u64,
+        )?;
+        validate_range(
+            "max-handler-errors",
+            self.max_handler_errors as u64,
+            1,
+            u32::MAX as u64,
+        )?;
+        validate_range("skip-frames", self.skip_frames as u64, 0, 1000)?;
+        if let Some(max_duration_sec) = self.max_duration_sec {
+            validate_range("max-duration-sec", max_duration_sec, 1, u64::MAX)?;
+        }
+        if let Some(preview_sec) = self.preview_sec {
+            validate_range("preview-sec", preview_sec, 1, 3600)?;
+        }
+        if self.restart_interval_sec.is_some() && self.max_duration_sec.is_none() {
+            return Err(ConfigError::RequiresFlag {
+                flag: "restart-interval-sec",
+                requires: "max-duration-sec",
+            });
+        }
+        if let Some(fallback_restore_sec) = self.fallback_restore_sec {
+            if self.fallback_video.is_none() {
+                return Err(ConfigError::RequiresFlag {
+                    flag: "fallback-restore-sec",
+                    requires: "fallback-video",
+                });
+            }
+            validate_range("fallback-restore-sec", fallback_restore_sec, 10, 86_400)?;
+        }
+        if let Some(auto_scroll) = self.auto_scroll {
+            validate_range("auto-scroll", u64::from(auto_scroll), 1, 10_000)?;
+        }
+        if let Some(scroll_pause_sec) = self.scroll_pause_sec {
+            if self.auto_scroll.is_none() {
+                return Err(ConfigError::RequiresFlag {
+                    flag: "scroll-pause-sec",
+                    requires: "auto-scroll",
+                });
+            }
+            validate_range("scroll-pause-sec", scroll_pause_sec, 0, 3_600)?;
+        }
+        if let Some(blank_timeout_sec) = self.blank_timeout_sec {
+            validate_range("blank-timeout-sec", blank_timeout_sec, 5, 86_400)?;
+        }
+        let on_backpressure = self
+            .on_backpressure
+            .as_deref()
+            .map(parse_backpressure_policy)
+            .transpose()?
+            .unwrap_or(BackpressurePolicy::Block);
+        if let Some(threshold_ms) = self.backpressure_threshold_ms {
+            if on_backpressure != BackpressurePolicy::Drop {
+                return Err(ConfigError::RequiresFlag {
+                    flag: "backpressure-threshold-ms",
+                    requires: "on-backpressure drop",
+                });
+            }
+            validate_range("backpressure-threshold-ms", threshold_ms, 1, 60_000)?;
+        }
+        if self.on_blank.is_some() && self.blank_timeout_sec.is_none() {
+            return Err(ConfigError::RequiresFlag {
+                flag: "on-blank",
+                requires: "blank-timeout-sec",
+            });
+        }
+        let on_blank = self
+            .on_blank
+            .as_deref()
+            .map(parse_blank_action)
+            .transpose()?
+            .unwrap_or(BlankAction::Warn);
+        match (self.diff_alert, self.baseline.is_some()) {
+            (true, false) => {
+                return Err(ConfigError::RequiresFlag {
+                    flag: "diff-alert",
+                    requires: "baseline",
+                });
+            }
+            (false, true) => {
+                return Err(ConfigError::RequiresFlag {
+                    flag: "baseline",
+                    requires: "diff-alert",
+                });
+            }
+            _ => {}
+        }
+        if let Some(diff_threshold) = self.diff_threshold {
+            if !self.diff_alert {
+                return Err(ConfigError::RequiresFlag {
+                    flag: "diff-threshold",
+                    requires: "diff-alert",
+                });
+            }
+            validate_range("diff-threshold", diff_threshold, 1, 255)?;
+        }
+        if let Some(dump_every) = self.dump_every {
+            if self.dump_frames.is_none() {
+                return Err(ConfigError::RequiresFlag {
+                    flag: "dump-every",
+                    requires: "dump-frames",
+                });
+            }
+            validate_range("dump-every", dump_every, 1, u64::MAX)?;
+        }
+        if let Some(interval_sec) = self.screenshot_interval_sec {
+            if !self.screenshot_mode {
+                return Err(ConfigError::RequiresFlag {
+                    flag: "screenshot-interval-sec",
+                    requires: "screenshot-mode",
+                });
+            }
+            validate_range("screenshot-interval-sec", interval_sec, 1, 3600)?;
+        }
+        if let Some(nice) = self.nice
+            && !(-20..=19).contains(&nice)
+        {
+            return Err(ConfigError::InvalidNice(nice));
+        }
+        if let Some(max_browser_memory_mb) = self.max_browser_memory_mb {
+            validate_range(
+                "max-browser-memory-mb",
+                max_browser_memory_mb,
+                256,
+                u64::MAX,
+            )?;
+        }
+        if let Some(max_frame_buffer_bytes) = self.max_frame_buffer_bytes {
+            // The decoded current, held and incoming frames are kept whatever the limit,
+            // plus the frame held back by `--reload-wait`.
+            let held_frames = if self.reload_wait { 4 } else { 3 };
+            let held_bytes = u64::from(width) * u64::from(height) * 3 * held_frames;
+            validate_range(
+                "max-frame-buffer-bytes",
+                max_frame_buffer_bytes,
+                held_bytes.max(MIN_FRAME_BUFFER_BYTES),
+                u64::MAX,
+            )?;
+        }
+
+        if self.incognito && self.user_data_dir.is_some() {
+            return Err(ConfigError::IncognitoWithUserDataDir);
+        }
+
+        if self.clear_stale_lock && self.user_data_dir.is_none() {
+            return Err(ConfigError::RequiresFlag {
+                flag: "clear-stale-lock",
+                requires: "user-data-dir",
+            });
+        }
+
+        if self.verify_only && self.verify_screenshot.is_none() {
+            return Err(ConfigError::RequiresFlag {
+                flag: "verify-only",
+                requires: "verify-screenshot",
+            });
+        }
+
+        let tune = self.tune.as_deref().map(parse_tune).transpose()?;
 
-        let website_url =
-            Url::parse(&self.url).map_err(|_| ConfigError::InvalidWebsiteUrl(self.url.clone()))?;
-        match website_url.scheme() {
-            "http" | "https" => {}
-            other => return Err(ConfigError::UnsupportedWebsiteScheme(other.to_string())),
+        let malformed = malformed_x264_opts(&self.x264_opts);
+        if let Some(entry) = malformed.first() {
+            if self.strict {
+                return Err(ConfigError::InvalidX264Opts(entry.clone()));
+            }
+            warn!(
+                entries = ?malformed,
+                "malformed --x264-opts entries are passed to ffmpeg as-is; expected `key=value`"
+            );
         }
 
-        let output = crate::rtmp::build_output(self.output, self.rtmp_url, self.stream_key)?;
+        let color_range = parse_color_range(&self.color_range)?;
+        let ffmpeg_source = parse_ffmpeg_source(&self.ffmpeg_source)?;
+
+        let video_filter = self
+            .video_filter
+            .map(|filter| filter.trim().to_string())
+            .filter(|filter| !filter.is_empty());
+        if let Some(filter) = video_filter.as_deref() {
+            let geometry: Vec<&str> = filter
+                .split([',', ';'])
+                .filter_map(|entry| {
+                    let name = entry.trim().split(['=', '@']).next().unwrap_or_default();
+                    GEOMETRY_FILTERS
+                        .iter()
+                        .copied()
+                        .find(|known| *known == name)
+                })
+                .collect();
+            if !geometry.is_empty() {
+                warn!(
+                    filters = ?geometry,
+                    width,
+                    height,
+                    "--video-filter changes the frame size; output will not match --width/--height"
+                );
+            }
+        }
+
+        let grid = self
+            .grid
+            .iter()
+            .map(|raw| parse_website_url(raw.trim()))
+            .collect::<Result<Vec<_>, _>>()?;
+        if !grid.is_empty() && grid.len() != GRID_PAGES {
+            return Err(ConfigError::InvalidGrid(grid.len()));
+        }
+        let website_url = match (grid.first(), self.url.as_deref()) {
+            (Some(first), _) => first.clone(),
+            (None, Some(url)) => parse_website_url(url)?,
+            // Only `--test-pattern` and `--frame-source` run without a URL; no page is loaded.
+            (None, None) => Url::parse("about:blank").expect("about:blank is a valid URL"),
+        };
+
+        if let Some(connect_url) = self.connect_url.as_deref() {
+            match Url::parse(connect_url) {
+                Ok(url) if matches!(url.scheme(), "ws" | "wss" | "http" | "https") => {}
+                _ => return Err(ConfigError::InvalidConnectUrl(connect_url.to_string())),
+            }
+        }
+
+        if let Some(frame_source) = self.frame_source.as_deref() {
+            match Url::parse(frame_source) {
+                Ok(url) if url.scheme() == "ws" => {}
+                _ => return Err(ConfigError::InvalidFrameSource(frame_source.to_string())),
+            }
+        }
+
+        let test_pattern = self
+            .test_pattern
+            .as_deref()
+            .map(parse_test_pattern)
+            .transpose()?;
+
+        let splash_url = Url::parse(self.splash_url.trim())
+            .map_err(|_| ConfigError::InvalidSplashUrl(self.splash_url.clone()))?;
+        if !matches!(splash_url.scheme(), "about" | "data" | "http" | "https") {
+            return Err(ConfigError::InvalidSplashUrl(self.splash_url));
+        }
+
+        if self.target_filter.is_some() && self.connect_url.is_none() {
+            return Err(ConfigError::RequiresFlag {
+                flag: "target-filter",
+                requires: "connect-url",
+            });
+        }
+
+        let mut allow_domains = self
+            .allow_domains
+            .iter()
+            .map(|raw| parse_allow_domain(raw))
+            .collect::<Result<Vec<_>, _>>()?;
+        if !allow_domains.is_empty() {
+            // Every page must be able to load its own start URL, including each grid page.
+            for start_url in std::iter::once(&website_url).chain(&grid) {
+                if let Some(host) = start_url.host_str()
+                    && !domain_allowed(host, &allow_domains)
+                {
+                    allow_domains.push(host.to_ascii_lowercase());
+                }
+            }
+        }
+
+        let gpu = if self.webgl_software {
+            Some(GpuMode::SwiftShader)
+        } else {
+            self.gpu.as_deref().map(parse_gpu_mode).transpose()?
+        };
+        let window_position = self
+            .window_position
+            .as_deref()
+            .map(parse_window_position)
+            .transpose()?;
+        let record = match self.record.as_deref().map(str::trim) {
+            Some(path) if !path.is_empty() => {
+                let format = self
+                    .record_format
+                    .as_deref()
+                    .map(parse_record_format)
+                    .transpose()?
+                    .unwrap_or(RecordFormat::Mkv);
+                let codec = self
+                    .record_codec
+                    .as_deref()
+                    .map(parse_record_codec)
+                    .transpose()?
+                    .unwrap_or(RecordCodec::Vp9);
+                if format == RecordFormat::Webm && codec != RecordCodec::Vp9 {
+                    return Err(ConfigError::UnsupportedRecordCodec {
+                        format: "webm",
+                        codec: "h264",
+                    });
+                }
+                Some(RecordConfig {
+                    path: path.to_string(),
+                    format,
+                    codec,
+                })
+            }
+            _ => {
+                if self.record_format.is_some() {
+                    return Err(ConfigError::RequiresFlag {
+                        flag: "record-format",
+                        requires: "record",
+                    });
+                }
+                if self.record_codec.is_some() {
+                    return Err(ConfigError::RequiresFlag {
+                        flag: "record-codec",
+                        requires: "record",
+                    });
+                }
+                None
+            }
+        };
+
+        let host_resolver_rules = self
+            .host_resolver_rules
+            .iter()
+            .map(|raw| parse_host_resolver_rule(raw))
+            .collect::<Result<Vec<_>, _>>()?;
+        let emulate_media = self
+            .emulate_media
+            .as_deref()
+            .map(parse_emulated_media)
+            .transpose()?;
+
+        let network_throttle = self
+            .network_throttle
+            .as_deref()
+            .map(parse_network_throttle)
+            .transpose()?;
+
+        let referer = self
+            .referer
+            .map(|value| validate_header_url("referer", value))
+            .transpose()?;
+        let origin = self
+            .origin
+            .map(|value| validate_header_url("origin", value))
+            .transpose()?;
+        let stream_title = self
+            .stream_title
+            .map(|value| validate_metadata("stream-title", value))
+            .transpose()?;
+        let stream_description = self
+            .stream_description
+            .map(|value| validate_metadata("stream-description", value))
+            .transpose()?;
+        let accept_language = self
+            .accept_language
+            .as_deref()
+            .map(parse_accept_language)
+            .transpose()?;
+
+        let (main_output, main_height, main_bitrate_kbps) = match self.output.as_deref() {
+            Some(raw) => {
+                let (output, height, bitrate_kbps) = split_rendition_suffix(raw)?;
+                (Some(output), height, bitrate_kbps)
+            }
+            None => (None, None, None),
+        };
+        let simulcast = self
+            .simulcast
+            .iter()
+            .map(|raw| {
+                let (output, height, rendition_kbps) = split_rendition_suffix(raw)?;
+                let output = crate::rtmp::build_output(Some(output), None, None)?;
+                Ok(Rendition {
+                    output,
+                    height,
+                    bitrate_kbps: rendition_kbps.unwrap_or(bitrate_kbps),
+                })
+            })
+            .collect::<Result<Vec<_>, ConfigError>>()?;
+        for rendition_kbps in main_bitrate_kbps
+            .iter()
+            .chain(simulcast.iter().map(|rendition| &rendition.bitrate_kbps))
+        {
+            validate_range(
+                "rendition bitrate-kbps",
+                *rendition_kbps as u64,
+                100,
+                u32::MAX as u64,
+            )?;
+        }
+        let has_renditions = self.segment_pattern.is_none()
+            && (main_height.is_some() || main_bitrate_kbps.is_some() || !simulcast.is_empty());
+        if self.hls_dir.is_some() && has_renditions {
+            return Err(ConfigError::HlsWithRenditions);
+        }
+
+        let output = match (self.segment_pattern, self.segment_time_sec) {
+            _ if self.output_to_null => "-".to_string(),
+            // The one output that is a local file rather than an RTMP URL.
+            _ if self.preview_sec.is_some() => {
+                let output = main_output.map_or(DEFAULT_PREVIEW_OUTPUT.to_string(), |output| {
+                    output.trim().to_string()
+                });
+                if output.is_empty() || !crate::encoder::is_file_output(&output) {
+                    return Err(ConfigError::PreviewRequiresFileOutput(
+                        crate::rtmp::redact_output(&output),
+                    ));
+                }
+                let is_flv = Path::new(&output)
+                    .extension()
+                    .is_some_and(|extension| extension.eq_ignore_ascii_case("flv"));
+                if !is_flv {
+                    return Err(ConfigError::PreviewRequiresFlvOutput(output));
+                }
+                output
+            }
+            (Some(pattern), Some(segment_time_sec)) => {
+                validate_range(
+                    "segment-time-sec",
+                    segment_time_sec as u64,
+                    1,
+                    u32::MAX as u64,
+                )?;
+                if !has_sequence_specifier(&pattern) {
+                    return Err(ConfigError::InvalidSegmentPattern(pattern));
+                }
+                pattern
+            }
+            (Some(_), None) => {
+                return Err(ConfigError::RequiresFlag {
+                    flag: "segment-pattern",
+                    requires: "segment-time-sec",
+                });
+            }
+            (None, Some(_)) => {
+                return Err(ConfigError::RequiresFlag {
+                    flag: "segment-time-sec",
+                    requires: "segment-pattern",
+                });
+            }
+            (None, None) => crate::rtmp::build_output(main_output, self.rtmp_url, self.stream_key)?,
+        };
+        // Everything except the null and segment muxers is written as FLV.
+        let flv_output = !self.output_to_null && self.segment_time_sec.is_none();
+        if flv_output && let Some(bframes) = x264_bframes(&self.x264_opts).filter(|&n| n > 0) {
+            warn!(
+                bframes,
+                "--x264-opts enables B-frames on an FLV/RTMP output; some ingests mishandle their timestamps, consider bframes=0"
+            );
+        }
+        // The tee muxer does not pass `-tls_verify` on, so an rtmps target would go unverified.
+        if self.hls_dir.is_some() && crate::rtmp::is_secure_output(&output) {
+            return Err(ConfigError::HlsWithSecureOutput);
+        }
+        let tls_flag = match self.ca_file.as_deref() {
+            Some(path) if !path.is_file() => {
+                return Err(ConfigError::MissingCaFile(path.to_path_buf()));
+            }
+            Some(_) => Some("ca-file"),
+            None if !self.tls_verify => Some("tls-verify"),
+            None => None,
+        };
+        if let Some(flag) = tls_flag
+            && let Some(insecure) = std::iter::once(&output)
+                .chain(simulcast.iter().map(|rendition| &rendition.output))
+                .find(|output| !crate::rtmp::is_secure_output(output))
+        {
+            return Err(ConfigError::TlsRequiresSecureOutput {
+                flag,
+                output: crate::rtmp::redact_output(insecure),
+            });
+        }
+        let renditions = if has_renditions {
+            std::iter::once(Rendition {
+                output: output.clone(),
+                height: main_height,
+                bitrate_kbps: main_bitrate_kbps.unwrap_or(bitrate_kbps),
+            })
+            .chain(simulcast)
+            .collect()
+        } else {
+            Vec::new()
+        };
 
         Ok(AppConfig {
             website_url,
-            width: self.width,
-            height: self.height,
-            fps: self.fps,
-            bitrate_kbps: self.bitrate_kbps,
+            width,
+            height,
+            capture_width,
+            capture_height,
+            fit_content: self.fit_content,
+            fit_content_max_width: self
+                .fit_content_max_width
+                .unwrap_or(DEFAULT_FIT_CONTENT_MAX_WIDTH),
+            fit_content_max_height: self
+                .fit_content_max_height
+                .unwrap_or(DEFAULT_FIT_CONTENT_MAX_HEIGHT),
+            fps,
+            adaptive_fps: self.adaptive_fps,
+            bitrate_kbps,
+            bitrate_auto,
             keyint_sec: self.keyint_sec,
             x264_opts: self.x264_opts,
+            tune,
+            keyframe_on_refresh: self.keyframe_on_refresh,
+            force_keyframe_sec: self.force_keyframe_sec,
+            output_pix_fmt,
+            color_range,
+            video_filter,
             output,
-            retries: self.retries,
-            retry_backoff_ms: self.retry_backoff_ms,
+            renditions,
+            segment_time_sec: self.segment_time_sec,
+            output_to_null: self.output_to_null,
+            hls_dir: self.hls_dir,
+            rtmp_live: self.rtmp_live,
+            flush_packets: self.flush_packets,
+            on_backpressure,
+            backpressure_threshold_ms: self
+                .backpressure_threshold_ms
+                .unwrap_or(DEFAULT_BACKPRESSURE_THRESHOLD_MS),
+            tls_verify: self.tls_verify,
+            ca_file: self.ca_file,
+            record,
+            preflight: self.preflight,
+            retries: if self.once { 0 } else { self.retries },
+            retry_backoff_ms: if self.once { 0 } else { self.retry_backoff_ms },
+            once: self.once || self.preview_sec.is_some(),
+            max_duration_sec: self.preview_sec.or(self.max_duration_sec),
+            preview_sec: self.preview_sec,
+            restart_interval_sec: self.restart_interval_sec,
+            persistent_encoder: self.persistent_encoder,
+            reuse_browser: self.reuse_browser,
+            warm_start: self.warm_start,
+            stall_image: self.stall_image,
+            fallback_video: self.fallback_video,
+            fallback_restore_sec: self
+                .fallback_restore_sec
+                .unwrap_or(DEFAULT_FALLBACK_RESTORE_SEC),
             startup_delay_ms: self.startup_delay_ms,
+            reload_wait: self.reload_wait,
+            control_prefix: self.control_prefix,
             frame_timeout_ms: self.frame_timeout_ms,
+            browser_launch_timeout_ms: self.browser_launch_timeout_ms,
+            encoder_init_timeout_ms: self.encoder_init_timeout_ms,
+            max_decode_errors: self.max_decode_errors,
+            max_handler_errors: self.max_handler_errors,
+            skip_frames: self.skip_frames,
+            max_browser_memory_mb: self.max_browser_memory_mb,
+            max_frame_buffer_bytes: self.max_frame_buffer_bytes,
             no_audio: self.no_audio,
+            audio_filter: self
+                .audio_filter
+                .map(|filter| filter.trim().to_string())
+                .filter(|filter| !filter.is_empty()),
+            stream_title,
+            stream_description,
             ffmpeg_path: self.ffmpeg_path,
+            ffmpeg_source,
             chromium_path: self.chromium_path,
+            sidecar_dir: self.sidecar_dir,
+            connect_url: self.connect_url,
+            frame_source: self.frame_source,
+            test_pattern,
+            grid,
+            splash_url,
+            target_filter: self.target_filter,
+            allow_domains,
+            network_throttle,
+            referer,
+            origin,
+            accept_language,
+            gpu,
+            window_position,
+            host_resolver_rules,
+            emulate_media,
+            capture_cursor: self.capture_cursor,
+            auto_scroll: self.auto_scroll,
+            scroll_pause_sec: self.scroll_pause_sec.unwrap_or(DEFAULT_SCROLL_PAUSE_SEC),
+            blank_timeout_sec: self.blank_timeout_sec,
+            on_blank,
+            baseline: self.baseline,
+            diff_threshold: self
+                .diff_threshold
+                .map_or(DEFAULT_DIFF_THRESHOLD, |threshold| threshold as u8),
+            dump_frames: self.dump_frames,
+            dump_every: self.dump_every.unwrap_or(DEFAULT_DUMP_EVERY),
+            screenshot_mode: self.screenshot_mode,
+            screenshot_interval_sec: self
+                .screenshot_interval_sec
+                .unwrap_or(DEFAULT_SCREENSHOT_INTERVAL_SEC),
+            fail_on_http_error: self.fail_on_http_error,
+            log_console: self.log_console,
+            user_data_dir: self.user_data_dir,
+            clear_stale_lock: self.clear_stale_lock,
+            incognito: self.incognito,
+            disk_cache_size: self.disk_cache_size,
+            verify_screenshot: self.verify_screenshot,
+            verify_only: self.verify_only,
+            list_cdp_targets: self.list_cdp_targets,
+            print_config: self.print_config,
+            dry_run: self.dry_run,
+            nice: self.nice,
+            ready_file: self.ready_file,
             verbose: self.verbose,
         })
     }
 }
 
+/// Returns whether `pattern` contains a printf-style integer specifier such as
+/// `%d` or `%03d`, which ffmpeg's segment muxer uses for the segment index.
+fn has_sequence_specifier(pattern: &str) -> bool {
+    let mut rest = pattern;
+    while let Some(index) = rest.find('%') {
+        let after = &rest[index + 1..];
+        let spec = after.trim_start_matches(|c: char| c.is_ascii_digit());
+        if spec.starts_with('d') {
+            return true;
+        }
+        rest = after.strip_prefix('%').unwrap_or(after);
+    }
+    false
+}
+
+fn validate_header_url(flag: &'static str, value: String) -> Result<String, ConfigError> {
+    match Url::parse(value.trim()) {
+        Ok(url) if matches!(url.scheme(), "http" | "https") => Ok(value.trim().to_string()),
+        _ => Err(ConfigError::InvalidHeaderUrl { flag, value }),
+    }
+}
+
 fn validate_range(field: &'static str, actual: u64, min: u64, max: u64) -> Result<(), ConfigError> {
     if actual < min || actual > max {
         return Err(ConfigError::OutOfRange {
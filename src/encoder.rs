@@ -1,25 +1,77 @@
+use std::future::Future;
 use std::path::{Path, PathBuf};
 use std::process::ExitStatus;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 use anyhow::{Context, Result, bail};
-use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::io::{AsyncBufReadExt, AsyncWrite, AsyncWriteExt, BufReader};
 use tokio::process::{Child, ChildStdin, Command};
 use tokio::task::JoinHandle;
-use tracing::{debug, info, warn};
+use tokio::time::MissedTickBehavior;
+use tracing::{Instrument, debug, info, warn};
 
+use crate::cli::{ColorRange, FrameRate, RecordCodec, RecordConfig, Rendition};
+use crate::error::RuntimeError;
 use crate::frame::RgbFrame;
+use crate::progress::{FfmpegProgress, ProgressParser};
 
 #[derive(Debug, Clone)]
 pub struct EncoderSettings {
     pub width: u32,
     pub height: u32,
-    pub fps: u32,
+    pub fps: FrameRate,
     pub bitrate_kbps: u32,
     pub keyint_sec: u32,
     pub x264_opts: String,
+    pub tune: Option<String>,
+    /// Lets x264 open a new GOP (IDR) on scene cuts such as a page reload
+    /// instead of holding the fixed `-keyint_min` interval.
+    pub idr_on_scene_cut: bool,
+    /// Forces a keyframe every N seconds of output time, independent of `-g`.
+    pub force_keyframe_sec: Option<u32>,
+    pub pix_fmt: String,
+    pub color_range: ColorRange,
+    /// Video filtergraph passed as `-vf`, applied to the rgb frames before encoding.
+    pub video_filter: Option<String>,
     pub output: String,
+    /// Simulcast: when non-empty, one x264 encode per rendition replaces the single
+    /// `output` at `bitrate_kbps`.
+    pub renditions: Vec<Rendition>,
+    pub segment_time_sec: Option<u32>,
+    /// Discard the encoded output with ffmpeg's `null` muxer.
+    pub null_output: bool,
+    /// Also write the encoded output as HLS into this directory. Both copies share
+    /// one encode through the `tee` muxer.
+    pub hls_dir: Option<PathBuf>,
+    /// `-rtmp_live live` on rtmp and rtmps outputs.
+    pub rtmp_live: bool,
+    /// `-flush_packets 1` on rtmp and rtmps outputs, writing each packet out
+    /// immediately instead of buffering.
+    pub flush_packets: bool,
+    /// `-tls_verify` on rtmps outputs. ffmpeg itself defaults to not verifying.
+    pub tls_verify: bool,
+    /// `-ca_file` on rtmps outputs.
+    pub ca_file: Option<PathBuf>,
+    /// Second output that archives the same input to a local file.
+    pub record: Option<RecordConfig>,
     pub include_silent_audio: bool,
+    /// Audio filtergraph passed as `-af`; ignored without an audio track.
+    pub audio_filter: Option<String>,
+    /// `-metadata title=` on every streamed output, but not on `record`.
+    pub stream_title: Option<String>,
+    /// `-metadata description=` on every streamed output, but not on `record`.
+    pub stream_description: Option<String>,
+    /// Loop this file as the video input instead of reading rgb frames from stdin.
+    /// Its own audio track, if any, is preferred over the silent track.
+    pub loop_video: Option<PathBuf>,
     pub ffmpeg_path: PathBuf,
+    /// How long the first frame write, and closing ffmpeg down, may block while
+    /// ffmpeg is still opening its output.
+    pub init_timeout: Duration,
+    /// With `--on-backpressure drop`, how long a frame write may block before
+    /// frames are skipped. `None` always waits.
+    pub drop_after: Option<Duration>,
 }
 
 pub fn build_ffmpeg_args(settings: &EncoderSettings) -> Vec<String> {
@@ -27,8 +79,7 @@ pub fn build_ffmpeg_args(settings: &EncoderSettings) -> Vec<String> {
 }
 
 pub fn build_ffmpeg_args_with_loglevel(settings: &EncoderSettings, loglevel: &str) -> Vec<String> {
-    let keyint = settings.fps.saturating_mul(settings.keyint_sec).max(1);
-    let bufsize = settings.bitrate_kbps.saturating_mul(2);
+    let keyint = ((settings.fps.as_f64() * f64::from(settings.keyint_sec)).round() as u32).max(1);
 
     let mut args = vec![
         "-hide_banner".to_string(),
@@ -37,18 +88,34 @@ pub fn build_ffmpeg_args_with_loglevel(settings: &EncoderSettings, loglevel: &st
         "-stats_period".to_string(),
         "5".to_string(),
         "-stats".to_string(),
-        "-f".to_string(),
-        "rawvideo".to_string(),
-        "-pix_fmt".to_string(),
-        "rgb24".to_string(),
-        "-s".to_string(),
-        format!("{}x{}", settings.width, settings.height),
-        "-r".to_string(),
-        settings.fps.to_string(),
-        "-i".to_string(),
-        "-".to_string(),
+        "-progress".to_string(),
+        "pipe:1".to_string(),
     ];
 
+    match settings.loop_video.as_ref() {
+        Some(path) => args.extend([
+            // stdin stays open but unused; keep ffmpeg from reading it as keyboard input.
+            "-nostdin".to_string(),
+            "-re".to_string(),
+            "-stream_loop".to_string(),
+            "-1".to_string(),
+            "-i".to_string(),
+            path.display().to_string(),
+        ]),
+        None => args.extend([
+            "-f".to_string(),
+            "rawvideo".to_string(),
+            "-pix_fmt".to_string(),
+            "rgb24".to_string(),
+            "-s".to_string(),
+            format!("{}x{}", settings.width, settings.height),
+            "-r".to_string(),
+            settings.fps.to_string(),
+            "-i".to_string(),
+            "-".to_string(),
+        ]),
+    }
+
     if settings.include_silent_audio {
         args.extend([
             "-f".to_string(),
@@ -58,45 +125,410 @@ pub fn build_ffmpeg_args_with_loglevel(settings: &EncoderSettings, loglevel: &st
         ]);
     }
 
+    if !settings.renditions.is_empty() {
+        push_simulcast_outputs(&mut args, settings, keyint);
+        return args;
+    }
+
+    if let Some(video_filter) = input_video_filter(settings) {
+        args.extend(["-vf".to_string(), video_filter]);
+    }
+
+    push_x264_args(&mut args, settings, settings.bitrate_kbps, keyint);
+
+    if settings.hls_dir.is_some() {
+        // The tee muxer needs explicit streams, and FLV needs the codec headers out of band.
+        args.extend(["-map".to_string(), "0:v".to_string()]);
+        if settings.include_silent_audio {
+            args.extend(["-map".to_string(), "1:a".to_string()]);
+        }
+        args.extend(["-flags".to_string(), "+global_header".to_string()]);
+    }
+
+    if settings.include_silent_audio {
+        push_aac_args(&mut args, settings);
+        if is_file_output(&settings.output) {
+            // `anullsrc` never ends; stop the audio track together with the video in recordings.
+            args.push("-shortest".to_string());
+        }
+    } else {
+        args.push("-an".to_string());
+    }
+    push_metadata_args(&mut args, settings);
+    // The tee muxer does not pass protocol options on to its targets.
+    if settings.hls_dir.is_none() {
+        push_rtmp_args(&mut args, settings, &settings.output);
+    }
+
+    match settings.segment_time_sec {
+        _ if settings.null_output => {
+            args.extend(["-f".to_string(), "null".to_string(), "-".to_string()])
+        }
+        None if settings.hls_dir.is_some() => {
+            let hls_dir = settings.hls_dir.as_deref().unwrap_or(Path::new("."));
+            args.extend([
+                "-f".to_string(),
+                "tee".to_string(),
+                build_tee_outputs(&settings.output, hls_dir),
+            ]);
+        }
+        Some(segment_time_sec) => args.extend([
+            "-f".to_string(),
+            "segment".to_string(),
+            "-segment_time".to_string(),
+            segment_time_sec.to_string(),
+            "-reset_timestamps".to_string(),
+            "1".to_string(),
+            settings.output.clone(),
+        ]),
+        None => args.extend(["-f".to_string(), "flv".to_string(), settings.output.clone()]),
+    }
+
+    if let Some(record) = settings.record.as_ref() {
+        push_record_output(&mut args, settings, record, keyint);
+    }
+
+    args
+}
+
+/// Renders `program` and `args` as one command line for the host shell, for
+/// display only: ffmpeg itself is spawned without a shell.
+pub fn format_command(program: &Path, args: &[String]) -> String {
+    std::iter::once(program.display().to_string())
+        .chain(args.iter().cloned())
+        .map(|arg| shell_quote(&arg))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Quotes `arg` for the host shell: `cmd` on Windows, POSIX `sh` elsewhere.
+pub fn shell_quote(arg: &str) -> String {
+    if cfg!(windows) {
+        shell_quote_cmd(arg)
+    } else {
+        shell_quote_sh(arg)
+    }
+}
+
+/// POSIX `sh` quoting: plain words stay bare, anything else is single-quoted with
+/// embedded `'` written as `'\''`.
+pub fn shell_quote_sh(arg: &str) -> String {
+    let plain = !arg.is_empty()
+        && arg
+            .chars()
+            .all(|ch| ch.is_ascii_alphanumeric() || "_-+=@%:,./".contains(ch));
+    if plain {
+        return arg.to_string();
+    }
+    format!("'{}'", arg.replace('\'', r"'\''"))
+}
+
+/// Windows quoting as parsed by `CommandLineToArgvW`: the argument is wrapped in
+/// double quotes with `"` escaped and backslashes before a quote doubled. `cmd`
+/// still expands `%VAR%` inside quotes, which cannot be escaped there.
+pub fn shell_quote_cmd(arg: &str) -> String {
+    let plain = !arg.is_empty()
+        && !arg
+            .chars()
+            .any(|ch| ch.is_whitespace() || "\"&|<>^()".contains(ch));
+    if plain {
+        return arg.to_string();
+    }
+
+    let mut quoted = String::from("\"");
+    let mut backslashes = 0;
+    for ch in arg.chars() {
+        match ch {
+            '\\' => backslashes += 1,
+            '"' => {
+                quoted.push_str(&"\\".repeat(backslashes * 2 + 1));
+                quoted.push('"');
+                backslashes = 0;
+            }
+            _ => {
+                quoted.push_str(&"\\".repeat(backslashes));
+                quoted.push(ch);
+                backslashes = 0;
+            }
+        }
+    }
+    // Backslashes before the closing quote would escape it.
+    quoted.push_str(&"\\".repeat(backslashes * 2));
+    quoted.push('"');
+    quoted
+}
+
+/// Filters applied to the video input before encoding: `--video-filter`, preceded
+/// for a looped video by a letterbox to the output size and frame rate.
+fn input_video_filter(settings: &EncoderSettings) -> Option<String> {
+    let fit = settings.loop_video.as_ref().map(|_| {
+        let (width, height) = (settings.width, settings.height);
+        format!(
+            "scale={width}:{height}:force_original_aspect_ratio=decrease,\
+             pad={width}:{height}:(ow-iw)/2:(oh-ih)/2,setsar=1,fps={}",
+            settings.fps
+        )
+    });
+
+    match (fit, settings.video_filter.as_ref()) {
+        (Some(fit), Some(video_filter)) => Some(format!("{fit},{video_filter}")),
+        (Some(fit), None) => Some(fit),
+        (None, video_filter) => video_filter.cloned(),
+    }
+}
+
+/// Video encoder options for one x264 output.
+fn push_x264_args(
+    args: &mut Vec<String>,
+    settings: &EncoderSettings,
+    bitrate_kbps: u32,
+    keyint: u32,
+) {
+    let bufsize = bitrate_kbps.saturating_mul(2);
+    let keyint_min = if settings.idr_on_scene_cut { 1 } else { keyint };
+
     args.extend([
         "-c:v".to_string(),
         "libx264".to_string(),
         "-preset".to_string(),
         "veryfast".to_string(),
         "-pix_fmt".to_string(),
-        "yuv420p".to_string(),
+        settings.pix_fmt.clone(),
+    ]);
+    push_color_args(args, settings);
+    args.extend([
         "-b:v".to_string(),
-        format!("{}k", settings.bitrate_kbps),
+        format!("{}k", bitrate_kbps),
         "-maxrate".to_string(),
-        format!("{}k", settings.bitrate_kbps),
+        format!("{}k", bitrate_kbps),
         "-bufsize".to_string(),
         format!("{}k", bufsize),
         "-g".to_string(),
         keyint.to_string(),
         "-keyint_min".to_string(),
+        keyint_min.to_string(),
+    ]);
+
+    if let Some(force_keyframe_sec) = settings.force_keyframe_sec {
+        args.extend([
+            "-force_key_frames".to_string(),
+            format!("expr:gte(t,n_forced*{force_keyframe_sec})"),
+        ]);
+    }
+
+    if let Some(tune) = settings.tune.as_ref() {
+        args.extend(["-tune".to_string(), tune.clone()]);
+    }
+
+    args.extend(["-x264-params".to_string(), settings.x264_opts.clone()]);
+}
+
+/// Tags the output as BT.709 in the configured range. ffmpeg's automatic rgb24 to
+/// YUV conversion follows these tags, so the pixels match what is signalled.
+fn push_color_args(args: &mut Vec<String>, settings: &EncoderSettings) {
+    args.extend([
+        "-color_range".to_string(),
+        settings.color_range.as_ffmpeg().to_string(),
+        "-colorspace".to_string(),
+        "bt709".to_string(),
+        "-color_primaries".to_string(),
+        "bt709".to_string(),
+        "-color_trc".to_string(),
+        "bt709".to_string(),
+    ]);
+}
+
+/// Title and description of one streamed output. Each is a single argv entry, so
+/// no quoting is needed.
+fn push_metadata_args(args: &mut Vec<String>, settings: &EncoderSettings) {
+    let entries = [
+        ("title", settings.stream_title.as_ref()),
+        ("description", settings.stream_description.as_ref()),
+    ];
+    for (key, value) in entries {
+        if let Some(value) = value {
+            args.extend(["-metadata".to_string(), format!("{key}={value}")]);
+        }
+    }
+}
+
+/// RTMP tuning and, for rtmps, certificate checks for one output. Other outputs
+/// get none of these; ffmpeg rejects options on muxers and protocols that do not
+/// use them.
+fn push_rtmp_args(args: &mut Vec<String>, settings: &EncoderSettings, output: &str) {
+    if !crate::rtmp::is_rtmp_output(output) {
+        return;
+    }
+    if settings.rtmp_live {
+        args.extend(["-rtmp_live".to_string(), "live".to_string()]);
+    }
+    if settings.flush_packets {
+        args.extend(["-flush_packets".to_string(), "1".to_string()]);
+    }
+    if !crate::rtmp::is_secure_output(output) {
+        return;
+    }
+    args.extend([
+        "-tls_verify".to_string(),
+        if settings.tls_verify { "1" } else { "0" }.to_string(),
+    ]);
+    if let Some(ca_file) = settings.ca_file.as_ref() {
+        args.extend(["-ca_file".to_string(), ca_file.display().to_string()]);
+    }
+}
+
+/// Audio encoder options for the silent track of one output.
+fn push_aac_args(args: &mut Vec<String>, settings: &EncoderSettings) {
+    if let Some(audio_filter) = settings.audio_filter.as_ref() {
+        args.extend(["-af".to_string(), audio_filter.clone()]);
+    }
+    args.extend([
+        "-c:a".to_string(),
+        "aac".to_string(),
+        "-b:a".to_string(),
+        "128k".to_string(),
+        "-ar".to_string(),
+        "48000".to_string(),
+        "-ac".to_string(),
+        "2".to_string(),
+    ]);
+}
+
+/// Splits the input once per rendition with `-filter_complex`, scales each copy
+/// to its height and encodes it to its own FLV output. `--video-filter` runs once
+/// before the split.
+fn push_simulcast_outputs(args: &mut Vec<String>, settings: &EncoderSettings, keyint: u32) {
+    let count = settings.renditions.len();
+    let mut graph = String::from("[0:v]");
+    if let Some(video_filter) = input_video_filter(settings) {
+        graph.push_str(&video_filter);
+        graph.push(',');
+    }
+    graph.push_str(&format!("split={count}"));
+    for index in 0..count {
+        graph.push_str(&format!("[s{index}]"));
+    }
+    for (index, rendition) in settings.renditions.iter().enumerate() {
+        match rendition.height {
+            Some(height) => graph.push_str(&format!(";[s{index}]scale=-2:{height}[v{index}]")),
+            None => graph.push_str(&format!(";[s{index}]null[v{index}]")),
+        }
+    }
+    args.extend(["-filter_complex".to_string(), graph]);
+
+    for (index, rendition) in settings.renditions.iter().enumerate() {
+        args.extend(["-map".to_string(), format!("[v{index}]")]);
+        if settings.include_silent_audio {
+            args.extend(["-map".to_string(), "1:a".to_string()]);
+        }
+        push_x264_args(args, settings, rendition.bitrate_kbps, keyint);
+        if settings.include_silent_audio {
+            push_aac_args(args, settings);
+        } else {
+            args.push("-an".to_string());
+        }
+        push_metadata_args(args, settings);
+        push_rtmp_args(args, settings, &rendition.output);
+        args.extend([
+            "-f".to_string(),
+            "flv".to_string(),
+            rendition.output.clone(),
+        ]);
+    }
+}
+
+/// Appends the `--record` output. ffmpeg encodes it separately from the main
+/// output, so it costs a second video encode.
+fn push_record_output(
+    args: &mut Vec<String>,
+    settings: &EncoderSettings,
+    record: &RecordConfig,
+    keyint: u32,
+) {
+    if let Some(video_filter) = input_video_filter(settings) {
+        args.extend(["-vf".to_string(), video_filter]);
+    }
+
+    match record.codec {
+        RecordCodec::Vp9 => args.extend([
+            "-c:v".to_string(),
+            "libvpx-vp9".to_string(),
+            "-deadline".to_string(),
+            "realtime".to_string(),
+            "-cpu-used".to_string(),
+            "8".to_string(),
+            "-row-mt".to_string(),
+            "1".to_string(),
+            "-pix_fmt".to_string(),
+            "yuv420p".to_string(),
+        ]),
+        RecordCodec::H264 => args.extend([
+            "-c:v".to_string(),
+            "libx264".to_string(),
+            "-preset".to_string(),
+            "veryfast".to_string(),
+            "-pix_fmt".to_string(),
+            settings.pix_fmt.clone(),
+            "-x264-params".to_string(),
+            settings.x264_opts.clone(),
+        ]),
+    }
+    push_color_args(args, settings);
+    args.extend([
+        "-b:v".to_string(),
+        format!("{}k", settings.bitrate_kbps),
+        "-g".to_string(),
         keyint.to_string(),
-        "-x264-params".to_string(),
-        settings.x264_opts.clone(),
     ]);
 
     if settings.include_silent_audio {
+        if let Some(audio_filter) = settings.audio_filter.as_ref() {
+            args.extend(["-af".to_string(), audio_filter.clone()]);
+        }
         args.extend([
             "-c:a".to_string(),
-            "aac".to_string(),
+            "libopus".to_string(),
             "-b:a".to_string(),
             "128k".to_string(),
-            "-ar".to_string(),
-            "48000".to_string(),
-            "-ac".to_string(),
-            "2".to_string(),
+            "-shortest".to_string(),
         ]);
     } else {
         args.push("-an".to_string());
     }
 
-    args.extend(["-f".to_string(), "flv".to_string(), settings.output.clone()]);
+    args.extend([
+        "-f".to_string(),
+        record.format.muxer().to_string(),
+        record.path.clone(),
+    ]);
+}
 
-    args
+/// Tee muxer target writing `output` as FLV and `index.m3u8` plus numbered `.ts`
+/// segments into `hls_dir`. Later attempts append to the existing playlist.
+pub fn build_tee_outputs(output: &str, hls_dir: &Path) -> String {
+    let playlist = hls_dir.join("index.m3u8");
+    format!(
+        "[f=flv]{}|[f=hls:hls_time=4:hls_list_size=0:hls_flags=append_list]{}",
+        escape_tee(output),
+        escape_tee(&playlist.display().to_string())
+    )
+}
+
+/// Escapes the characters the tee muxer treats as separators in a target name.
+fn escape_tee(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for ch in value.chars() {
+        if matches!(ch, '\\' | '|' | '[' | ']') {
+            escaped.push('\\');
+        }
+        escaped.push(ch);
+    }
+    escaped
+}
+
+/// Returns whether `output` is a local file path rather than a network URL.
+pub fn is_file_output(output: &str) -> bool {
+    !output.contains("://")
 }
 
 #[derive(Debug)]
@@ -104,6 +536,14 @@ pub struct FfmpegEncoder {
     child: Child,
     stdin: ChildStdin,
     stderr_task: JoinHandle<()>,
+    progress_task: JoinHandle<()>,
+    progress: Arc<Mutex<Option<FfmpegProgress>>>,
+    spawned_at: Instant,
+    init_timeout: Duration,
+    /// Set once ffmpeg has taken a whole frame, meaning its output is open.
+    initialized: bool,
+    drop_after: Option<Duration>,
+    dropping: DroppingWriter,
 }
 
 impl FfmpegEncoder {
@@ -121,7 +561,7 @@ impl FfmpegEncoder {
         cmd.args(&args)
             .stdin(std::process::Stdio::piped())
             .stderr(std::process::Stdio::piped())
-            .stdout(std::process::Stdio::null());
+            .stdout(std::process::Stdio::piped());
 
         let mut child = cmd.spawn().with_context(|| {
             format!(
@@ -134,13 +574,38 @@ impl FfmpegEncoder {
 
         let stderr = child.stderr.take().context("ffmpeg stderr unavailable")?;
 
-        let stderr_task = tokio::spawn(async move {
-            let mut lines = BufReader::new(stderr).lines();
+        let log_stderr = async move {
+            // Stats lines are terminated by `\r` rather than `\n`, so split on both.
+            let mut segments = BufReader::new(stderr).split(b'\r');
+            while let Ok(Some(segment)) = segments.next_segment().await {
+                for raw_line in segment.split(|byte| *byte == b'\n') {
+                    let line = String::from_utf8_lossy(raw_line);
+                    let line = line.trim();
+                    if line.is_empty() {
+                        continue;
+                    }
+
+                    if verbose {
+                        info!(target: "ffmpeg", "{line}");
+                    } else {
+                        debug!(target: "ffmpeg", "{line}");
+                    }
+                }
+            }
+        };
+        let stderr_task = tokio::spawn(log_stderr.in_current_span());
+
+        let stdout = child.stdout.take().context("ffmpeg stdout unavailable")?;
+        let progress = Arc::new(Mutex::new(None));
+        let latest_progress = Arc::clone(&progress);
+        let progress_task = tokio::spawn(async move {
+            let mut parser = ProgressParser::default();
+            let mut lines = BufReader::new(stdout).lines();
             while let Ok(Some(line)) = lines.next_line().await {
-                if verbose {
-                    info!(target: "ffmpeg", "{line}");
-                } else {
-                    debug!(target: "ffmpeg", "{line}");
+                if let Some(snapshot) = parser.push_line(&line)
+                    && let Ok(mut latest) = latest_progress.lock()
+                {
+                    *latest = Some(snapshot);
                 }
             }
         });
@@ -149,9 +614,30 @@ impl FfmpegEncoder {
             child,
             stdin,
             stderr_task,
+            progress_task,
+            progress,
+            spawned_at: Instant::now(),
+            init_timeout: settings.init_timeout,
+            initialized: false,
+            drop_after: settings.drop_after,
+            dropping: DroppingWriter::default(),
         })
     }
 
+    pub fn spawned_at(&self) -> Instant {
+        self.spawned_at
+    }
+
+    /// Frames skipped under `--on-backpressure drop`.
+    pub fn dropped_frames(&self) -> u64 {
+        self.dropping.dropped_frames()
+    }
+
+    /// Latest snapshot reported on ffmpeg's `-progress` pipe, if any.
+    pub fn progress(&self) -> Option<FfmpegProgress> {
+        self.progress.lock().ok().and_then(|latest| latest.clone())
+    }
+
     pub fn try_wait(&mut self) -> Result<Option<ExitStatus>> {
         self.child
             .try_wait()
@@ -164,17 +650,77 @@ impl FfmpegEncoder {
         }
 
         if let Some(status) = self.try_wait()? {
-            bail!("ffmpeg exited early with status {status}");
+            return Err(RuntimeError::EncoderExited { status }.into());
         }
 
-        self.stdin
-            .write_all(&frame.data)
-            .await
-            .context("failed writing frame to ffmpeg stdin")?;
+        // ffmpeg opens its output before reading stdin, so a stalled connect to the
+        // ingest backs up the pipe and blocks the first write.
+        let written = if let (true, Some(drop_after)) = (self.initialized, self.drop_after) {
+            self.dropping
+                .write(&mut self.stdin, &frame.data, drop_after)
+                .await
+        } else if self.initialized {
+            self.stdin.write_all(&frame.data).await
+        } else {
+            match tokio::time::timeout(self.init_timeout, self.stdin.write_all(&frame.data)).await {
+                Ok(written) => written,
+                Err(_) => return Err(self.init_timed_out().await),
+            }
+        };
+        if let Err(err) = written {
+            // A broken pipe usually means ffmpeg exited between the poll and the write.
+            if let Ok(Some(status)) = self.try_wait() {
+                return Err(RuntimeError::EncoderExited { status }.into());
+            }
+            return Err(anyhow::Error::new(err).context("failed writing frame to ffmpeg stdin"));
+        }
 
+        self.initialized = true;
         Ok(())
     }
 
+    /// Kills an ffmpeg that is stuck opening its output, so the caller does not
+    /// keep it for the next attempt.
+    async fn init_timed_out(&mut self) -> anyhow::Error {
+        self.kill_and_wait().await;
+        RuntimeError::EncoderInitTimeout {
+            timeout_ms: self.init_timeout.as_millis() as u64,
+        }
+        .into()
+    }
+
+    /// Drives `task` to completion while re-sending `frame` every `interval`, so
+    /// the output keeps its frame rate while no new frames arrive. Without a
+    /// frame this simply awaits `task`. If ffmpeg stops accepting frames, `task`
+    /// still runs to completion before the write error is returned.
+    pub async fn hold_frame_while<F: Future>(
+        &mut self,
+        frame: Option<&RgbFrame>,
+        interval: Duration,
+        task: F,
+    ) -> Result<F::Output> {
+        let Some(frame) = frame else {
+            return Ok(task.await);
+        };
+
+        let mut tick = tokio::time::interval(interval);
+        tick.set_missed_tick_behavior(MissedTickBehavior::Skip);
+        tokio::pin!(task);
+        let write_error = loop {
+            tokio::select! {
+                output = &mut task => return Ok(output),
+                _ = tick.tick() => {
+                    if let Err(err) = self.write_frame(frame).await {
+                        break err;
+                    }
+                }
+            }
+        };
+
+        task.await;
+        Err(write_error)
+    }
+
     pub async fn kill_and_wait(&mut self) {
         match self.child.kill().await {
             Ok(()) => {}
@@ -188,18 +734,107 @@ impl FfmpegEncoder {
     }
 
     pub async fn wait_for_exit(mut self) -> Result<ExitStatus> {
+        if let Some(rest) = self.dropping.take_unwritten() {
+            // A truncated last frame would be discarded by ffmpeg.
+            if let Err(err) = self.stdin.write_all(&rest).await {
+                debug!("failed to finish the last frame: {err}");
+            }
+        }
         drop(self.stdin);
-        let status = self
-            .child
-            .wait()
-            .await
-            .context("failed waiting for ffmpeg exit")?;
+        let status = if self.initialized {
+            self.child.wait().await
+        } else {
+            match tokio::time::timeout(self.init_timeout, self.child.wait()).await {
+                Ok(status) => status,
+                Err(_) => {
+                    self.stderr_task.abort();
+                    self.progress_task.abort();
+                    let timeout_ms = self.init_timeout.as_millis() as u64;
+                    if let Err(err) = self.child.kill().await {
+                        warn!("failed to kill ffmpeg: {err}");
+                    }
+                    return Err(RuntimeError::EncoderInitTimeout { timeout_ms }.into());
+                }
+            }
+        }
+        .context("failed waiting for ffmpeg exit")?;
 
         self.stderr_task.abort();
+        self.progress_task.abort();
         Ok(status)
     }
 }
 
+/// Frame writes for `--on-backpressure drop`: a frame that does not fit before
+/// its deadline keeps its tail for the next write, and frames that come while
+/// that tail still does not fit are dropped.
+#[derive(Debug, Default)]
+pub struct DroppingWriter {
+    /// Tail of a frame whose write ran past its deadline. It goes out before
+    /// anything else, since ffmpeg reads raw frames back to back.
+    unwritten: Option<Vec<u8>>,
+    dropped_frames: u64,
+}
+
+impl DroppingWriter {
+    /// Writes `frame` for at most `drop_after`, after finishing any earlier frame.
+    /// When that earlier frame still does not fit, `frame` is dropped; when `frame`
+    /// itself does not, its tail is kept for the next call.
+    pub async fn write<W: AsyncWrite + Unpin>(
+        &mut self,
+        writer: &mut W,
+        frame: &[u8],
+        drop_after: Duration,
+    ) -> std::io::Result<()> {
+        let deadline = tokio::time::Instant::now() + drop_after;
+        if let Some(mut rest) = self.unwritten.take() {
+            let written = write_until(writer, &rest, deadline).await?;
+            if written < rest.len() {
+                rest.drain(..written);
+                self.unwritten = Some(rest);
+                self.dropped_frames = self.dropped_frames.saturating_add(1);
+                return Ok(());
+            }
+        }
+
+        let written = write_until(writer, frame, deadline).await?;
+        if written < frame.len() {
+            self.unwritten = Some(frame[written..].to_vec());
+        }
+        Ok(())
+    }
+
+    /// Frames dropped so far.
+    pub fn dropped_frames(&self) -> u64 {
+        self.dropped_frames
+    }
+
+    /// Takes the tail of a partly written frame, if any.
+    pub fn take_unwritten(&mut self) -> Option<Vec<u8>> {
+        self.unwritten.take()
+    }
+}
+
+/// Writes as much of `data` as `writer` takes before `deadline`, returning the
+/// byte count. Each `write` either completes or writes nothing when the
+/// deadline cancels it, so the count is exact.
+async fn write_until<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    data: &[u8],
+    deadline: tokio::time::Instant,
+) -> std::io::Result<usize> {
+    let mut written = 0;
+    while written < data.len() {
+        match tokio::time::timeout_at(deadline, writer.write(&data[written..])).await {
+            Ok(Ok(0)) => return Err(std::io::ErrorKind::WriteZero.into()),
+            Ok(Ok(count)) => written += count,
+            Ok(Err(err)) => return Err(err),
+            Err(_) => break,
+        }
+    }
+    Ok(written)
+}
+
 pub fn ffmpeg_executable_name() -> &'static str {
     if cfg!(target_os = "windows") {
         "ffmpeg.exe"
@@ -208,10 +843,6 @@ pub fn ffmpeg_executable_name() -> &'static str {
     }
 }
 
-pub fn default_ffmpeg_sidecar_path(exe_dir: &Path) -> PathBuf {
-    exe_dir
-        .join("..")
-        .join("sidecar")
-        .join("ffmpeg")
-        .join(ffmpeg_executable_name())
+pub fn default_ffmpeg_sidecar_path(sidecar_dir: &Path) -> PathBuf {
+    sidecar_dir.join("ffmpeg").join(ffmpeg_executable_name())
 }
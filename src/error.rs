@@ -1,4 +1,5 @@
 use std::path::PathBuf;
+use std::process::ExitStatus;
 
 use thiserror::Error;
 
@@ -8,6 +9,107 @@ pub enum ConfigError {
     UnsupportedWebsiteScheme(String),
     #[error("invalid website URL `{0}`")]
     InvalidWebsiteUrl(String),
+    #[error("`--url -` expects the page URL on the first line of stdin")]
+    MissingStdinUrl,
+    #[error("`--grid` takes exactly 4 page URLs for a 2x2 layout, got {0}")]
+    InvalidGrid(usize),
+    #[error(
+        "invalid network throttle `{0}`: expected `slow-3g`, `fast-3g` or `download_kbps:upload_kbps:latency_ms`"
+    )]
+    InvalidNetworkThrottle(String),
+    #[error("invalid gpu mode `{0}`: expected `on`, `off` or `swiftshader`")]
+    InvalidGpuMode(String),
+    #[error(
+        "invalid rendition in `{0}`: expected a suffix like `@720p:2500k` with an even height of at least 16"
+    )]
+    InvalidRendition(String),
+    #[error("invalid test pattern `{0}`: expected `bars`, `box`, `solid` or `solid:RRGGBB`")]
+    InvalidTestPattern(String),
+    #[error("invalid color range `{0}`: expected `limited` or `full`")]
+    InvalidColorRange(String),
+    #[error("invalid ffmpeg source `{0}`: expected `auto`, `sidecar` or `system`")]
+    InvalidFfmpegSource(String),
+    #[error(
+        "`--hls-dir` shares one encode with `--output`, so the output cannot have a rendition suffix"
+    )]
+    HlsWithRenditions,
+    #[error(
+        "`--hls-dir` cannot be combined with an `rtmps://` output: ffmpeg's tee muxer would skip certificate verification"
+    )]
+    HlsWithSecureOutput,
+    #[error(
+        "`--incognito` cannot be combined with `--user-data-dir`: incognito sessions do not persist a profile"
+    )]
+    IncognitoWithUserDataDir,
+    #[error("invalid record format `{0}`: expected `mkv` or `webm`")]
+    InvalidRecordFormat(String),
+    #[error("invalid record codec `{0}`: expected `vp9` or `h264`")]
+    InvalidRecordCodec(String),
+    #[error("`{format}` recordings cannot use `{codec}`")]
+    UnsupportedRecordCodec {
+        format: &'static str,
+        codec: &'static str,
+    },
+    #[error("invalid emulated media `{0}`: expected `print` or `screen`")]
+    InvalidEmulatedMedia(String),
+    #[error("invalid blank page action `{0}`: expected `warn`, `reload` or `fail`")]
+    InvalidBlankAction(String),
+    #[error("invalid backpressure policy `{0}`: expected `block` or `drop`")]
+    InvalidBackpressurePolicy(String),
+    #[error("invalid scale `{0}`: expected a factor above 0 and at most 1, such as `0.5`")]
+    InvalidScale(f64),
+    #[error("`--{field}` must be even for {pix_fmt} output, got {value}")]
+    OddDimension {
+        field: &'static str,
+        value: u32,
+        pix_fmt: String,
+    },
+    #[error("invalid frame rate `{0}`: expected e.g. `30`, `29.97` or `30000/1001`")]
+    InvalidFrameRate(String),
+    #[error("invalid bitrate `{0}`: expected kbps such as `4500`, or `auto`")]
+    InvalidBitrate(String),
+    #[error("invalid allowed domain `{0}`: expected a host name such as `example.com`")]
+    InvalidAllowDomain(String),
+    #[error(
+        "invalid host resolver rule `{0}`: expected `MAP <host pattern> <replacement>` or `EXCLUDE <host pattern>`"
+    )]
+    InvalidHostResolverRule(String),
+    #[error("invalid window position `{0}`: expected `x,y`")]
+    InvalidWindowPosition(String),
+    #[error("segment pattern `{0}` must contain a sequence specifier such as `%03d`")]
+    InvalidSegmentPattern(String),
+    #[error(
+        "unsupported output pixel format `{0}`, expected one of yuv420p, yuv422p, yuv444p, nv12"
+    )]
+    UnsupportedPixelFormat(String),
+    #[error(
+        "invalid x264 tune `{0}`: expected at most one of film, animation, grain, stillimage, psnr, ssim, optionally combined with fastdecode and/or zerolatency"
+    )]
+    InvalidTune(String),
+    #[error("malformed `--x264-opts` entry `{0}`: expected `key=value`")]
+    InvalidX264Opts(String),
+    #[error("`--{flag}` must be an http or https URL, got `{value}`")]
+    InvalidHeaderUrl { flag: &'static str, value: String },
+    #[error("`--{flag}` must be non-empty text without control characters, got `{value}`")]
+    InvalidMetadata { flag: &'static str, value: String },
+    #[error("invalid accept language `{0}`: expected entries like `fr-FR` or `fr;q=0.9`")]
+    InvalidAcceptLanguage(String),
+    #[error("connect URL must use ws, wss, http or https, got `{0}`")]
+    InvalidConnectUrl(String),
+    #[error("frame source must be a ws:// URL, got `{0}`")]
+    InvalidFrameSource(String),
+    #[error("splash URL must use about, data, http or https, got `{0}`")]
+    InvalidSplashUrl(String),
+    #[error("`--nice` must be between -20 and 19, got {0}")]
+    InvalidNice(i32),
+    #[error("`--{flag}` only applies to rtmps:// outputs, got `{output}`")]
+    TlsRequiresSecureOutput { flag: &'static str, output: String },
+    #[error("`--preview-sec` records to a local file, got `{0}`")]
+    PreviewRequiresFileOutput(String),
+    #[error("`--preview-sec` records FLV, so its output must end in `.flv`, got `{0}`")]
+    PreviewRequiresFlvOutput(String),
+    #[error("CA file `{}` does not exist", .0.display())]
+    MissingCaFile(PathBuf),
     #[error("`{field}` out of range: got {actual}, expected {min}..={max}")]
     OutOfRange {
         field: &'static str,
@@ -15,6 +117,11 @@ pub enum ConfigError {
         max: u64,
         actual: u64,
     },
+    #[error("`--{flag}` requires `--{requires}`")]
+    RequiresFlag {
+        flag: &'static str,
+        requires: &'static str,
+    },
     #[error(transparent)]
     Rtmp(#[from] crate::rtmp::RtmpError),
 }
@@ -25,8 +132,51 @@ pub enum RuntimeError {
     ShutdownRequested,
     #[error("timed out waiting for screencast frames")]
     ScreencastTimeout,
+    #[error("lost connection to chromium: {reason}")]
+    BrowserDisconnected { reason: String },
+    #[error("lost connection to frame source: {reason}")]
+    FrameSourceDisconnected { reason: String },
+    #[error("output {host} is unreachable: {reason}")]
+    OutputUnreachable { host: String, reason: String },
+    #[error("no open page matches target filter `{filter}`")]
+    NoMatchingTarget { filter: String },
+    #[error("chromium is using {rss_mb}MB, above the {limit_mb}MB limit")]
+    BrowserMemoryExceeded { rss_mb: u64, limit_mb: u64 },
+    #[error("{url} responded with HTTP {status}")]
+    HttpError { status: u16, url: String },
+    #[error("page has shown a single flat color for {blank_sec}s")]
+    BlankPage { blank_sec: u64 },
+    #[error("frame pacing cannot keep up at {from}fps; lowering to {to}fps")]
+    FpsDownshift {
+        from: crate::cli::FrameRate,
+        to: crate::cli::FrameRate,
+    },
+    #[error("bitrate changed from {from}kbps to {to}kbps; restarting ffmpeg")]
+    BitrateChange { from: u32, to: u32 },
+    #[error("ffmpeg exited early with status {status}")]
+    EncoderExited { status: ExitStatus },
+    #[error("timed out after {timeout_ms}ms waiting for ffmpeg to open its output")]
+    EncoderInitTimeout { timeout_ms: u64 },
+    #[error("timed out after {timeout_ms}ms waiting for chromium to launch")]
+    BrowserLaunchTimeout { timeout_ms: u64 },
     #[error(
-        "missing sidecar binary `{name}` at `{path}`. Provide an explicit override path or place sidecars at this location. For local development, fetch sidecars with `./scripts/fetch-sidecars.sh` (macOS/Linux) or `./scripts/fetch-sidecars.ps1` (Windows). Supported packaged targets: macOS arm64, Linux x86_64, Windows x86_64"
+        "missing sidecar binary `{name}` at `{path}`. Provide an explicit override path or place sidecars at this location. Fetch sidecars with `browser-stream fetch-sidecars`, or with `./scripts/fetch-sidecars.sh` (macOS/Linux) or `./scripts/fetch-sidecars.ps1` (Windows). Supported packaged targets: macOS arm64, Linux x86_64, Windows x86_64"
     )]
     MissingSidecar { name: &'static str, path: PathBuf },
+    #[error(
+        "downloaded {name} does not match the pinned checksum: expected sha256 {expected}, got {actual}"
+    )]
+    SidecarChecksumMismatch {
+        name: &'static str,
+        expected: String,
+        actual: String,
+    },
+}
+
+impl RuntimeError {
+    /// ffmpeg exited with status 0 mid-stream, typically because the ingest closed
+    /// the connection gracefully rather than ffmpeg crashing.
+    pub fn is_clean_encoder_exit(&self) -> bool {
+        matches!(self, Self::EncoderExited { status } if status.success())
+    }
 }
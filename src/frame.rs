@@ -1,6 +1,10 @@
+use std::path::Path;
+
 use anyhow::{Context, Result};
 use base64::Engine;
 
+use crate::cli::TestPattern;
+
 #[derive(Debug, Clone)]
 pub struct RgbFrame {
     pub width: u32,
@@ -13,11 +17,32 @@ pub fn decode_screencast_frame(
     target_width: u32,
     target_height: u32,
 ) -> Result<RgbFrame> {
-    let bytes = base64::engine::general_purpose::STANDARD
-        .decode(encoded_data)
-        .context("failed to decode CDP frame payload")?;
+    let engine = base64::engine::general_purpose::STANDARD;
+    // CDP payloads have no whitespace, but remote frame sources may wrap lines.
+    let bytes = if encoded_data.bytes().any(|byte| byte.is_ascii_whitespace()) {
+        let compact: String = encoded_data
+            .chars()
+            .filter(|ch| !ch.is_ascii_whitespace())
+            .collect();
+        engine.decode(compact)
+    } else {
+        engine.decode(encoded_data)
+    }
+    .context("failed to decode CDP frame payload")?;
+
+    decode_image_frame(&bytes, target_width, target_height)
+}
+
+/// Loads an image file such as `--stall-image`, resized like a screencast frame.
+pub fn load_image_frame(path: &Path, target_width: u32, target_height: u32) -> Result<RgbFrame> {
+    let bytes =
+        std::fs::read(path).with_context(|| format!("failed to read {}", path.display()))?;
+    decode_image_frame(&bytes, target_width, target_height)
+        .with_context(|| format!("failed to load image {}", path.display()))
+}
 
-    let img = image::load_from_memory(&bytes).context("failed to decode image bytes")?;
+fn decode_image_frame(bytes: &[u8], target_width: u32, target_height: u32) -> Result<RgbFrame> {
+    let img = image::load_from_memory(bytes).context("failed to decode image bytes")?;
     let normalized = if img.width() == target_width && img.height() == target_height {
         img
     } else {
@@ -35,3 +60,206 @@ pub fn decode_screencast_frame(
         data: rgb.into_raw(),
     })
 }
+
+/// Writes `frame` to `path` as a PNG.
+pub fn save_png(frame: &RgbFrame, path: &Path) -> Result<()> {
+    let image = image::ImageBuffer::<image::Rgb<u8>, _>::from_raw(
+        frame.width,
+        frame.height,
+        frame.data.as_slice(),
+    )
+    .context("frame data does not match its dimensions")?;
+    image
+        .save_with_format(path, image::ImageFormat::Png)
+        .with_context(|| format!("failed to write {}", path.display()))
+}
+
+/// 75% SMPTE bars: white, yellow, cyan, green, magenta, red, blue.
+const SMPTE_TOP: [[u8; 3]; 7] = [
+    [191, 191, 191],
+    [191, 191, 0],
+    [0, 191, 191],
+    [0, 191, 0],
+    [191, 0, 191],
+    [191, 0, 0],
+    [0, 0, 191],
+];
+/// Reverse blue bars below the main bars.
+const SMPTE_MIDDLE: [[u8; 3]; 7] = [
+    [0, 0, 191],
+    [16, 16, 16],
+    [191, 0, 191],
+    [16, 16, 16],
+    [0, 191, 191],
+    [16, 16, 16],
+    [191, 191, 191],
+];
+const BLACK: [u8; 3] = [16, 16, 16];
+
+/// Renders frame `frame_index` of `pattern`. Output depends only on the arguments,
+/// so the same index always produces the same frame.
+pub fn render_test_pattern(
+    pattern: TestPattern,
+    width: u32,
+    height: u32,
+    frame_index: u64,
+) -> RgbFrame {
+    let mut data = Vec::with_capacity(width as usize * height as usize * 3);
+    match pattern {
+        TestPattern::Solid(color) => {
+            for _ in 0..u64::from(width) * u64::from(height) {
+                data.extend_from_slice(&color);
+            }
+        }
+        TestPattern::Bars => {
+            for y in 0..height {
+                for x in 0..width {
+                    data.extend_from_slice(&smpte_pixel(x, y, width, height));
+                }
+            }
+        }
+        TestPattern::MovingBox => {
+            let size = (width.min(height) / 8).max(1);
+            let step = u64::from((width.min(height) / 60).max(1));
+            let left = bounce(frame_index * step, width - size);
+            let top = bounce(frame_index * step, height - size);
+            for y in 0..height {
+                for x in 0..width {
+                    let inside = (left..left + size).contains(&x) && (top..top + size).contains(&y);
+                    data.extend_from_slice(if inside { &[235, 235, 235] } else { &BLACK });
+                }
+            }
+        }
+    }
+
+    RgbFrame {
+        width,
+        height,
+        data,
+    }
+}
+
+/// The color of `frame` if every pixel is within `tolerance` of the first one on
+/// each channel, which is how a blank or crashed page looks after JPEG encoding.
+/// Stops at the first pixel that differs, so ordinary frames cost very little.
+pub fn uniform_color(frame: &RgbFrame, tolerance: u8) -> Option<[u8; 3]> {
+    let mut pixels = frame.data.chunks_exact(3);
+    let first = pixels.next()?;
+    pixels
+        .all(|pixel| {
+            pixel
+                .iter()
+                .zip(first)
+                .all(|(value, reference)| value.abs_diff(*reference) <= tolerance)
+        })
+        .then(|| [first[0], first[1], first[2]])
+}
+
+/// Mean absolute difference between `frame` and `baseline` over every color
+/// channel, from 0 for identical frames to 255. `None` when the sizes differ.
+pub fn mean_abs_diff(frame: &RgbFrame, baseline: &RgbFrame) -> Option<f64> {
+    if (frame.width, frame.height) != (baseline.width, baseline.height)
+        || frame.data.len() != baseline.data.len()
+        || frame.data.is_empty()
+    {
+        return None;
+    }
+    let total: u64 = frame
+        .data
+        .iter()
+        .zip(&baseline.data)
+        .map(|(value, reference)| u64::from(value.abs_diff(*reference)))
+        .sum();
+    Some(total as f64 / frame.data.len() as f64)
+}
+
+/// Cell `(x, y, width, height)` of each page in a 2x2 `--grid` of `width` x
+/// `height`, in reading order. An odd dimension gives its extra pixel to the right
+/// column or the bottom row.
+pub fn grid_cells(width: u32, height: u32) -> [(u32, u32, u32, u32); 4] {
+    let (left, top) = (width / 2, height / 2);
+    let (right, bottom) = (width - left, height - top);
+    [
+        (0, 0, left, top),
+        (left, 0, right, top),
+        (0, top, left, bottom),
+        (left, top, right, bottom),
+    ]
+}
+
+/// Places each frame of `cells` into its [`grid_cells`] slot of a new `width` x
+/// `height` frame. Missing frames, and frames whose size does not match their
+/// cell, leave the cell black.
+pub fn composite_grid(width: u32, height: u32, cells: &[Option<RgbFrame>]) -> RgbFrame {
+    let row_bytes = width as usize * 3;
+    let mut data = vec![0; row_bytes * height as usize];
+    for ((x, y, cell_width, cell_height), frame) in grid_cells(width, height).into_iter().zip(cells)
+    {
+        let Some(frame) = frame else {
+            continue;
+        };
+        if (frame.width, frame.height) != (cell_width, cell_height) {
+            continue;
+        }
+        let cell_row_bytes = cell_width as usize * 3;
+        for (row, source) in frame.data.chunks_exact(cell_row_bytes).enumerate() {
+            let start = (y as usize + row) * row_bytes + x as usize * 3;
+            data[start..start + cell_row_bytes].copy_from_slice(source);
+        }
+    }
+    RgbFrame {
+        width,
+        height,
+        data,
+    }
+}
+
+/// Whether consecutive frames of `pattern` differ.
+pub fn test_pattern_is_animated(pattern: TestPattern) -> bool {
+    matches!(pattern, TestPattern::MovingBox)
+}
+
+fn smpte_pixel(x: u32, y: u32, width: u32, height: u32) -> [u8; 3] {
+    let (x, y, width, height) = (
+        u64::from(x),
+        u64::from(y),
+        u64::from(width),
+        u64::from(height),
+    );
+    let bar = (x * 7 / width) as usize;
+    if y < height * 2 / 3 {
+        return SMPTE_TOP[bar];
+    }
+    if y < height * 3 / 4 {
+        return SMPTE_MIDDLE[bar];
+    }
+
+    // Bottom row in quarter-bar units: -I, white, +Q and black take five units each,
+    // then a pluge (below black, black, above black) in one bar, then black.
+    match x * 28 / width {
+        0..5 => [0, 33, 76],
+        5..10 => [235, 235, 235],
+        10..15 => [50, 0, 106],
+        20..24 => match x * 21 / width {
+            15 => [9, 9, 9],
+            16 => BLACK,
+            _ => [29, 29, 29],
+        },
+        _ => BLACK,
+    }
+}
+
+/// Position along `0..=range` after travelling `distance`, reflecting at both ends.
+fn bounce(distance: u64, range: u32) -> u32 {
+    let range = u64::from(range);
+    if range == 0 {
+        return 0;
+    }
+    let phase = distance % (2 * range);
+    let position = if phase <= range {
+        phase
+    } else {
+        2 * range - phase
+    };
+    position as u32
+}
@@ -0,0 +1,142 @@
+use std::pin::Pin;
+use std::time::Duration;
+
+use anyhow::{Context, Result, anyhow};
+use async_tungstenite::tungstenite::Message;
+use futures::{Stream, StreamExt};
+use tokio::time::MissedTickBehavior;
+use tokio_util::sync::CancellationToken;
+use tracing::info;
+
+use crate::cli::{AppConfig, TestPattern};
+use crate::encoder::FfmpegEncoder;
+use crate::error::RuntimeError;
+use crate::frame::{
+    RgbFrame, decode_screencast_frame, render_test_pattern, test_pattern_is_animated,
+};
+use crate::pump::{SourceItem, pump_frames};
+
+/// Extracts the base64 image payload from a remote frame envelope.
+///
+/// Remote capture agents send one JSON text message per frame:
+/// `{"data": "<base64 JPEG or PNG>"}`. Other fields are ignored so agents can
+/// attach their own metadata.
+pub fn parse_frame_envelope(text: &str) -> Result<String> {
+    let envelope: serde_json::Value =
+        serde_json::from_str(text).context("frame envelope is not valid JSON")?;
+
+    envelope
+        .get("data")
+        .and_then(serde_json::Value::as_str)
+        .map(str::to_string)
+        .ok_or_else(|| anyhow!("frame envelope is missing a string `data` field"))
+}
+
+/// Connects to a remote capture agent at `url` and feeds the frames it sends to
+/// the encoder, paced the same way as a local screencast. `last_frame`,
+/// `produced_frames` and `cancel` behave as in
+/// [`crate::chromium::stream_browser_to_encoder`].
+pub async fn stream_ws_to_encoder(
+    config: &AppConfig,
+    url: &str,
+    encoder: &mut FfmpegEncoder,
+    last_frame: &mut Option<RgbFrame>,
+    produced_frames: &mut bool,
+    cancel: &CancellationToken,
+) -> Result<()> {
+    info!(url, "connecting to remote frame source");
+    let frame_interval = config.fps.interval();
+    let connect_timeout = Duration::from_millis(config.browser_launch_timeout_ms);
+    let connecting = tokio::time::timeout(
+        connect_timeout,
+        async_tungstenite::tokio::connect_async(url),
+    );
+    let (socket, _) = encoder
+        .hold_frame_while(last_frame.as_ref(), frame_interval, connecting)
+        .await?
+        .map_err(|_| anyhow!("timed out connecting to frame source {url}"))?
+        .with_context(|| format!("failed to connect to frame source {url}"))?;
+
+    let messages = socket.filter_map(|message| {
+        let item = match message {
+            Ok(Message::Text(text)) => Some(Ok(parse_frame_envelope(&text)
+                .and_then(|data| decode_screencast_frame(&data, config.width, config.height)))),
+            Ok(Message::Close(close)) => {
+                let reason = close
+                    .map(|frame| frame.reason.to_string())
+                    .filter(|reason| !reason.is_empty())
+                    .unwrap_or_else(|| "closed by remote".to_string());
+                Some(Err(RuntimeError::FrameSourceDisconnected { reason }.into()))
+            }
+            Ok(_) => None,
+            Err(err) => Some(Err(RuntimeError::FrameSourceDisconnected {
+                reason: err.to_string(),
+            }
+            .into())),
+        };
+        std::future::ready(item)
+    });
+    let ended = futures::stream::once(async {
+        Err(RuntimeError::FrameSourceDisconnected {
+            reason: "connection ended".to_string(),
+        }
+        .into())
+    });
+
+    pump_frames(
+        config,
+        messages.chain(ended),
+        encoder,
+        last_frame,
+        produced_frames,
+        None,
+        cancel,
+    )
+    .await
+}
+
+/// Feeds synthesized `pattern` frames to the encoder at the configured rate, with
+/// no browser involved. Runs until `cancel`, `--max-duration-sec` or
+/// an encoder failure.
+pub async fn stream_test_pattern_to_encoder(
+    config: &AppConfig,
+    pattern: TestPattern,
+    encoder: &mut FfmpegEncoder,
+    last_frame: &mut Option<RgbFrame>,
+    produced_frames: &mut bool,
+    cancel: &CancellationToken,
+) -> Result<()> {
+    info!(%pattern, "streaming test pattern");
+    let (width, height) = (config.width, config.height);
+    let frames: Pin<Box<dyn Stream<Item = SourceItem> + Send>> =
+        if test_pattern_is_animated(pattern) {
+            let mut render_tick = tokio::time::interval(config.fps.interval());
+            render_tick.set_missed_tick_behavior(MissedTickBehavior::Skip);
+            Box::pin(futures::stream::unfold(
+                (render_tick, 0_u64),
+                move |(mut render_tick, index)| async move {
+                    render_tick.tick().await;
+                    let frame = render_test_pattern(pattern, width, height, index);
+                    Some((Ok(Ok(frame)), (render_tick, index.saturating_add(1))))
+                },
+            ))
+        } else {
+            Box::pin(
+                futures::stream::once(std::future::ready(Ok(Ok(render_test_pattern(
+                    pattern, width, height, 0,
+                )))))
+                .chain(futures::stream::pending()),
+            )
+        };
+
+    pump_frames(
+        config,
+        frames,
+        encoder,
+        last_frame,
+        produced_frames,
+        None,
+        cancel,
+    )
+    .await
+}
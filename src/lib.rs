@@ -3,5 +3,13 @@ pub mod cli;
 pub mod encoder;
 pub mod error;
 pub mod frame;
+pub mod frame_source;
+pub mod pipeline;
+pub mod progress;
+pub mod pump;
 pub mod retry;
 pub mod rtmp;
+pub mod sidecar;
+pub mod startup;
+
+pub use chromium::capture_frames;
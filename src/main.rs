@@ -1,223 +1,240 @@
-use std::path::PathBuf;
-use std::time::Duration;
+use std::time::{Duration, SystemTime};
 
 use anyhow::{Context, Result, bail};
-use clap::Parser;
-use tracing::{info, warn};
+use clap::CommandFactory;
+use tokio_util::sync::CancellationToken;
+use tracing::{Instrument, info, info_span, warn};
 
 use browser_stream::chromium;
-use browser_stream::cli::{AppConfig, CliArgs};
-use browser_stream::encoder::{self, EncoderSettings, FfmpegEncoder};
-use browser_stream::error::RuntimeError;
-use browser_stream::retry::RetryPolicy;
-
-#[derive(Debug, Clone)]
-struct RuntimePaths {
-    ffmpeg: PathBuf,
-    chromium: PathBuf,
-}
+use browser_stream::cli::{CliArgs, CliCommand, parse_ffmpeg_source};
+use browser_stream::encoder;
+use browser_stream::pipeline;
+use browser_stream::sidecar::{
+    self, SidecarManifest, find_in_path, find_sidecar, resolve_ffmpeg_path, sidecar_dir_candidates,
+};
+use browser_stream::startup;
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    let args = CliArgs::parse();
+    startup::process_start();
+    let args = CliArgs::parse_layered();
     init_tracing(args.verbose);
 
-    let config = args.into_config()?;
-    let runtime_paths = resolve_runtime_paths(&config)?;
-    let retry_policy = RetryPolicy::new(
-        config.retries,
-        Duration::from_millis(config.retry_backoff_ms),
-    );
-
-    run_with_retry(&config, &runtime_paths, &retry_policy).await
+    let run_span = info_span!("run", run_id = startup::run_id());
+    let cancel = CancellationToken::new();
+    let result = run(args, cancel.clone()).instrument(run_span).await;
+    if result.is_ok() && cancel.is_cancelled() {
+        // Force process termination in case any background runtime task/thread
+        // holds the process open after graceful shutdown.
+        std::process::exit(0);
+    }
+    result
 }
 
-async fn run_with_retry(
-    config: &AppConfig,
-    runtime_paths: &RuntimePaths,
-    retry_policy: &RetryPolicy,
-) -> Result<()> {
-    let mut failures = 0_u32;
+async fn run(mut args: CliArgs, cancel: CancellationToken) -> Result<()> {
+    match args.command {
+        Some(CliCommand::VerifySidecars) => return verify_sidecars(&args).await,
+        Some(CliCommand::FetchSidecars { ref manifest }) => {
+            let manifest = match manifest {
+                Some(path) => std::fs::read_to_string(path)
+                    .with_context(|| format!("failed to read {}", path.display()))?,
+                None => sidecar::DEFAULT_MANIFEST.to_string(),
+            };
+            let sidecar_dir = sidecar_dir_candidates(args.sidecar_dir.as_deref())?.remove(0);
+            return sidecar::fetch_sidecars(
+                &SidecarManifest::parse(&manifest)?,
+                &sidecar::current_target(),
+                &sidecar_dir,
+            )
+            .await;
+        }
+        Some(CliCommand::Completions { shell }) => {
+            let mut command = CliArgs::command();
+            let name = command.get_name().to_string();
+            clap_complete::generate(shell, &mut command, name, &mut std::io::stdout());
+            return Ok(());
+        }
+        None => {}
+    }
 
-    loop {
-        let attempt = failures + 1;
-        info!(attempt, "starting stream attempt");
+    // Std's stdin buffer is shared with the control listener's reads, so nothing
+    // after the URL line is lost.
+    args.read_url_from(&mut std::io::stdin().lock())?;
+    let config = args.into_config()?;
+    if let Some(nice) = config.nice {
+        apply_nice(nice);
+    }
+    if config.print_config {
+        println!("{}", config.to_redacted_json()?);
+        return Ok(());
+    }
 
-        let result = run_once(config, runtime_paths).await;
+    if config.dry_run {
+        let runtime_paths = pipeline::resolve_ffmpeg_only(&config)?;
+        let settings = pipeline::encoder_settings(&config, &runtime_paths, SystemTime::now(), 1);
+        println!(
+            "{}",
+            encoder::format_command(
+                &settings.ffmpeg_path,
+                &encoder::build_ffmpeg_args(&settings)
+            )
+        );
+        return Ok(());
+    }
 
-        match result {
-            Ok(()) => return Ok(()),
-            Err(err) => {
-                if is_shutdown_error(&err) {
-                    info!("shutdown requested, exiting");
-                    // Force process termination in case any background runtime task/thread
-                    // holds the process open after graceful shutdown.
-                    std::process::exit(0);
-                }
+    if config.list_cdp_targets {
+        let runtime_paths = pipeline::resolve_runtime_paths(&config)?;
+        return chromium::list_cdp_targets(&config, runtime_paths.chromium.as_deref()).await;
+    }
 
-                failures = failures.saturating_add(1);
-                if !retry_policy.should_retry(failures) {
-                    return Err(err.context(format!(
-                        "stream failed after {attempt} attempt(s) with {failures} failure(s)"
-                    )));
-                }
+    if config.verify_only
+        && let Some(path) = config.verify_screenshot.as_deref()
+    {
+        let runtime_paths = pipeline::resolve_runtime_paths(&config)?;
+        return chromium::capture_verify_screenshot(
+            &config,
+            runtime_paths.chromium.as_deref(),
+            path,
+        )
+        .await;
+    }
 
-                warn!(
-                    attempt,
-                    failures,
-                    backoff_ms = retry_policy.backoff.as_millis(),
-                    error = %err,
-                    "stream attempt failed; retrying"
-                );
+    // Registered only now: the subcommands and one-shot modes above keep the
+    // default signal behavior of ending the process at once.
+    let stop = cancel.clone();
+    tokio::spawn(async move {
+        shutdown_signal().await;
+        stop.cancel();
+    });
+    pipeline::run(config, Some(cancel)).await
+}
+
+/// Completes on ctrl-c or, on unix, SIGTERM, the signal supervisors such as
+/// systemd and docker stop a service with.
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{SignalKind, signal};
 
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
                 tokio::select! {
-                    _ = tokio::time::sleep(retry_policy.backoff) => {}
-                    _ = tokio::signal::ctrl_c() => {
-                        info!("shutdown requested during retry backoff, exiting");
-                        return Ok(());
-                    }
+                    _ = tokio::signal::ctrl_c() => {}
+                    _ = terminate.recv() => info!("received SIGTERM"),
                 }
+                return;
             }
+            Err(err) => warn!("failed to listen for SIGTERM: {err}"),
         }
     }
+    let _ = tokio::signal::ctrl_c().await;
 }
 
-async fn run_once(config: &AppConfig, runtime_paths: &RuntimePaths) -> Result<()> {
-    let settings = EncoderSettings {
-        width: config.width,
-        height: config.height,
-        fps: config.fps,
-        bitrate_kbps: config.bitrate_kbps,
-        keyint_sec: config.keyint_sec,
-        x264_opts: config.x264_opts.clone(),
-        output: config.output.clone(),
-        include_silent_audio: !config.no_audio,
-        ffmpeg_path: runtime_paths.ffmpeg.clone(),
-    };
-
-    let mut encoder = FfmpegEncoder::spawn(&settings, config.verbose).await?;
-
-    let stream_result =
-        chromium::stream_browser_to_encoder(config, &runtime_paths.chromium, &mut encoder).await;
-
-    match stream_result {
-        Ok(()) => {
-            let status = encoder.wait_for_exit().await?;
-            if !status.success() {
-                bail!("ffmpeg exited with status {status}");
-            }
-            Ok(())
-        }
-        Err(err) => {
-            encoder.kill_and_wait().await;
-            Err(err)
+/// Sets the scheduling niceness of this process. Linux tracks niceness per thread,
+/// so every thread the runtime has started so far is updated; later threads and
+/// the ffmpeg and Chromium children inherit it.
+#[cfg(unix)]
+fn apply_nice(nice: i32) {
+    #[cfg(target_os = "linux")]
+    let targets: Vec<libc::id_t> = std::fs::read_dir("/proc/self/task")
+        .map(|tasks| {
+            tasks
+                .flatten()
+                .filter_map(|task| task.file_name().to_str()?.parse().ok())
+                .collect()
+        })
+        .unwrap_or_else(|_| vec![0]);
+    // `0` is the calling process.
+    #[cfg(not(target_os = "linux"))]
+    let targets: Vec<libc::id_t> = vec![0];
+
+    for target in targets {
+        // SAFETY: `setpriority` has no memory-safety preconditions.
+        if unsafe { libc::setpriority(libc::PRIO_PROCESS, target, nice) } != 0 {
+            warn!(
+                nice,
+                "failed to apply --nice: {}",
+                std::io::Error::last_os_error()
+            );
+            return;
         }
     }
+    info!(nice, "applied process niceness");
 }
 
-fn resolve_runtime_paths(config: &AppConfig) -> Result<RuntimePaths> {
-    let current_exe =
-        std::env::current_exe().context("failed to determine current executable path")?;
-    let exe_dir = current_exe
-        .parent()
-        .context("failed to determine current executable directory")?;
-
-    let ffmpeg_path = resolve_ffmpeg_path(config.ffmpeg_path.clone(), exe_dir)?;
-
-    let chromium_path = resolve_binary_path(
-        config.chromium_path.clone(),
-        chromium::default_chromium_sidecar_path(exe_dir),
-        "headless_shell",
-    )?;
-
-    Ok(RuntimePaths {
-        ffmpeg: ffmpeg_path,
-        chromium: chromium_path,
-    })
+#[cfg(not(unix))]
+fn apply_nice(nice: i32) {
+    warn!(nice, "--nice is only supported on unix; ignoring");
 }
 
-fn resolve_binary_path(
-    override_path: Option<PathBuf>,
-    default_path: PathBuf,
-    name: &'static str,
-) -> Result<PathBuf> {
-    let candidate = override_path.unwrap_or(default_path);
-
-    if candidate.is_file() {
-        return Ok(candidate);
-    }
-
-    Err(RuntimeError::MissingSidecar {
-        name,
-        path: candidate,
-    }
-    .into())
-}
-
-fn resolve_ffmpeg_path(
-    override_path: Option<PathBuf>,
-    exe_dir: &std::path::Path,
-) -> Result<PathBuf> {
-    if let Some(path) = override_path {
-        return resolve_binary_path(Some(path), PathBuf::new(), "ffmpeg");
-    }
-
-    let sidecar = encoder::default_ffmpeg_sidecar_path(exe_dir);
-    let system = find_in_path(encoder::ffmpeg_executable_name());
-
-    if cfg!(target_os = "macos") {
-        if let Some(system_path) = system {
-            info!(
-                ffmpeg = %system_path.display(),
-                "using system ffmpeg on macOS (preferred over sidecar)"
-            );
-            return Ok(system_path);
+/// `verify-sidecars`: reports whether each sidecar is in place, and the ffmpeg
+/// version, without starting a stream.
+async fn verify_sidecars(args: &CliArgs) -> Result<()> {
+    let sidecar_dirs = sidecar_dir_candidates(args.sidecar_dir.as_deref())?;
+    // The same choice a stream would make, so `--ffmpeg-source` is honored.
+    let ffmpeg = resolve_ffmpeg_path(
+        args.ffmpeg_path.clone(),
+        find_sidecar(&sidecar_dirs, encoder::default_ffmpeg_sidecar_path),
+        find_in_path(encoder::ffmpeg_executable_name()),
+        parse_ffmpeg_source(&args.ffmpeg_source)?,
+    );
+    let chromium = args
+        .chromium_path
+        .clone()
+        .unwrap_or_else(|| find_sidecar(&sidecar_dirs, chromium::default_chromium_sidecar_path));
+
+    let mut failed = Vec::new();
+    match ffmpeg {
+        Ok(ffmpeg) => match ffmpeg_version(&ffmpeg).await {
+            Ok(version) => println!("ffmpeg: {} ({version})", ffmpeg.display()),
+            Err(err) => {
+                println!("ffmpeg: {} FAILED: {err:#}", ffmpeg.display());
+                failed.push("ffmpeg");
+            }
+        },
+        Err(err) => {
+            println!("ffmpeg: FAILED: {err:#}");
+            failed.push("ffmpeg");
         }
     }
-
-    if sidecar.is_file() {
-        return Ok(sidecar);
+    if chromium.is_file() {
+        println!("headless_shell: {}", chromium.display());
+    } else {
+        println!("headless_shell: {} FAILED: not found", chromium.display());
+        failed.push("headless_shell");
     }
 
-    if let Some(system_path) = find_in_path(encoder::ffmpeg_executable_name()) {
-        info!(
-            ffmpeg = %system_path.display(),
-            "using system ffmpeg from PATH"
+    if !failed.is_empty() {
+        bail!(
+            "missing or broken sidecars: {}. Fetch them with `browser-stream fetch-sidecars`",
+            failed.join(", ")
         );
-        return Ok(system_path);
-    }
-
-    Err(RuntimeError::MissingSidecar {
-        name: "ffmpeg",
-        path: sidecar,
     }
-    .into())
+    Ok(())
 }
 
-fn find_in_path(executable_name: &str) -> Option<PathBuf> {
-    let path_var = std::env::var_os("PATH")?;
-    let candidates = std::env::split_paths(&path_var);
-
-    for dir in candidates {
-        let direct = dir.join(executable_name);
-        if direct.is_file() {
-            return Some(direct);
-        }
-
-        if cfg!(target_os = "windows") {
-            let exe = dir.join(format!("{executable_name}.exe"));
-            if exe.is_file() {
-                return Some(exe);
-            }
-        }
+/// First line of `ffmpeg -version`.
+async fn ffmpeg_version(path: &std::path::Path) -> Result<String> {
+    if !path.is_file() {
+        bail!("not found");
     }
-
-    None
-}
-
-fn is_shutdown_error(err: &anyhow::Error) -> bool {
-    err.downcast_ref::<RuntimeError>()
-        .is_some_and(|runtime| matches!(runtime, RuntimeError::ShutdownRequested))
+    let running = tokio::process::Command::new(path)
+        .arg("-version")
+        .kill_on_drop(true)
+        .output();
+    let output = tokio::time::timeout(Duration::from_secs(10), running)
+        .await
+        .context("timed out")?
+        .context("failed to start")?;
+    if !output.status.success() {
+        bail!("exited with status {}", output.status);
+    }
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_string())
 }
 
 fn init_tracing(verbose: bool) {
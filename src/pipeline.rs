@@ -0,0 +1,659 @@
+//! The streaming pipeline behind the CLI: resolving the binaries, then running
+//! stream attempts with retries, fallback video and managed restarts until the
+//! source finishes or the stream is cancelled.
+
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+use anyhow::{Context, Result, bail};
+use tokio_util::sync::CancellationToken;
+use tracing::{Instrument, info, info_span, warn};
+
+use crate::chromium::{self, KeptBrowser};
+use crate::cli::{AppConfig, BackpressurePolicy, auto_bitrate_kbps};
+use crate::encoder::{self, EncoderSettings, FfmpegEncoder};
+use crate::error::RuntimeError;
+use crate::frame::{self, RgbFrame};
+use crate::frame_source;
+use crate::retry::RetryPolicy;
+use crate::rtmp;
+use crate::sidecar::{
+    find_in_path, find_sidecar, resolve_binary_path, resolve_ffmpeg_path, sidecar_dir_candidates,
+};
+use crate::startup;
+
+/// Binaries a stream runs.
+#[derive(Debug, Clone)]
+pub struct RuntimePaths {
+    pub ffmpeg: PathBuf,
+    /// `None` when attaching to a running browser via `--connect-url`, receiving
+    /// frames from `--frame-source` or streaming a `--test-pattern`.
+    pub chromium: Option<PathBuf>,
+}
+
+/// State carried from one stream attempt to the next.
+struct AttemptState {
+    /// With `--persistent-encoder`, ffmpeg survives between attempts.
+    encoder: Option<FfmpegEncoder>,
+    /// With `--reuse-browser` and after managed restarts, the browser survives
+    /// attempts that failed on the encoder side.
+    browser: Option<KeptBrowser>,
+    /// Held on the output until the source produces a frame, and set to the newest
+    /// frame when an attempt stops.
+    last_frame: Option<RgbFrame>,
+    /// Set once the current attempt's source produced a frame.
+    produced_frames: bool,
+    cancel: CancellationToken,
+}
+
+/// Streams `config` the way the CLI does, retrying failed attempts, until the
+/// source finishes, retries run out or `cancel` is cancelled. Cancelling is a
+/// clean stop and returns `Ok`. Without a token the stream only ends on its own.
+///
+/// With `--preflight`, every output is checked for reachability first. A
+/// `--ready-file` written during the run is removed before this returns.
+pub async fn run(config: AppConfig, cancel: Option<CancellationToken>) -> Result<()> {
+    let cancel = cancel.unwrap_or_default();
+    let runtime_paths = resolve_runtime_paths(&config)?;
+
+    if config.preflight {
+        let simulcast = config
+            .renditions
+            .iter()
+            .skip(1)
+            .map(|rendition| &rendition.output);
+        for output in std::iter::once(&config.output).chain(simulcast) {
+            let output = rtmp::expand_output_template(output, SystemTime::now(), 1);
+            rtmp::preflight_output(&output).await?;
+        }
+    }
+
+    let retry_policy = RetryPolicy::new(
+        config.retries,
+        Duration::from_millis(config.retry_backoff_ms),
+    );
+
+    let ready_file = config.ready_file.clone();
+    let result = run_with_retry(config, &runtime_paths, &retry_policy, cancel).await;
+    if let Some(path) = ready_file.as_deref() {
+        startup::clear_ready(path);
+    }
+    result
+}
+
+async fn run_with_retry(
+    mut config: AppConfig,
+    runtime_paths: &RuntimePaths,
+    retry_policy: &RetryPolicy,
+    cancel: CancellationToken,
+) -> Result<()> {
+    let started_at = SystemTime::now();
+    let mut failures = 0_u32;
+    let mut attempt = 0_u32;
+    let mut state = AttemptState {
+        encoder: None,
+        browser: None,
+        last_frame: None,
+        produced_frames: false,
+        cancel,
+    };
+    let mut stall_frame = config
+        .stall_image
+        .as_deref()
+        .map(|path| frame::load_image_frame(path, config.width, config.height))
+        .transpose()?;
+    if let Some(path) = config.fallback_video.as_deref()
+        && !path.is_file()
+    {
+        bail!("fallback video {} does not exist", path.display());
+    }
+    // A file left over from an earlier run must not read as this one being live.
+    if let Some(path) = config.ready_file.as_deref()
+        && let Err(err) = std::fs::remove_file(path)
+        && err.kind() != std::io::ErrorKind::NotFound
+    {
+        warn!(path = %path.display(), "failed to remove stale ready file: {err}");
+    }
+    if let Some(dir) = config.dump_frames.as_deref() {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("failed to create frame dump directory {}", dir.display()))?;
+    }
+    // Each attempt loads the baseline again; a bad file should stop us before the first one.
+    if let Some(path) = config.baseline.as_deref() {
+        frame::load_image_frame(path, config.width, config.height)?;
+    }
+
+    if config.fit_content {
+        // The size is measured once; ffmpeg and every later attempt keep it. The
+        // checks above run first, since a failure from here on must close the browser.
+        let browser =
+            chromium::prelaunch_browser(&config, runtime_paths.chromium.as_deref()).await?;
+        match chromium::fit_viewport_to_content(&browser, &config).await {
+            Ok((width, height)) => {
+                (config.width, config.height) = (width, height);
+                (config.capture_width, config.capture_height) = (width, height);
+                if config.bitrate_auto {
+                    config.bitrate_kbps = auto_bitrate_kbps(width, height, config.fps);
+                    info!(
+                        bitrate_kbps = config.bitrate_kbps,
+                        width, height, "selected bitrate for the measured page size"
+                    );
+                }
+                state.browser = Some(browser);
+            }
+            Err(err) => {
+                browser.close().await;
+                return Err(err.context("--fit-content could not size the page"));
+            }
+        }
+        if let Some(path) = config.stall_image.as_deref() {
+            match frame::load_image_frame(path, config.width, config.height) {
+                Ok(frame) => stall_frame = Some(frame),
+                Err(err) => {
+                    if let Some(browser) = state.browser.take() {
+                        browser.close().await;
+                    }
+                    return Err(err);
+                }
+            }
+        }
+    }
+
+    loop {
+        attempt = attempt.saturating_add(1);
+        info!(attempt, "starting stream attempt");
+
+        // The stall image stands in for the held frame until the source produces a new one.
+        if let Some(stall_frame) = stall_frame.as_ref() {
+            state.last_frame = Some(stall_frame.clone());
+        }
+        state.produced_frames = false;
+        let result = run_once(&config, runtime_paths, started_at, attempt, &mut state)
+            .instrument(info_span!("attempt", attempt))
+            .await;
+
+        match result {
+            Ok(()) => {
+                let Some(restart_interval_sec) = config.restart_interval_sec else {
+                    if let Some(preview_sec) = config.preview_sec {
+                        info!(preview_sec, output = %config.output, "preview recorded");
+                    }
+                    return Ok(());
+                };
+                // A scheduled restart starts a fresh cycle; earlier failures no longer count.
+                failures = 0;
+                info!(
+                    restart_interval_sec,
+                    "capture finished; restarting after interval"
+                );
+                tokio::select! {
+                    _ = tokio::time::sleep(Duration::from_secs(restart_interval_sec)) => {}
+                    () = state.cancel.cancelled() => {
+                        info!("shutdown requested before scheduled restart, exiting");
+                        return Ok(());
+                    }
+                }
+            }
+            Err(err) => {
+                if is_shutdown_error(&err) {
+                    info!("shutdown requested, exiting");
+                    if let Some(mut encoder) = state.encoder.take() {
+                        encoder.kill_and_wait().await;
+                    }
+                    if let Some(browser) = state.browser.take() {
+                        browser.close().await;
+                    }
+                    return Ok(());
+                }
+
+                if let Some(RuntimeError::BrowserMemoryExceeded { rss_mb, limit_mb }) =
+                    err.downcast_ref::<RuntimeError>()
+                    && state.produced_frames
+                {
+                    // Managed restart: does not spend a retry, but still waits out the
+                    // backoff. A page that hits the limit before its first frame counts
+                    // as a failed attempt below, so it cannot restart forever.
+                    warn!(
+                        attempt,
+                        rss_mb,
+                        limit_mb,
+                        backoff_ms = retry_policy.backoff.as_millis(),
+                        "browser memory limit exceeded; restarting browser"
+                    );
+                    if wait_backoff(&config, retry_policy, &mut state, stall_frame.as_ref()).await {
+                        info!("shutdown requested during retry backoff, exiting");
+                        if let Some(mut encoder) = state.encoder.take() {
+                            encoder.kill_and_wait().await;
+                        }
+                        return Ok(());
+                    }
+                    continue;
+                }
+
+                if let Some(RuntimeError::FpsDownshift { from, to }) =
+                    err.downcast_ref::<RuntimeError>()
+                {
+                    // Managed restart: ffmpeg's input rate is fixed at spawn, so relaunch at the lower rate.
+                    warn!(
+                        attempt,
+                        from = %from,
+                        to = %to,
+                        "host cannot keep up; restarting at lower frame rate"
+                    );
+                    config.fps = *to;
+                    if let Some(mut encoder) = state.encoder.take() {
+                        encoder.kill_and_wait().await;
+                    }
+                    continue;
+                }
+
+                if let Some(RuntimeError::BitrateChange { from, to }) =
+                    err.downcast_ref::<RuntimeError>()
+                {
+                    // Managed restart: x264's rate control is fixed at spawn. The last frame
+                    // is held on the new ffmpeg until the page sends another one.
+                    info!(attempt, from, to, "restarting ffmpeg at new bitrate");
+                    config.bitrate_kbps = *to;
+                    if let Some(mut encoder) = state.encoder.take() {
+                        encoder.kill_and_wait().await;
+                    }
+                    continue;
+                }
+
+                if config.once {
+                    if let Some(mut encoder) = state.encoder.take() {
+                        encoder.kill_and_wait().await;
+                    }
+                    if let Some(browser) = state.browser.take() {
+                        browser.close().await;
+                    }
+                    return Err(err);
+                }
+
+                failures = failures.saturating_add(1);
+                if !retry_policy.should_retry(failures) {
+                    if let Some(mut encoder) = state.encoder.take() {
+                        encoder.kill_and_wait().await;
+                    }
+                    if let Some(browser) = state.browser.take() {
+                        browser.close().await;
+                    }
+                    if config.fallback_video.is_some() {
+                        warn!(
+                            attempt,
+                            produced_frames = state.produced_frames,
+                            error = %err,
+                            "retries exhausted; streaming fallback video"
+                        );
+                        attempt = attempt.saturating_add(1);
+                        if run_fallback(&config, runtime_paths, started_at, attempt, &state.cancel)
+                            .await?
+                        {
+                            info!("shutdown requested during fallback video, exiting");
+                            return Ok(());
+                        }
+                        info!("fallback period over; trying the live source again");
+                        failures = 0;
+                        continue;
+                    }
+                    return Err(err.context(format!(
+                        "stream failed after {attempt} attempt(s) ({} retries allowed)",
+                        retry_policy.max_retries
+                    )));
+                }
+
+                if err
+                    .downcast_ref::<RuntimeError>()
+                    .is_some_and(RuntimeError::is_clean_encoder_exit)
+                {
+                    // Status 0 means the ingest hung up gracefully rather than ffmpeg crashing.
+                    info!(
+                        attempt,
+                        max_attempts = retry_policy.max_attempts(),
+                        backoff_ms = retry_policy.backoff.as_millis(),
+                        produced_frames = state.produced_frames,
+                        "ffmpeg closed the output cleanly; reconnecting"
+                    );
+                } else {
+                    warn!(
+                        attempt,
+                        max_attempts = retry_policy.max_attempts(),
+                        backoff_ms = retry_policy.backoff.as_millis(),
+                        produced_frames = state.produced_frames,
+                        error = %err,
+                        "stream attempt failed; retrying"
+                    );
+                }
+
+                if wait_backoff(&config, retry_policy, &mut state, stall_frame.as_ref()).await {
+                    info!("shutdown requested during retry backoff, exiting");
+                    if let Some(mut encoder) = state.encoder.take() {
+                        encoder.kill_and_wait().await;
+                    }
+                    if let Some(browser) = state.browser.take() {
+                        browser.close().await;
+                    }
+                    return Ok(());
+                }
+            }
+        }
+    }
+}
+
+/// Sleeps for the retry backoff, holding `stall_frame` or else the last frame on
+/// a persistent ffmpeg meanwhile. Returns whether shutdown was requested during
+/// the wait.
+async fn wait_backoff(
+    config: &AppConfig,
+    retry_policy: &RetryPolicy,
+    state: &mut AttemptState,
+    stall_frame: Option<&RgbFrame>,
+) -> bool {
+    let cancel = &state.cancel;
+    let backoff = async {
+        tokio::select! {
+            _ = tokio::time::sleep(retry_policy.backoff) => false,
+            () = cancel.cancelled() => true,
+        }
+    };
+    let Some(encoder) = state.encoder.as_mut() else {
+        return backoff.await;
+    };
+    let held_frame = stall_frame.or(state.last_frame.as_ref());
+    match encoder
+        .hold_frame_while(held_frame, config.fps.interval(), backoff)
+        .await
+    {
+        Ok(interrupted) => interrupted,
+        Err(err) => {
+            warn!("persistent ffmpeg stopped during retry backoff: {err:#}");
+            if let Some(mut encoder) = state.encoder.take() {
+                encoder.kill_and_wait().await;
+            }
+            false
+        }
+    }
+}
+
+/// Runs one stream attempt. With `--persistent-encoder`, an encoder left in
+/// `state` by the previous attempt is reused, and a still-running encoder is put
+/// back there when the attempt fails. The browser works the same way for
+/// `--reuse-browser`.
+async fn run_once(
+    config: &AppConfig,
+    runtime_paths: &RuntimePaths,
+    started_at: SystemTime,
+    attempt: u32,
+    state: &mut AttemptState,
+) -> Result<()> {
+    if let Some(encoder) = state.encoder.take() {
+        info!("reusing running ffmpeg; holding last frame while the source restarts");
+        return stream_with_encoder(config, runtime_paths, encoder, state).await;
+    }
+
+    let settings = encoder_settings(config, runtime_paths, started_at, attempt);
+    if let Some(hls_dir) = settings.hls_dir.as_deref() {
+        std::fs::create_dir_all(hls_dir)
+            .with_context(|| format!("failed to create HLS directory {}", hls_dir.display()))?;
+    }
+    let prelaunched = config.warm_start && state.browser.is_none();
+    if prelaunched {
+        state.browser =
+            Some(chromium::prelaunch_browser(config, runtime_paths.chromium.as_deref()).await?);
+    }
+    let encoder = match FfmpegEncoder::spawn(&settings, config.verbose).await {
+        Ok(encoder) => encoder,
+        Err(err) => {
+            if prelaunched && let Some(browser) = state.browser.take() {
+                browser.close().await;
+            }
+            return Err(err);
+        }
+    };
+    stream_with_encoder(config, runtime_paths, encoder, state).await
+}
+
+/// Loops `--fallback-video` to the output for `--fallback-restore-sec`, or until
+/// ffmpeg exits. Returns `true` when shutdown was requested meanwhile.
+async fn run_fallback(
+    config: &AppConfig,
+    runtime_paths: &RuntimePaths,
+    started_at: SystemTime,
+    attempt: u32,
+    cancel: &CancellationToken,
+) -> Result<bool> {
+    let settings = EncoderSettings {
+        loop_video: config.fallback_video.clone(),
+        // The archive is of the live page only.
+        record: None,
+        ..encoder_settings(config, runtime_paths, started_at, attempt)
+    };
+    let mut encoder = FfmpegEncoder::spawn(&settings, config.verbose).await?;
+
+    let restore = tokio::time::sleep(Duration::from_secs(config.fallback_restore_sec));
+    tokio::pin!(restore);
+    let mut exit_poll = tokio::time::interval(Duration::from_secs(1));
+    let interrupted = loop {
+        tokio::select! {
+            _ = &mut restore => break false,
+            () = cancel.cancelled() => break true,
+            _ = exit_poll.tick() => {
+                if let Some(status) = encoder.try_wait()? {
+                    warn!(%status, "fallback ffmpeg exited early");
+                    break false;
+                }
+            }
+        }
+    };
+
+    encoder.kill_and_wait().await;
+    Ok(interrupted)
+}
+
+/// ffmpeg settings for one attempt. File outputs get the attempt number in
+/// their name, see [`rtmp::attempt_output_path`].
+pub fn encoder_settings(
+    config: &AppConfig,
+    runtime_paths: &RuntimePaths,
+    started_at: SystemTime,
+    attempt: u32,
+) -> EncoderSettings {
+    // File recordings get a per-attempt path so a retry keeps the previous partial.
+    let attempt_path = |path: &str| {
+        rtmp::expand_output_template(
+            &rtmp::attempt_output_path(path, attempt),
+            started_at,
+            attempt,
+        )
+    };
+    EncoderSettings {
+        width: config.width,
+        height: config.height,
+        fps: config.fps,
+        bitrate_kbps: config.bitrate_kbps,
+        keyint_sec: config.keyint_sec,
+        x264_opts: config.x264_opts.clone(),
+        tune: config.tune.clone(),
+        idr_on_scene_cut: config.keyframe_on_refresh,
+        force_keyframe_sec: config.force_keyframe_sec,
+        pix_fmt: config.output_pix_fmt.clone(),
+        color_range: config.color_range,
+        video_filter: config.video_filter.clone(),
+        output: attempt_path(&config.output),
+        renditions: config
+            .renditions
+            .iter()
+            .cloned()
+            .map(|mut rendition| {
+                rendition.output = attempt_path(&rendition.output);
+                rendition
+            })
+            .collect(),
+        segment_time_sec: config.segment_time_sec,
+        null_output: config.output_to_null,
+        hls_dir: config.hls_dir.clone(),
+        rtmp_live: config.rtmp_live,
+        flush_packets: config.flush_packets,
+        tls_verify: config.tls_verify,
+        ca_file: config.ca_file.clone(),
+        record: config.record.clone().map(|mut record| {
+            record.path = attempt_path(&record.path);
+            record
+        }),
+        include_silent_audio: !config.no_audio,
+        audio_filter: config.audio_filter.clone(),
+        stream_title: config.stream_title.clone(),
+        stream_description: config.stream_description.clone(),
+        loop_video: None,
+        ffmpeg_path: runtime_paths.ffmpeg.clone(),
+        init_timeout: Duration::from_millis(config.encoder_init_timeout_ms),
+        drop_after: (config.on_backpressure == BackpressurePolicy::Drop)
+            .then(|| Duration::from_millis(config.backpressure_threshold_ms)),
+    }
+}
+
+async fn stream_with_encoder(
+    config: &AppConfig,
+    runtime_paths: &RuntimePaths,
+    mut encoder: FfmpegEncoder,
+    state: &mut AttemptState,
+) -> Result<()> {
+    let AttemptState {
+        browser: browser_slot,
+        last_frame,
+        produced_frames,
+        cancel,
+        ..
+    } = state;
+    let stream_result = match (config.test_pattern, config.frame_source.as_deref()) {
+        (Some(pattern), _) => {
+            frame_source::stream_test_pattern_to_encoder(
+                config,
+                pattern,
+                &mut encoder,
+                last_frame,
+                produced_frames,
+                cancel,
+            )
+            .await
+        }
+        (None, Some(url)) => {
+            frame_source::stream_ws_to_encoder(
+                config,
+                url,
+                &mut encoder,
+                last_frame,
+                produced_frames,
+                cancel,
+            )
+            .await
+        }
+        (None, None) if config.screenshot_mode => {
+            chromium::stream_screenshots_to_encoder(
+                config,
+                runtime_paths.chromium.as_deref(),
+                &mut encoder,
+                last_frame,
+                produced_frames,
+                cancel,
+            )
+            .await
+        }
+        (None, None) if !config.grid.is_empty() => {
+            chromium::stream_grid_to_encoder(
+                config,
+                runtime_paths.chromium.as_deref(),
+                &mut encoder,
+                last_frame,
+                produced_frames,
+                cancel,
+            )
+            .await
+        }
+        (None, None) => {
+            chromium::stream_browser_to_encoder(
+                config,
+                runtime_paths.chromium.as_deref(),
+                &mut encoder,
+                last_frame,
+                produced_frames,
+                browser_slot,
+                cancel,
+            )
+            .await
+        }
+    };
+
+    match stream_result {
+        Ok(()) => {
+            let status = encoder.wait_for_exit().await?;
+            if !status.success() {
+                bail!("ffmpeg exited with status {status}");
+            }
+            Ok(())
+        }
+        Err(err) => {
+            let keep_encoder = config.persistent_encoder
+                && !is_shutdown_error(&err)
+                && !matches!(
+                    err.downcast_ref::<RuntimeError>(),
+                    Some(RuntimeError::FpsDownshift { .. } | RuntimeError::BitrateChange { .. })
+                )
+                && matches!(encoder.try_wait(), Ok(None));
+            if keep_encoder {
+                state.encoder = Some(encoder);
+            } else {
+                encoder.kill_and_wait().await;
+            }
+            Err(err)
+        }
+    }
+}
+
+/// Finds ffmpeg and, unless the config needs no local browser, Chromium, from
+/// the explicit paths, the sidecar directories and `PATH`.
+pub fn resolve_runtime_paths(config: &AppConfig) -> Result<RuntimePaths> {
+    let sidecar_dirs = sidecar_dir_candidates(config.sidecar_dir.as_deref())?;
+    let ffmpeg_path = resolve_ffmpeg(config, &sidecar_dirs)?;
+
+    let chromium_path = if config.connect_url.is_some()
+        || config.frame_source.is_some()
+        || config.test_pattern.is_some()
+    {
+        None
+    } else {
+        Some(resolve_binary_path(
+            config.chromium_path.clone(),
+            find_sidecar(&sidecar_dirs, chromium::default_chromium_sidecar_path),
+            "headless_shell",
+        )?)
+    };
+
+    Ok(RuntimePaths {
+        ffmpeg: ffmpeg_path,
+        chromium: chromium_path,
+    })
+}
+
+/// Finds ffmpeg alone, for modes such as `--dry-run` that never start a browser.
+pub fn resolve_ffmpeg_only(config: &AppConfig) -> Result<RuntimePaths> {
+    let sidecar_dirs = sidecar_dir_candidates(config.sidecar_dir.as_deref())?;
+    Ok(RuntimePaths {
+        ffmpeg: resolve_ffmpeg(config, &sidecar_dirs)?,
+        chromium: None,
+    })
+}
+
+fn resolve_ffmpeg(config: &AppConfig, sidecar_dirs: &[PathBuf]) -> Result<PathBuf> {
+    resolve_ffmpeg_path(
+        config.ffmpeg_path.clone(),
+        find_sidecar(sidecar_dirs, encoder::default_ffmpeg_sidecar_path),
+        find_in_path(encoder::ffmpeg_executable_name()),
+        config.ffmpeg_source,
+    )
+}
+
+fn is_shutdown_error(err: &anyhow::Error) -> bool {
+    err.downcast_ref::<RuntimeError>()
+        .is_some_and(|runtime| matches!(runtime, RuntimeError::ShutdownRequested))
+}
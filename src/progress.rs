@@ -0,0 +1,62 @@
+/// One snapshot of ffmpeg's `-progress` output. ffmpeg emits a block of `key=value`
+/// lines every stats period, terminated by `progress=continue` (or `progress=end`).
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FfmpegProgress {
+    pub frame: u64,
+    pub fps: f64,
+    pub bitrate_kbps: Option<f64>,
+    pub out_time_us: i64,
+    pub drop_frames: u64,
+    pub dup_frames: u64,
+    pub ended: bool,
+}
+
+/// Accumulates `-progress` lines into complete [`FfmpegProgress`] snapshots.
+#[derive(Debug, Default)]
+pub struct ProgressParser {
+    current: FfmpegProgress,
+}
+
+impl ProgressParser {
+    /// Feeds a single line. Returns the finished snapshot when the line closes a block.
+    /// Unknown keys and unparseable values are ignored so new ffmpeg versions keep working.
+    pub fn push_line(&mut self, line: &str) -> Option<FfmpegProgress> {
+        let (key, value) = line.trim().split_once('=')?;
+        let value = value.trim();
+
+        match key.trim() {
+            "frame" => set_parsed(&mut self.current.frame, value),
+            "fps" => set_parsed(&mut self.current.fps, value),
+            "bitrate" => self.current.bitrate_kbps = parse_bitrate_kbps(value),
+            "out_time_us" => set_parsed(&mut self.current.out_time_us, value),
+            "drop_frames" => set_parsed(&mut self.current.drop_frames, value),
+            "dup_frames" => set_parsed(&mut self.current.dup_frames, value),
+            "progress" => {
+                self.current.ended = value == "end";
+                return Some(std::mem::take(&mut self.current));
+            }
+            _ => {}
+        }
+
+        None
+    }
+}
+
+fn set_parsed<T: std::str::FromStr>(target: &mut T, value: &str) {
+    if let Ok(parsed) = value.parse() {
+        *target = parsed;
+    }
+}
+
+/// Parses ffmpeg's bitrate notation (`4410.3kbits/s`) into kbit/s. `N/A` yields `None`.
+fn parse_bitrate_kbps(value: &str) -> Option<f64> {
+    let (number, unit) = value.split_at(value.find(|c: char| c.is_ascii_alphabetic())?);
+    let number: f64 = number.trim().parse().ok()?;
+
+    match unit {
+        "kbits/s" => Some(number),
+        "Mbits/s" => Some(number * 1000.0),
+        "bits/s" => Some(number / 1000.0),
+        _ => None,
+    }
+}
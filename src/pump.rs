@@ -0,0 +1,224 @@
+use std::pin::Pin;
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use futures::{Stream, StreamExt};
+use tokio::time::MissedTickBehavior;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info, warn};
+
+use crate::cli::AppConfig;
+use crate::encoder::FfmpegEncoder;
+use crate::error::RuntimeError;
+use crate::frame::RgbFrame;
+use crate::startup::{self, StartupTimeline};
+
+/// One item from a frame source: `Ok` with the result of decoding one frame, or
+/// an `Err` that ends the stream attempt, such as the source disconnecting.
+pub type SourceItem = Result<Result<RgbFrame>>;
+
+/// Applies the checks every frame source shares to `frames`: the first frame must
+/// arrive within `--frame-timeout-ms`, and `--max-decode-errors` undecodable
+/// frames in a row fail the stream. Fewer undecodable frames are logged and
+/// skipped, and so are the first `--skip-frames` decoded ones, which do not count
+/// as the first frame. The stream ends after its first error, or when `frames`
+/// ends.
+pub fn checked_frames<S>(frames: S, config: &AppConfig) -> impl Stream<Item = Result<RgbFrame>>
+where
+    S: Stream<Item = SourceItem>,
+{
+    let checker = FrameChecker {
+        frames: Box::pin(frames),
+        first_frame_deadline: tokio::time::Instant::now()
+            + Duration::from_millis(config.frame_timeout_ms),
+        max_decode_errors: config.max_decode_errors,
+        skip_frames: config.skip_frames,
+        skipped_frames: 0,
+        delivered_frames: 0,
+        decode_errors: 0,
+        consecutive_decode_errors: 0,
+    };
+    futures::stream::unfold(Some(checker), |checker| async move {
+        let mut checker = checker?;
+        match checker.next_frame().await {
+            Some(Ok(frame)) => Some((Ok(frame), Some(checker))),
+            Some(Err(err)) => Some((Err(err), None)),
+            None => None,
+        }
+    })
+}
+
+struct FrameChecker<S> {
+    frames: Pin<Box<S>>,
+    first_frame_deadline: tokio::time::Instant,
+    max_decode_errors: u32,
+    skip_frames: u32,
+    skipped_frames: u32,
+    delivered_frames: u64,
+    decode_errors: u64,
+    consecutive_decode_errors: u32,
+}
+
+impl<S: Stream<Item = SourceItem>> FrameChecker<S> {
+    async fn next_frame(&mut self) -> Option<Result<RgbFrame>> {
+        loop {
+            let item = if self.delivered_frames == 0 {
+                match tokio::time::timeout_at(self.first_frame_deadline, self.frames.next()).await {
+                    Ok(item) => item,
+                    Err(_) => return Some(Err(RuntimeError::ScreencastTimeout.into())),
+                }
+            } else {
+                self.frames.next().await
+            };
+            let decoded = match item? {
+                Ok(decoded) => decoded,
+                Err(err) => return Some(Err(err)),
+            };
+
+            match decoded {
+                Ok(_) if self.skipped_frames < self.skip_frames => {
+                    // Junk frames are dropped before they can prime ffmpeg.
+                    self.consecutive_decode_errors = 0;
+                    self.skipped_frames += 1;
+                    debug!(
+                        skipped_frames = self.skipped_frames,
+                        skip_frames = self.skip_frames,
+                        "skipping initial frame"
+                    );
+                }
+                Ok(frame) => {
+                    self.consecutive_decode_errors = 0;
+                    self.delivered_frames = self.delivered_frames.saturating_add(1);
+                    return Some(Ok(frame));
+                }
+                Err(err) => {
+                    self.decode_errors = self.decode_errors.saturating_add(1);
+                    self.consecutive_decode_errors =
+                        self.consecutive_decode_errors.saturating_add(1);
+                    let consecutive_decode_errors = self.consecutive_decode_errors;
+                    if consecutive_decode_errors >= self.max_decode_errors {
+                        return Some(Err(err.context(format!(
+                            "failed to decode {consecutive_decode_errors} consecutive frames"
+                        ))));
+                    }
+                    warn!(
+                        decode_errors = self.decode_errors,
+                        consecutive_decode_errors, "skipping undecodable frame: {err:#}"
+                    );
+                }
+            }
+        }
+    }
+}
+
+/// Feeds the frames from `frames` to `encoder` at the output rate, after the
+/// [`checked_frames`] checks. The newest frame is repeated on every tick until the
+/// next one replaces it, and `last_frame` fills the ticks before the first one,
+/// which also goes through [`write_first_frame`]. The single-page screencast runs
+/// its own loop around the same checks to add runtime controls.
+///
+/// Ends with `Ok` at `--max-duration-sec`, with
+/// [`RuntimeError::ShutdownRequested`] once `cancel` fires, and with an error when
+/// the source fails or ends. Afterwards `last_frame` holds the newest frame and
+/// `produced_frames` is set if the source produced any.
+pub async fn pump_frames<S>(
+    config: &AppConfig,
+    frames: S,
+    encoder: &mut FfmpegEncoder,
+    last_frame: &mut Option<RgbFrame>,
+    produced_frames: &mut bool,
+    mut timeline: Option<&mut StartupTimeline>,
+    cancel: &CancellationToken,
+) -> Result<()>
+where
+    S: Stream<Item = SourceItem>,
+{
+    let frames = checked_frames(frames, config);
+    tokio::pin!(frames);
+
+    let mut frame_tick = tokio::time::interval(config.fps.interval());
+    frame_tick.set_missed_tick_behavior(MissedTickBehavior::Skip);
+    frame_tick.tick().await;
+    let mut stats_tick = tokio::time::interval(Duration::from_secs(5));
+    stats_tick.set_missed_tick_behavior(MissedTickBehavior::Skip);
+    stats_tick.tick().await;
+    let duration_limit = tokio::time::sleep(config.max_duration().unwrap_or(Duration::MAX));
+    tokio::pin!(duration_limit);
+    let mut latest_frame: Option<RgbFrame> = None;
+    let mut decoded_frames: u64 = 0;
+    let mut encoded_frames: u64 = 0;
+
+    let stream_result: Result<()> = async {
+        loop {
+            tokio::select! {
+                biased;
+                _ = frame_tick.tick() => {
+                    if let Some(frame) = latest_frame.as_ref().or(last_frame.as_ref()) {
+                        encoder.write_frame(frame).await?;
+                        encoded_frames = encoded_frames.saturating_add(1);
+                    }
+                }
+                frame = frames.next() => {
+                    let frame = frame.context("frame source ended unexpectedly")??;
+                    if latest_frame.is_none() {
+                        write_first_frame(config, encoder, &frame, timeline.as_deref_mut()).await?;
+                        encoded_frames = encoded_frames.saturating_add(1);
+                    }
+                    decoded_frames = decoded_frames.saturating_add(1);
+                    latest_frame = Some(frame);
+                }
+                _ = stats_tick.tick() => {
+                    let progress = encoder.progress().unwrap_or_default();
+                    debug!(
+                        decoded_frames,
+                        encoded_frames,
+                        ffmpeg_frame = progress.frame,
+                        ffmpeg_fps = progress.fps,
+                        output_bitrate_kbps = progress.bitrate_kbps,
+                        output_dropped_frames = encoder.dropped_frames(),
+                        has_frame = latest_frame.is_some(),
+                        "streaming stats"
+                    );
+                }
+                () = cancel.cancelled() => {
+                    return Err(RuntimeError::ShutdownRequested.into());
+                }
+                _ = &mut duration_limit, if config.max_duration_sec.is_some() => {
+                    info!(max_duration_sec = config.max_duration_sec, "max duration reached; stopping");
+                    return Ok(());
+                }
+            }
+        }
+    }
+    .await;
+
+    if latest_frame.is_some() {
+        *last_frame = latest_frame;
+        *produced_frames = true;
+    }
+
+    stream_result
+}
+
+/// Writes a source's first frame to `encoder` right away, so ffmpeg can initialize
+/// its output without waiting for the next tick, then completes `timeline` and
+/// marks the stream ready.
+pub async fn write_first_frame(
+    config: &AppConfig,
+    encoder: &mut FfmpegEncoder,
+    frame: &RgbFrame,
+    timeline: Option<&mut StartupTimeline>,
+) -> Result<()> {
+    info!("received first frame");
+    let first_frame_at = Instant::now();
+    encoder.write_frame(frame).await?;
+    if let Some(timeline) = timeline {
+        timeline.first_screencast_frame(first_frame_at);
+        timeline.first_encoded_frame(Instant::now());
+        startup::log_startup_report(timeline);
+    }
+    if let Some(path) = config.ready_file.as_deref() {
+        startup::mark_ready(path);
+    }
+    Ok(())
+}
@@ -1,5 +1,10 @@
 use std::time::Duration;
 
+/// Retry policy for whole stream attempts.
+///
+/// `max_retries` counts retries *after* the first attempt, so a policy with
+/// `max_retries = 5` makes at most 6 attempts in total, and `max_retries = 0`
+/// makes exactly one.
 #[derive(Debug, Clone)]
 pub struct RetryPolicy {
     pub max_retries: u32,
@@ -14,6 +19,14 @@ impl RetryPolicy {
         }
     }
 
+    /// Total number of attempts this policy allows, including the first one.
+    pub fn max_attempts(&self) -> u32 {
+        self.max_retries.saturating_add(1)
+    }
+
+    /// Returns whether another attempt should follow after `failures_so_far` failed
+    /// attempts. The n-th failure is followed by retry number n, so this holds while
+    /// `failures_so_far <= max_retries`.
     pub fn should_retry(&self, failures_so_far: u32) -> bool {
         failures_so_far <= self.max_retries
     }
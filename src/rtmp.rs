@@ -1,6 +1,12 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
 use thiserror::Error;
+use tokio::net::TcpStream;
+use tracing::info;
 use url::Url;
 
+use crate::error::RuntimeError;
+
 #[derive(Debug, Error, PartialEq)]
 pub enum RtmpError {
     #[error("provide either `--output` or both `--rtmp-url` and `--stream-key`")]
@@ -20,7 +26,7 @@ pub fn build_output(
 ) -> Result<String, RtmpError> {
     if let Some(full_output) = output {
         let trimmed = full_output.trim();
-        validate_output_url(trimmed)?;
+        validate_output_template(trimmed)?;
         return Ok(trimmed.to_string());
     }
 
@@ -28,7 +34,7 @@ pub fn build_output(
         (Some(base), Some(key)) => {
             let normalized_key = normalize_stream_key(&key)?;
             let merged = format!("{}/{}", base.trim_end_matches('/'), normalized_key);
-            validate_output_url(&merged)?;
+            validate_output_template(&merged)?;
             Ok(merged)
         }
         _ => Err(RtmpError::MissingDestination),
@@ -52,3 +58,147 @@ fn validate_output_url(candidate: &str) -> Result<(), RtmpError> {
         other => Err(RtmpError::InvalidScheme(other.to_string())),
     }
 }
+
+/// Host and port ffmpeg will connect to for an RTMP output, or `None` for file outputs.
+/// The port defaults to 1935 for `rtmp` and 443 for `rtmps`.
+pub fn output_endpoint(output: &str) -> Option<(String, u16)> {
+    let parsed = Url::parse(output).ok()?;
+    let default_port = match parsed.scheme() {
+        "rtmp" => 1935,
+        "rtmps" => 443,
+        _ => return None,
+    };
+    let host = parsed.host_str()?.to_string();
+    Some((host, parsed.port().unwrap_or(default_port)))
+}
+
+/// Resolves the output host and opens a TCP connection to it, so a typo'd ingest
+/// host fails immediately instead of after a browser launch.
+pub async fn preflight_output(output: &str) -> Result<(), RuntimeError> {
+    let Some((host, port)) = output_endpoint(output) else {
+        return Ok(());
+    };
+    let unreachable = |reason: String| RuntimeError::OutputUnreachable {
+        host: format!("{host}:{port}"),
+        reason,
+    };
+
+    let addrs: Vec<_> = tokio::net::lookup_host((host.as_str(), port))
+        .await
+        .map_err(|err| unreachable(format!("DNS lookup failed: {err}")))?
+        .collect();
+    let addr = addrs
+        .first()
+        .ok_or_else(|| unreachable("DNS lookup returned no addresses".to_string()))?;
+
+    match tokio::time::timeout(Duration::from_secs(5), TcpStream::connect(addr)).await {
+        Ok(Ok(_)) => {
+            info!(host, port, %addr, "output host is reachable");
+            Ok(())
+        }
+        Ok(Err(err)) => Err(unreachable(format!("TCP connect to {addr} failed: {err}"))),
+        Err(_) => Err(unreachable(format!("TCP connect to {addr} timed out"))),
+    }
+}
+
+/// Expands `{date}` (`YYYY-MM-DD`), `{time}` (`HHMMSS`) and `{n}` (attempt number)
+/// placeholders in an output URL. Dates and times are UTC.
+pub fn expand_output_template(template: &str, started_at: SystemTime, attempt: u32) -> String {
+    if !template.contains('{') {
+        return template.to_string();
+    }
+
+    let secs = started_at
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or(0);
+    let (year, month, day) = civil_from_days((secs / 86_400) as i64);
+    let second_of_day = secs % 86_400;
+
+    template
+        .replace("{date}", &format!("{year:04}-{month:02}-{day:02}"))
+        .replace(
+            "{time}",
+            &format!(
+                "{:02}{:02}{:02}",
+                second_of_day / 3600,
+                second_of_day % 3600 / 60,
+                second_of_day % 60
+            ),
+        )
+        .replace("{n}", &attempt.to_string())
+}
+
+/// Returns whether `output` is an `rtmp://` or `rtmps://` URL.
+pub fn is_rtmp_output(output: &str) -> bool {
+    output.split_once("://").is_some_and(|(scheme, _)| {
+        scheme.eq_ignore_ascii_case("rtmp") || scheme.eq_ignore_ascii_case("rtmps")
+    })
+}
+
+/// Returns whether `output` is sent over TLS, which is what `--tls-verify` and
+/// `--ca-file` configure.
+pub fn is_secure_output(output: &str) -> bool {
+    output
+        .get(..8)
+        .is_some_and(|scheme| scheme.eq_ignore_ascii_case("rtmps://"))
+}
+
+/// Replaces the stream key (the last path segment of a network output such as
+/// `rtmp://host/app/key`) with `<redacted>` for display. File outputs and URLs
+/// without an app segment are returned unchanged.
+pub fn redact_output(output: &str) -> String {
+    let Some((scheme, rest)) = output.split_once("://") else {
+        return output.to_string();
+    };
+    let Some((authority, path)) = rest.split_once('/') else {
+        return output.to_string();
+    };
+    match path.trim_end_matches('/').rsplit_once('/') {
+        Some((app, key)) if !app.is_empty() && !key.is_empty() => {
+            format!("{scheme}://{authority}/{app}/<redacted>")
+        }
+        _ => output.to_string(),
+    }
+}
+
+/// Gives each retry of a file recording its own path by inserting `.attemptN`
+/// before the extension from the second attempt on, so the partial recording of a
+/// failed attempt is kept. Network outputs, `-` and templates using `{n}` are
+/// returned unchanged.
+pub fn attempt_output_path(output: &str, attempt: u32) -> String {
+    if attempt <= 1
+        || output == "-"
+        || output.contains("{n}")
+        || !crate::encoder::is_file_output(output)
+    {
+        return output.to_string();
+    }
+
+    let name_start = output.rfind(['/', '\\']).map_or(0, |index| index + 1);
+    match output[name_start..].rfind('.') {
+        Some(dot) if dot > 0 => {
+            let dot = name_start + dot;
+            format!("{}.attempt{attempt}{}", &output[..dot], &output[dot..])
+        }
+        _ => format!("{output}.attempt{attempt}"),
+    }
+}
+
+fn validate_output_template(template: &str) -> Result<(), RtmpError> {
+    validate_output_url(&expand_output_template(template, SystemTime::now(), 1))
+}
+
+/// Converts days since the Unix epoch to a `(year, month, day)` civil date.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
@@ -0,0 +1,445 @@
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result, bail};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use tokio::io::AsyncWriteExt;
+use tracing::info;
+
+use crate::chromium::default_chromium_sidecar_path;
+use crate::cli::FfmpegSource;
+use crate::encoder::default_ffmpeg_sidecar_path;
+use crate::error::RuntimeError;
+
+/// Sidecar directories to search, in order: `--sidecar-dir` alone when given,
+/// otherwise `../sidecar` relative to each of [`exe_dir_candidates`].
+pub fn sidecar_dir_candidates(sidecar_dir: Option<&Path>) -> Result<Vec<PathBuf>> {
+    if let Some(dir) = sidecar_dir {
+        return Ok(vec![dir.to_path_buf()]);
+    }
+    Ok(exe_dir_candidates()?
+        .into_iter()
+        .map(|exe_dir| exe_dir.join("..").join("sidecar"))
+        .collect())
+}
+
+/// Directories holding the running executable, as [`exe_dirs`] orders them.
+/// The invoked path is `argv[0]`, looked up on `PATH` when it is a bare name.
+pub fn exe_dir_candidates() -> Result<Vec<PathBuf>> {
+    let current_exe =
+        std::env::current_exe().context("failed to determine current executable path")?;
+    let invoked = std::env::args_os()
+        .next()
+        .map(PathBuf::from)
+        .and_then(|argv0| {
+            if argv0.components().count() > 1 {
+                Some(argv0)
+            } else {
+                find_in_path(&argv0.to_string_lossy())
+            }
+        });
+    let canonical = std::fs::canonicalize(&current_exe).ok();
+    exe_dirs(&current_exe, canonical.as_deref(), invoked.as_deref())
+}
+
+/// Directories of `current_exe`, its `canonical` path and the `invoked` path,
+/// in that order and without repeats. Depending on the platform `current_exe`
+/// is either the symlink a package manager installed or its target, so both are
+/// tried.
+pub fn exe_dirs(
+    current_exe: &Path,
+    canonical: Option<&Path>,
+    invoked: Option<&Path>,
+) -> Result<Vec<PathBuf>> {
+    let mut dirs = vec![
+        current_exe
+            .parent()
+            .map(PathBuf::from)
+            .context("failed to determine current executable directory")?,
+    ];
+    for dir in [canonical, invoked]
+        .into_iter()
+        .flatten()
+        .filter_map(Path::parent)
+    {
+        if !dir.as_os_str().is_empty() && !dirs.iter().any(|known| known == dir) {
+            dirs.push(dir.to_path_buf());
+        }
+    }
+    Ok(dirs)
+}
+
+/// The sidecar at `default_path` under the first of `dirs` that has it, or under
+/// the first directory when none does, so errors name the usual location.
+pub fn find_sidecar(dirs: &[PathBuf], default_path: fn(&Path) -> PathBuf) -> PathBuf {
+    dirs.iter()
+        .map(|dir| default_path(dir))
+        .find(|path| path.is_file())
+        .unwrap_or_else(|| default_path(&dirs[0]))
+}
+
+/// `override_path` when given, otherwise `default_path`, as long as the file exists.
+pub fn resolve_binary_path(
+    override_path: Option<PathBuf>,
+    default_path: PathBuf,
+    name: &'static str,
+) -> Result<PathBuf> {
+    let candidate = override_path.unwrap_or(default_path);
+
+    if candidate.is_file() {
+        return Ok(candidate);
+    }
+
+    Err(RuntimeError::MissingSidecar {
+        name,
+        path: candidate,
+    }
+    .into())
+}
+
+/// Picks the ffmpeg binary: `--ffmpeg-path` when given, otherwise `sidecar` or the
+/// `system` ffmpeg found on `PATH`, as `source` allows. With `auto`, the sidecar
+/// wins except on macOS, and the system ffmpeg is the fallback.
+pub fn resolve_ffmpeg_path(
+    override_path: Option<PathBuf>,
+    sidecar: PathBuf,
+    system: Option<PathBuf>,
+    source: FfmpegSource,
+) -> Result<PathBuf> {
+    if let Some(path) = override_path {
+        return resolve_binary_path(Some(path), PathBuf::new(), "ffmpeg");
+    }
+
+    match source {
+        FfmpegSource::Auto => {}
+        FfmpegSource::Sidecar => return resolve_binary_path(None, sidecar, "ffmpeg"),
+        FfmpegSource::System => {
+            return system.context("`--ffmpeg-source system` was given but ffmpeg is not on PATH");
+        }
+    }
+
+    if cfg!(target_os = "macos")
+        && let Some(system_path) = system.clone()
+    {
+        info!(
+            ffmpeg = %system_path.display(),
+            "using system ffmpeg on macOS (preferred over sidecar)"
+        );
+        return Ok(system_path);
+    }
+
+    if sidecar.is_file() {
+        return Ok(sidecar);
+    }
+
+    if let Some(system_path) = system {
+        info!(
+            ffmpeg = %system_path.display(),
+            "using system ffmpeg from PATH"
+        );
+        return Ok(system_path);
+    }
+
+    Err(RuntimeError::MissingSidecar {
+        name: "ffmpeg",
+        path: sidecar,
+    }
+    .into())
+}
+
+pub fn find_in_path(executable_name: &str) -> Option<PathBuf> {
+    let path_var = std::env::var_os("PATH")?;
+    let candidates = std::env::split_paths(&path_var);
+
+    for dir in candidates {
+        let direct = dir.join(executable_name);
+        if direct.is_file() {
+            return Some(direct);
+        }
+
+        if cfg!(target_os = "windows") {
+            let exe = dir.join(format!("{executable_name}.exe"));
+            if exe.is_file() {
+                return Some(exe);
+            }
+        }
+    }
+
+    None
+}
+
+/// The manifest built into the binary; see `sidecars.toml`.
+pub const DEFAULT_MANIFEST: &str = include_str!("../sidecars.toml");
+
+/// Pinned sidecar downloads for `fetch-sidecars`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct SidecarManifest {
+    #[serde(rename = "sidecar")]
+    pub sidecars: Vec<SidecarDownload>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct SidecarDownload {
+    pub name: SidecarKind,
+    /// `<os>-<arch>` as in [`current_target`].
+    pub target: String,
+    /// A zip archive.
+    pub url: String,
+    /// Hex SHA-256 of the archive. Empty means not pinned yet.
+    pub sha256: String,
+    /// File name of the executable inside the archive.
+    pub executable: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SidecarKind {
+    Ffmpeg,
+    HeadlessShell,
+}
+
+impl SidecarKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            SidecarKind::Ffmpeg => "ffmpeg",
+            SidecarKind::HeadlessShell => "headless_shell",
+        }
+    }
+
+    /// Where the executable is installed under `sidecar_dir`, the same path the
+    /// binary looks it up at.
+    pub fn install_path(self, sidecar_dir: &Path) -> PathBuf {
+        match self {
+            SidecarKind::Ffmpeg => default_ffmpeg_sidecar_path(sidecar_dir),
+            SidecarKind::HeadlessShell => default_chromium_sidecar_path(sidecar_dir),
+        }
+    }
+}
+
+impl SidecarManifest {
+    pub fn parse(text: &str) -> Result<Self> {
+        toml::from_str(text).context("invalid sidecar manifest")
+    }
+
+    /// Downloads for `target`, or an error naming the targets the manifest has.
+    pub fn for_target(&self, target: &str) -> Result<Vec<&SidecarDownload>> {
+        let downloads: Vec<_> = self
+            .sidecars
+            .iter()
+            .filter(|download| download.target == target)
+            .collect();
+        if downloads.is_empty() {
+            let mut targets: Vec<_> = self
+                .sidecars
+                .iter()
+                .map(|download| download.target.as_str())
+                .collect();
+            targets.sort_unstable();
+            targets.dedup();
+            bail!(
+                "the sidecar manifest has no downloads for {target}; it covers {}",
+                targets.join(", ")
+            );
+        }
+        Ok(downloads)
+    }
+}
+
+/// This build's target as the manifest names it, e.g. `linux-x86_64`.
+pub fn current_target() -> String {
+    format!("{}-{}", std::env::consts::OS, std::env::consts::ARCH)
+}
+
+/// `fetch-sidecars`: downloads every sidecar `manifest` pins for `target`, checks
+/// each archive's SHA-256 and installs the executables under `sidecar_dir`.
+/// Nothing is downloaded when an entry has no checksum.
+pub async fn fetch_sidecars(
+    manifest: &SidecarManifest,
+    target: &str,
+    sidecar_dir: &Path,
+) -> Result<()> {
+    let downloads = manifest.for_target(target)?;
+    if let Some(unpinned) = downloads
+        .iter()
+        .find(|download| download.sha256.trim().is_empty())
+    {
+        bail!(
+            "the sidecar manifest has no sha256 for {} on {target}; pin it before fetching",
+            unpinned.name.as_str()
+        );
+    }
+
+    std::fs::create_dir_all(sidecar_dir)
+        .with_context(|| format!("failed to create {}", sidecar_dir.display()))?;
+    let client = reqwest::Client::new();
+    for download in downloads {
+        let archive = sidecar_dir.join(format!(".{}.zip", download.name.as_str()));
+        let installed = async {
+            download_verified(&client, download, &archive).await?;
+            let (archive, download, sidecar_dir) =
+                (archive.clone(), download.clone(), sidecar_dir.to_path_buf());
+            tokio::task::spawn_blocking(move || {
+                install_from_archive(&archive, &download, &sidecar_dir)
+            })
+            .await
+            .context("sidecar install task failed")?
+        }
+        .await;
+        let _ = std::fs::remove_file(&archive);
+        let installed =
+            installed.with_context(|| format!("failed to fetch {}", download.name.as_str()))?;
+        info!(
+            sidecar = download.name.as_str(),
+            path = %installed.display(),
+            "installed sidecar"
+        );
+    }
+    Ok(())
+}
+
+/// Streams `download` to `path`, logging progress every 10%, and fails unless
+/// the archive matches its pinned checksum.
+async fn download_verified(
+    client: &reqwest::Client,
+    download: &SidecarDownload,
+    path: &Path,
+) -> Result<()> {
+    info!(sidecar = download.name.as_str(), url = %download.url, "downloading sidecar");
+    let mut response = client
+        .get(&download.url)
+        .send()
+        .await
+        .and_then(reqwest::Response::error_for_status)
+        .with_context(|| format!("failed to download {}", download.url))?;
+    let total_bytes = response.content_length();
+    let mut file = tokio::fs::File::create(path)
+        .await
+        .with_context(|| format!("failed to create {}", path.display()))?;
+    let mut hasher = Sha256::new();
+    let mut received_bytes: u64 = 0;
+    let mut reported_percent: u64 = 0;
+    while let Some(chunk) = response
+        .chunk()
+        .await
+        .with_context(|| format!("failed to download {}", download.url))?
+    {
+        hasher.update(&chunk);
+        file.write_all(&chunk)
+            .await
+            .with_context(|| format!("failed to write {}", path.display()))?;
+        received_bytes = received_bytes.saturating_add(chunk.len() as u64);
+        if let Some(total_bytes) = total_bytes.filter(|&total| total > 0) {
+            let percent = received_bytes.saturating_mul(100) / total_bytes;
+            if percent >= reported_percent + 10 {
+                reported_percent = percent - percent % 10;
+                info!(
+                    sidecar = download.name.as_str(),
+                    percent = reported_percent,
+                    received_mb = received_bytes / (1024 * 1024),
+                    "download progress"
+                );
+            }
+        }
+    }
+    file.flush()
+        .await
+        .with_context(|| format!("failed to write {}", path.display()))?;
+
+    verify_sha256(download, &hex(&hasher.finalize()))?;
+    Ok(())
+}
+
+/// Fails with [`RuntimeError::SidecarChecksumMismatch`] unless `actual` is the
+/// pinned checksum of `download`. Case is ignored.
+pub fn verify_sha256(download: &SidecarDownload, actual: &str) -> Result<(), RuntimeError> {
+    if download.sha256.trim().eq_ignore_ascii_case(actual) {
+        return Ok(());
+    }
+    Err(RuntimeError::SidecarChecksumMismatch {
+        name: download.name.as_str(),
+        expected: download.sha256.trim().to_ascii_lowercase(),
+        actual: actual.to_string(),
+    })
+}
+
+/// Lowercase hex SHA-256 of `data`.
+pub fn sha256_hex(data: &[u8]) -> String {
+    hex(&Sha256::digest(data))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+/// Extracts `download`'s executable from the zip at `archive` to its
+/// [`SidecarKind::install_path`] under `sidecar_dir`. For `headless_shell`, every
+/// file next to the executable in the archive is installed with it. Returns the
+/// installed executable.
+pub fn install_from_archive(
+    archive: &Path,
+    download: &SidecarDownload,
+    sidecar_dir: &Path,
+) -> Result<PathBuf> {
+    let file = std::fs::File::open(archive)
+        .with_context(|| format!("failed to open {}", archive.display()))?;
+    let mut zip = zip::ZipArchive::new(file).context("sidecar download is not a zip archive")?;
+
+    let executable = (0..zip.len())
+        .filter_map(|index| zip.by_index(index).ok()?.enclosed_name())
+        .filter(|path| {
+            path.file_name()
+                .is_some_and(|name| name == download.executable.as_str())
+        })
+        .min_by_key(|path| path.components().count())
+        .with_context(|| format!("{} not found in the archive", download.executable))?;
+    let install_path = download.name.install_path(sidecar_dir);
+    let install_dir = install_path
+        .parent()
+        .context("sidecar install path has no parent")?;
+    std::fs::create_dir_all(install_dir)
+        .with_context(|| format!("failed to create {}", install_dir.display()))?;
+
+    if download.name == SidecarKind::HeadlessShell {
+        let root = executable.parent().unwrap_or(Path::new(""));
+        for index in 0..zip.len() {
+            let mut entry = zip.by_index(index)?;
+            let Some(relative) = entry
+                .enclosed_name()
+                .and_then(|path| path.strip_prefix(root).ok().map(Path::to_path_buf))
+            else {
+                continue;
+            };
+            let target = install_dir.join(&relative);
+            if entry.is_dir() {
+                std::fs::create_dir_all(&target)?;
+                continue;
+            }
+            let mode = entry.unix_mode();
+            extract_entry(&mut entry, &target, mode)?;
+        }
+    }
+
+    let mut entry = zip.by_name(&executable.to_string_lossy())?;
+    extract_entry(&mut entry, &install_path, Some(0o755))?;
+    Ok(install_path)
+}
+
+fn extract_entry(entry: &mut impl Read, target: &Path, mode: Option<u32>) -> Result<()> {
+    if let Some(parent) = target.parent() {
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("failed to create {}", parent.display()))?;
+    }
+    let mut out = std::fs::File::create(target)
+        .with_context(|| format!("failed to create {}", target.display()))?;
+    std::io::copy(entry, &mut out)
+        .with_context(|| format!("failed to extract {}", target.display()))?;
+    #[cfg(unix)]
+    if let Some(mode) = mode {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(target, std::fs::Permissions::from_mode(mode))?;
+    }
+    #[cfg(not(unix))]
+    let _ = mode;
+    Ok(())
+}
@@ -0,0 +1,159 @@
+use std::hash::{BuildHasher, RandomState};
+use std::path::Path;
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant, SystemTime};
+
+use tracing::{info, warn};
+
+static PROCESS_START: OnceLock<Instant> = OnceLock::new();
+static RUN_ID: OnceLock<String> = OnceLock::new();
+static READY: AtomicBool = AtomicBool::new(false);
+
+/// Time the process started, as recorded by the first call. `main` calls this
+/// first thing so later callers get the real start.
+pub fn process_start() -> Instant {
+    *PROCESS_START.get_or_init(Instant::now)
+}
+
+/// Short random id for this process, attached to every log line so output from
+/// several instances sharing a log sink can be told apart.
+pub fn run_id() -> &'static str {
+    RUN_ID.get_or_init(|| {
+        let random = RandomState::new().hash_one((std::process::id(), SystemTime::now()));
+        format!("{:08x}", random as u32)
+    })
+}
+
+/// Writes the process id to `path` the first time the stream goes live, so a
+/// supervisor can wait for the file instead of guessing. Later calls, including
+/// those from retried attempts, do nothing. Failures are only logged.
+pub fn mark_ready(path: &Path) {
+    if READY.swap(true, Ordering::Relaxed) {
+        return;
+    }
+    match std::fs::write(path, format!("{}\n", std::process::id())) {
+        Ok(()) => info!(path = %path.display(), "stream is live; wrote ready file"),
+        Err(err) => warn!(path = %path.display(), "failed to write ready file: {err}"),
+    }
+}
+
+/// Removes the file written by [`mark_ready`], if it was written.
+pub fn clear_ready(path: &Path) {
+    if READY.swap(false, Ordering::Relaxed)
+        && let Err(err) = std::fs::remove_file(path)
+    {
+        warn!(path = %path.display(), "failed to remove ready file: {err}");
+    }
+}
+
+/// Milestones of one stream attempt, from the attempt starting to the first frame
+/// reaching ffmpeg.
+#[derive(Debug, Clone)]
+pub struct StartupTimeline {
+    attempt_started: Instant,
+    encoder_spawned: Option<Instant>,
+    browser_launched: Option<Instant>,
+    page_loaded: Option<Instant>,
+    first_screencast_frame: Option<Instant>,
+    first_encoded_frame: Option<Instant>,
+}
+
+/// Millisecond durations between consecutive startup milestones.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StartupReport {
+    /// Attempt start until the browser process is up.
+    pub browser_launch_ms: u64,
+    /// Browser up until the page finished loading.
+    pub navigation_ms: u64,
+    /// Page loaded until the first screencast frame, including `--startup-delay-ms`.
+    pub first_frame_ms: u64,
+    /// First screencast frame until it was written to ffmpeg.
+    pub encode_ms: u64,
+    /// Attempt start until the first encoded frame.
+    pub total_ms: u64,
+    /// How long ffmpeg waited for its first frame of this attempt.
+    pub encoder_idle_ms: u64,
+}
+
+impl StartupTimeline {
+    pub fn new(attempt_started: Instant) -> Self {
+        Self {
+            attempt_started,
+            encoder_spawned: None,
+            browser_launched: None,
+            page_loaded: None,
+            first_screencast_frame: None,
+            first_encoded_frame: None,
+        }
+    }
+
+    /// An encoder spawned before the attempt started counts from the attempt start.
+    pub fn encoder_spawned(&mut self, at: Instant) {
+        self.encoder_spawned.get_or_insert(at);
+    }
+
+    pub fn browser_launched(&mut self, at: Instant) {
+        self.browser_launched.get_or_insert(at);
+    }
+
+    pub fn page_loaded(&mut self, at: Instant) {
+        self.page_loaded.get_or_insert(at);
+    }
+
+    pub fn first_screencast_frame(&mut self, at: Instant) {
+        self.first_screencast_frame.get_or_insert(at);
+    }
+
+    pub fn first_encoded_frame(&mut self, at: Instant) {
+        self.first_encoded_frame.get_or_insert(at);
+    }
+
+    /// Durations between milestones once the first frame has been encoded. A
+    /// milestone that was skipped, such as the page load when attaching to an
+    /// existing tab, counts as zero.
+    pub fn report(&self) -> Option<StartupReport> {
+        let encoded = self.first_encoded_frame?;
+        let launched = self.browser_launched.unwrap_or(self.attempt_started);
+        let loaded = self.page_loaded.unwrap_or(launched);
+        let screencast = self.first_screencast_frame.unwrap_or(loaded);
+        let millis = |from: Instant, to: Instant| duration_ms(to.saturating_duration_since(from));
+
+        Some(StartupReport {
+            browser_launch_ms: millis(self.attempt_started, launched),
+            navigation_ms: millis(launched, loaded),
+            first_frame_ms: millis(loaded, screencast),
+            encode_ms: millis(screencast, encoded),
+            total_ms: millis(self.attempt_started, encoded),
+            encoder_idle_ms: millis(
+                self.encoder_spawned
+                    .map_or(self.attempt_started, |spawned| {
+                        spawned.max(self.attempt_started)
+                    }),
+                encoded,
+            ),
+        })
+    }
+}
+
+/// Logs the startup milestones of this attempt once its first frame reached ffmpeg.
+pub fn log_startup_report(timeline: &StartupTimeline) {
+    let Some(report) = timeline.report() else {
+        return;
+    };
+    let since_process_start_ms = duration_ms(process_start().elapsed());
+    info!(
+        browser_launch_ms = report.browser_launch_ms,
+        navigation_ms = report.navigation_ms,
+        first_frame_ms = report.first_frame_ms,
+        encode_ms = report.encode_ms,
+        total_ms = report.total_ms,
+        encoder_idle_ms = report.encoder_idle_ms,
+        since_process_start_ms,
+        "startup complete"
+    );
+}
+
+fn duration_ms(duration: Duration) -> u64 {
+    u64::try_from(duration.as_millis()).unwrap_or(u64::MAX)
+}
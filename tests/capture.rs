@@ -0,0 +1,74 @@
+use std::ops::ControlFlow;
+
+use clap::Parser;
+
+use browser_stream::chromium::forward_decoded_frames;
+use browser_stream::cli::{AppConfig, CliArgs};
+
+// 1x1 red PNG
+const RED_PIXEL: &str =
+    "iVBORw0KGgoAAAANSUhEUgAAAAEAAAABCAAAAAA6fptVAAAACklEQVR4nGNgAAAAAgABSK+kcQAAAABJRU5ErkJggg==";
+
+fn config(extra: &[&str]) -> AppConfig {
+    let mut argv = vec![
+        "browser-stream",
+        "--url",
+        "https://example.com",
+        "--output-to-null",
+        "--width",
+        "16",
+        "--height",
+        "16",
+    ];
+    argv.extend_from_slice(extra);
+    CliArgs::try_parse_from(argv)
+        .expect("args should parse")
+        .into_config()
+        .expect("config should be valid")
+}
+
+#[tokio::test]
+async fn hands_decoded_frames_to_callback_until_it_breaks() {
+    let payloads = futures::stream::iter(vec![
+        RED_PIXEL.to_string(),
+        "not base64".to_string(),
+        RED_PIXEL.to_string(),
+        RED_PIXEL.to_string(),
+    ]);
+    let mut sizes = Vec::new();
+
+    forward_decoded_frames(payloads, &config(&[]), |frame| {
+        sizes.push((frame.width, frame.height, frame.data.len()));
+        if sizes.len() == 2 {
+            ControlFlow::Break(())
+        } else {
+            ControlFlow::Continue(())
+        }
+    })
+    .await
+    .expect("capture should stop cleanly");
+
+    assert_eq!(sizes, [(16, 16, 16 * 16 * 3), (16, 16, 16 * 16 * 3)]);
+}
+
+#[tokio::test]
+async fn fails_when_frames_stop_or_never_decode() {
+    let ended = forward_decoded_frames(
+        futures::stream::iter(vec![RED_PIXEL.to_string()]),
+        &config(&[]),
+        |_| ControlFlow::Continue(()),
+    )
+    .await;
+    assert!(ended.is_err());
+
+    let undecodable = forward_decoded_frames(
+        futures::stream::iter(vec!["not base64".to_string(); 3]),
+        &config(&["--max-decode-errors", "2"]),
+        |_| ControlFlow::Continue(()),
+    )
+    .await;
+    assert!(
+        format!("{:#}", undecodable.expect_err("decode errors should fail"))
+            .contains("2 consecutive")
+    );
+}
@@ -1,19 +1,63 @@
 use std::path::PathBuf;
+use std::time::Duration;
 
-use browser_stream::encoder::{EncoderSettings, build_ffmpeg_args};
+use browser_stream::cli::{
+    ColorRange, FrameRate, RecordCodec, RecordConfig, RecordFormat, Rendition,
+};
+use browser_stream::encoder::{
+    DroppingWriter, EncoderSettings, build_ffmpeg_args, build_tee_outputs, format_command,
+    shell_quote, shell_quote_cmd, shell_quote_sh,
+};
+use tokio::io::AsyncReadExt;
+
+#[tokio::test]
+async fn dropping_writer_finishes_a_partial_frame_before_the_next() {
+    let (mut pipe, mut reader) = tokio::io::duplex(8);
+    let mut writer = DroppingWriter::default();
+    let drop_after = Duration::from_millis(20);
+
+    // Only 8 of the 12 bytes fit; the tail waits for the next write.
+    writer
+        .write(&mut pipe, &[1; 12], drop_after)
+        .await
+        .expect("write should succeed");
+    // The pipe is still full, so the tail cannot go out and this frame is dropped.
+    writer
+        .write(&mut pipe, &[2; 12], drop_after)
+        .await
+        .expect("write should succeed");
+    assert_eq!(writer.dropped_frames(), 1);
+
+    let mut read = vec![0; 8];
+    reader
+        .read_exact(&mut read)
+        .await
+        .expect("read should succeed");
+    assert_eq!(read, [1; 8]);
+
+    // The tail goes out first, then as much of the new frame as fits.
+    writer
+        .write(&mut pipe, &[3; 12], drop_after)
+        .await
+        .expect("write should succeed");
+    reader
+        .read_exact(&mut read)
+        .await
+        .expect("read should succeed");
+    assert_eq!(read, [1, 1, 1, 1, 3, 3, 3, 3]);
+    assert_eq!(writer.dropped_frames(), 1);
+    assert_eq!(writer.take_unwritten(), Some(vec![3; 8]));
+}
 
 #[test]
 fn derives_keyint_from_fps_and_seconds() {
     let settings = EncoderSettings {
         width: 1280,
         height: 720,
-        fps: 30,
+        fps: 30.into(),
         bitrate_kbps: 2500,
         keyint_sec: 2,
-        x264_opts: "bframes=0".to_string(),
-        output: "rtmp://live.example.com/app/key".to_string(),
-        include_silent_audio: true,
-        ffmpeg_path: PathBuf::from("/tmp/ffmpeg"),
+        ..base_settings()
     };
 
     let args = build_ffmpeg_args(&settings);
@@ -25,15 +69,10 @@ fn derives_keyint_from_fps_and_seconds() {
 #[test]
 fn includes_cbr_like_flags() {
     let settings = EncoderSettings {
-        width: 1920,
-        height: 1080,
-        fps: 60,
+        fps: 60.into(),
         bitrate_kbps: 4500,
-        keyint_sec: 1,
-        x264_opts: "bframes=0".to_string(),
         output: "rtmps://live.example.com/app/key".to_string(),
-        include_silent_audio: true,
-        ffmpeg_path: PathBuf::from("/tmp/ffmpeg"),
+        ..base_settings()
     };
 
     let args = build_ffmpeg_args(&settings);
@@ -46,15 +85,9 @@ fn includes_cbr_like_flags() {
 #[test]
 fn passes_x264_opts_and_output() {
     let settings = EncoderSettings {
-        width: 1920,
-        height: 1080,
-        fps: 30,
         bitrate_kbps: 3000,
-        keyint_sec: 1,
         x264_opts: "bframes=0:scenecut=0".to_string(),
-        output: "rtmp://live.example.com/app/key".to_string(),
-        include_silent_audio: true,
-        ffmpeg_path: PathBuf::from("/tmp/ffmpeg"),
+        ..base_settings()
     };
 
     let args = build_ffmpeg_args(&settings);
@@ -66,6 +99,540 @@ fn passes_x264_opts_and_output() {
     );
 }
 
+#[test]
+fn uses_segment_muxer_when_segmenting() {
+    let settings = EncoderSettings {
+        output: "out_%03d.mp4".to_string(),
+        segment_time_sec: Some(3600),
+        ..base_settings()
+    };
+
+    let args = build_ffmpeg_args(&settings);
+
+    assert!(!args.iter().any(|arg| arg == "flv"));
+    assert_pair(&args, "-segment_time", "3600");
+    assert_pair(&args, "-reset_timestamps", "1");
+    assert_eq!(
+        &args[args.len() - 7..],
+        [
+            "-f",
+            "segment",
+            "-segment_time",
+            "3600",
+            "-reset_timestamps",
+            "1",
+            "out_%03d.mp4"
+        ]
+    );
+}
+
+#[test]
+fn ends_silent_audio_with_video_for_file_outputs() {
+    let recording = EncoderSettings {
+        output: "out_%03d.mp4".to_string(),
+        segment_time_sec: Some(60),
+        ..base_settings()
+    };
+    let live = base_settings();
+    let recording_without_audio = EncoderSettings {
+        include_silent_audio: false,
+        ..recording.clone()
+    };
+
+    assert!(build_ffmpeg_args(&recording).contains(&"-shortest".to_string()));
+    assert!(!build_ffmpeg_args(&live).contains(&"-shortest".to_string()));
+    assert!(!build_ffmpeg_args(&recording_without_audio).contains(&"-shortest".to_string()));
+}
+
+#[test]
+fn emits_configured_output_pixel_format() {
+    let settings = EncoderSettings {
+        pix_fmt: "yuv444p".to_string(),
+        ..base_settings()
+    };
+
+    let args = build_ffmpeg_args(&settings);
+
+    let codec_index = args
+        .iter()
+        .position(|item| item == "-c:v")
+        .expect("video codec should be set");
+    assert_pair(&args[codec_index..], "-pix_fmt", "yuv444p");
+}
+
+#[test]
+fn tags_output_with_bt709_color_metadata() {
+    let args = build_ffmpeg_args(&base_settings());
+
+    assert_pair(&args, "-color_range", "tv");
+    assert_pair(&args, "-colorspace", "bt709");
+    assert_pair(&args, "-color_primaries", "bt709");
+    assert_pair(&args, "-color_trc", "bt709");
+
+    let full = EncoderSettings {
+        color_range: ColorRange::Full,
+        ..base_settings()
+    };
+    assert_pair(&build_ffmpeg_args(&full), "-color_range", "pc");
+}
+
+#[test]
+fn requests_structured_progress_on_stdout() {
+    let args = build_ffmpeg_args(&base_settings());
+
+    assert_pair(&args, "-progress", "pipe:1");
+}
+
+#[test]
+fn emits_tune_before_x264_params() {
+    let settings = EncoderSettings {
+        tune: Some("animation,zerolatency".to_string()),
+        ..base_settings()
+    };
+
+    let args = build_ffmpeg_args(&settings);
+
+    assert_pair(&args, "-tune", "animation,zerolatency");
+    let tune_index = args.iter().position(|item| item == "-tune");
+    let params_index = args.iter().position(|item| item == "-x264-params");
+    assert!(tune_index < params_index);
+}
+
+#[test]
+fn omits_tune_by_default() {
+    let args = build_ffmpeg_args(&base_settings());
+
+    assert!(!args.iter().any(|item| item == "-tune"));
+}
+
+#[test]
+fn allows_scene_cut_idr_when_requested() {
+    let settings = EncoderSettings {
+        idr_on_scene_cut: true,
+        ..base_settings()
+    };
+
+    let args = build_ffmpeg_args(&settings);
+
+    assert_pair(&args, "-g", "30");
+    assert_pair(&args, "-keyint_min", "1");
+}
+
+#[test]
+fn forces_keyframes_on_wall_clock_interval() {
+    let settings = EncoderSettings {
+        force_keyframe_sec: Some(2),
+        ..base_settings()
+    };
+
+    let args = build_ffmpeg_args(&settings);
+
+    assert_pair(&args, "-force_key_frames", "expr:gte(t,n_forced*2)");
+    assert!(
+        !build_ffmpeg_args(&base_settings())
+            .iter()
+            .any(|item| item == "-force_key_frames")
+    );
+}
+
+#[test]
+fn discards_output_with_null_muxer() {
+    let settings = EncoderSettings {
+        output: "-".to_string(),
+        null_output: true,
+        ..base_settings()
+    };
+
+    let args = build_ffmpeg_args(&settings);
+
+    assert_eq!(&args[args.len() - 3..], ["-f", "null", "-"]);
+    assert!(!args.iter().any(|item| item == "flv"));
+}
+
+#[test]
+fn emits_fractional_frame_rate_as_ratio() {
+    let settings = EncoderSettings {
+        fps: FrameRate {
+            num: 30000,
+            den: 1001,
+        },
+        keyint_sec: 2,
+        ..base_settings()
+    };
+
+    let args = build_ffmpeg_args(&settings);
+
+    assert_pair(&args, "-r", "30000/1001");
+    assert_pair(&args, "-g", "60");
+}
+
+#[test]
+fn places_audio_filter_before_audio_codec() {
+    let settings = EncoderSettings {
+        audio_filter: Some("volume=0.5".to_string()),
+        ..base_settings()
+    };
+    let without_audio = EncoderSettings {
+        include_silent_audio: false,
+        ..settings.clone()
+    };
+
+    let args = build_ffmpeg_args(&settings);
+
+    assert_pair(&args, "-af", "volume=0.5");
+    let filter_index = args.iter().position(|item| item == "-af");
+    let codec_index = args.iter().position(|item| item == "-c:a");
+    assert!(filter_index < codec_index);
+    assert!(!build_ffmpeg_args(&without_audio).contains(&"-af".to_string()));
+}
+
+#[test]
+fn places_video_filter_before_video_codec() {
+    let settings = EncoderSettings {
+        video_filter: Some("eq=contrast=1.2,drawtext=text=live".to_string()),
+        ..base_settings()
+    };
+
+    let args = build_ffmpeg_args(&settings);
+
+    assert_pair(&args, "-vf", "eq=contrast=1.2,drawtext=text=live");
+    let input_index = args.iter().position(|item| item == "-i");
+    let filter_index = args.iter().position(|item| item == "-vf");
+    let codec_index = args.iter().position(|item| item == "-c:v");
+    assert!(input_index < filter_index);
+    assert!(filter_index < codec_index);
+    assert!(!build_ffmpeg_args(&base_settings()).contains(&"-vf".to_string()));
+}
+
+#[test]
+fn appends_vp9_mkv_record_output_after_main_output() {
+    let settings = EncoderSettings {
+        record: Some(RecordConfig {
+            path: "archive.mkv".to_string(),
+            format: RecordFormat::Mkv,
+            codec: RecordCodec::Vp9,
+        }),
+        ..base_settings()
+    };
+
+    let args = build_ffmpeg_args(&settings);
+
+    let main_output = args
+        .iter()
+        .position(|item| item == "rtmp://live.example.com/app/key")
+        .expect("main output should be present");
+    let record_args = &args[main_output + 1..];
+    assert_pair(record_args, "-c:v", "libvpx-vp9");
+    assert_pair(record_args, "-c:a", "libopus");
+    assert!(record_args.contains(&"-shortest".to_string()));
+    assert_eq!(&args[args.len() - 3..], ["-f", "matroska", "archive.mkv"]);
+}
+
+#[test]
+fn splits_scales_and_encodes_each_rendition() {
+    let settings = EncoderSettings {
+        video_filter: Some("eq=contrast=1.1".to_string()),
+        renditions: vec![
+            Rendition {
+                output: "rtmp://a.example.com/app/key".to_string(),
+                height: None,
+                bitrate_kbps: 4500,
+            },
+            Rendition {
+                output: "rtmp://b.example.com/app/key".to_string(),
+                height: Some(720),
+                bitrate_kbps: 2500,
+            },
+        ],
+        ..base_settings()
+    };
+
+    let args = build_ffmpeg_args(&settings);
+
+    assert_pair(
+        &args,
+        "-filter_complex",
+        "[0:v]eq=contrast=1.1,split=2[s0][s1];[s0]null[v0];[s1]scale=-2:720[v1]",
+    );
+    assert!(!args.iter().any(|item| item == "-vf"));
+
+    let first_output = args
+        .iter()
+        .position(|item| item == "rtmp://a.example.com/app/key")
+        .expect("first rendition output should be present");
+    let first = &args[..first_output];
+    let second = &args[first_output + 1..];
+    assert_pair(first, "-map", "[v0]");
+    assert_pair(first, "-b:v", "4500k");
+    assert_pair(first, "-c:a", "aac");
+    assert_pair(second, "-map", "[v1]");
+    assert_pair(second, "-b:v", "2500k");
+    assert_pair(second, "-maxrate", "2500k");
+    assert_pair(second, "-bufsize", "5000k");
+    assert_eq!(
+        &args[args.len() - 3..],
+        ["-f", "flv", "rtmp://b.example.com/app/key"]
+    );
+    assert!(
+        !args
+            .iter()
+            .any(|item| item == "rtmp://live.example.com/app/key")
+    );
+}
+
+#[test]
+fn loops_fallback_video_fitted_to_output_size() {
+    let settings = EncoderSettings {
+        loop_video: Some(PathBuf::from("standby.mp4")),
+        video_filter: Some("eq=contrast=1.1".to_string()),
+        ..base_settings()
+    };
+
+    let args = build_ffmpeg_args(&settings);
+
+    assert_pair(&args, "-stream_loop", "-1");
+    assert_pair(&args, "-i", "standby.mp4");
+    assert!(args.contains(&"-re".to_string()));
+    assert!(!args.iter().any(|item| item == "rawvideo"));
+    assert_pair(
+        &args,
+        "-vf",
+        "scale=1920:1080:force_original_aspect_ratio=decrease,pad=1920:1080:(ow-iw)/2:(oh-ih)/2,setsar=1,fps=30,eq=contrast=1.1",
+    );
+    assert_eq!(
+        &args[args.len() - 3..],
+        ["-f", "flv", "rtmp://live.example.com/app/key"]
+    );
+}
+
+#[test]
+fn tees_one_encode_to_rtmp_and_hls() {
+    let settings = EncoderSettings {
+        hls_dir: Some(PathBuf::from("archive/hls")),
+        ..base_settings()
+    };
+
+    let args = build_ffmpeg_args(&settings);
+
+    assert_eq!(args.iter().filter(|item| *item == "-c:v").count(), 1);
+    assert_pair(&args, "-flags", "+global_header");
+    let maps: Vec<&String> = args
+        .iter()
+        .zip(args.iter().skip(1))
+        .filter(|(flag, _)| *flag == "-map")
+        .map(|(_, value)| value)
+        .collect();
+    assert_eq!(maps, ["0:v", "1:a"]);
+    let hls_playlist = PathBuf::from("archive/hls").join("index.m3u8");
+    assert_eq!(
+        &args[args.len() - 3..],
+        [
+            "-f".to_string(),
+            "tee".to_string(),
+            format!(
+                "[f=flv]rtmp://live.example.com/app/key|[f=hls:hls_time=4:hls_list_size=0:hls_flags=append_list]{}",
+                hls_playlist.display()
+            ),
+        ]
+    );
+}
+
+#[test]
+fn escapes_tee_separators_in_targets() {
+    assert_eq!(
+        build_tee_outputs("rtmp://host/app/a|b", std::path::Path::new("hls")),
+        format!(
+            "[f=flv]rtmp://host/app/a\\|b|[f=hls:hls_time=4:hls_list_size=0:hls_flags=append_list]{}",
+            std::path::Path::new("hls").join("index.m3u8").display()
+        )
+    );
+}
+
+#[test]
+fn emits_stream_metadata_before_each_streamed_output() {
+    let settings = EncoderSettings {
+        stream_title: Some("Lobby \"A\" dashboard; $HOME".to_string()),
+        stream_description: Some("Office signage".to_string()),
+        ..base_settings()
+    };
+
+    let args = build_ffmpeg_args(&settings);
+
+    assert_eq!(
+        &args[args.len() - 7..],
+        [
+            "-metadata",
+            "title=Lobby \"A\" dashboard; $HOME",
+            "-metadata",
+            "description=Office signage",
+            "-f",
+            "flv",
+            "rtmp://live.example.com/app/key",
+        ]
+    );
+
+    let simulcast = EncoderSettings {
+        stream_description: None,
+        renditions: vec![
+            Rendition {
+                output: "rtmp://a.example.com/app/key".to_string(),
+                height: None,
+                bitrate_kbps: 4500,
+            },
+            Rendition {
+                output: "rtmp://b.example.com/app/key".to_string(),
+                height: Some(720),
+                bitrate_kbps: 2500,
+            },
+        ],
+        ..settings
+    };
+    let args = build_ffmpeg_args(&simulcast);
+    let metadata_flags: Vec<usize> = args
+        .iter()
+        .enumerate()
+        .filter(|(_, item)| *item == "-metadata")
+        .map(|(index, _)| index)
+        .collect();
+    assert_eq!(metadata_flags.len(), 2);
+    for (index, output) in metadata_flags
+        .iter()
+        .zip(["rtmp://a.example.com", "rtmp://b.example.com"])
+    {
+        assert!(args[index + 4].starts_with(output));
+    }
+}
+
+#[test]
+fn emits_tls_options_only_for_rtmps_outputs() {
+    let settings = EncoderSettings {
+        output: "rtmps://ingest.internal:443/app/key".to_string(),
+        tls_verify: false,
+        ca_file: Some(PathBuf::from("/etc/ssl/ingest-ca.pem")),
+        ..base_settings()
+    };
+
+    let args = build_ffmpeg_args(&settings);
+
+    assert_pair(&args, "-tls_verify", "0");
+    assert_pair(&args, "-ca_file", "/etc/ssl/ingest-ca.pem");
+    assert_eq!(
+        &args[args.len() - 3..],
+        ["-f", "flv", "rtmps://ingest.internal:443/app/key"]
+    );
+
+    let verified = build_ffmpeg_args(&EncoderSettings {
+        ca_file: None,
+        tls_verify: true,
+        ..settings.clone()
+    });
+    assert_pair(&verified, "-tls_verify", "1");
+    assert!(!verified.iter().any(|item| item == "-ca_file"));
+
+    let simulcast = build_ffmpeg_args(&EncoderSettings {
+        renditions: vec![
+            Rendition {
+                output: "rtmps://ingest.internal:443/app/key".to_string(),
+                height: None,
+                bitrate_kbps: 4500,
+            },
+            Rendition {
+                output: "rtmp://backup.example.com/app/key".to_string(),
+                height: Some(720),
+                bitrate_kbps: 2500,
+            },
+        ],
+        ..settings
+    });
+    assert_eq!(
+        simulcast
+            .iter()
+            .filter(|item| *item == "-tls_verify")
+            .count(),
+        1
+    );
+
+    let plain = build_ffmpeg_args(&base_settings());
+    assert!(!plain.iter().any(|item| item == "-tls_verify"));
+}
+
+#[test]
+fn emits_rtmp_live_options_only_for_rtmp_outputs() {
+    let settings = EncoderSettings {
+        rtmp_live: true,
+        flush_packets: true,
+        ..base_settings()
+    };
+
+    let args = build_ffmpeg_args(&settings);
+    assert_eq!(
+        &args[args.len() - 7..],
+        [
+            "-rtmp_live",
+            "live",
+            "-flush_packets",
+            "1",
+            "-f",
+            "flv",
+            "rtmp://live.example.com/app/key",
+        ]
+    );
+
+    for output in ["archive/stream.flv", "srt://ingest.example.com:9000"] {
+        let args = build_ffmpeg_args(&EncoderSettings {
+            output: output.to_string(),
+            ..settings.clone()
+        });
+        assert!(!args.iter().any(|item| item == "-rtmp_live"), "{output}");
+        assert!(
+            !args.iter().any(|item| item == "-flush_packets"),
+            "{output}"
+        );
+    }
+
+    let secure = build_ffmpeg_args(&EncoderSettings {
+        output: "RTMPS://live.example.com/app/key".to_string(),
+        ..settings
+    });
+    assert_pair(&secure, "-rtmp_live", "live");
+    assert_pair(&secure, "-tls_verify", "1");
+}
+
+fn base_settings() -> EncoderSettings {
+    EncoderSettings {
+        width: 1920,
+        height: 1080,
+        fps: 30.into(),
+        bitrate_kbps: 4500,
+        keyint_sec: 1,
+        x264_opts: "bframes=0".to_string(),
+        tune: None,
+        idr_on_scene_cut: false,
+        force_keyframe_sec: None,
+        pix_fmt: "yuv420p".to_string(),
+        color_range: ColorRange::Limited,
+        video_filter: None,
+        output: "rtmp://live.example.com/app/key".to_string(),
+        renditions: Vec::new(),
+        segment_time_sec: None,
+        null_output: false,
+        hls_dir: None,
+        rtmp_live: false,
+        flush_packets: false,
+        tls_verify: true,
+        ca_file: None,
+        record: None,
+        include_silent_audio: true,
+        audio_filter: None,
+        stream_title: None,
+        stream_description: None,
+        loop_video: None,
+        ffmpeg_path: PathBuf::from("/tmp/ffmpeg"),
+        init_timeout: Duration::from_secs(15),
+        drop_after: None,
+    }
+}
+
 fn assert_pair(args: &[String], flag: &str, value: &str) {
     let index = args
         .iter()
@@ -78,3 +645,42 @@ fn assert_pair(args: &[String], flag: &str, value: &str) {
 
     assert_eq!(next, value);
 }
+
+#[test]
+fn quotes_args_for_sh() {
+    assert_eq!(shell_quote_sh("-x264-params"), "-x264-params");
+    assert_eq!(
+        shell_quote_sh("rtmp://live.example.com/app/key"),
+        "rtmp://live.example.com/app/key"
+    );
+    assert_eq!(shell_quote_sh(""), "''");
+    assert_eq!(shell_quote_sh("two words"), "'two words'");
+    assert_eq!(shell_quote_sh("$HOME;rm"), "'$HOME;rm'");
+    assert_eq!(shell_quote_sh("it's"), r"'it'\''s'");
+    assert_eq!(shell_quote_sh(r#"say "hi""#), r#"'say "hi"'"#);
+}
+
+#[test]
+fn quotes_args_for_cmd() {
+    assert_eq!(shell_quote_cmd("bframes=0:ref=1"), "bframes=0:ref=1");
+    assert_eq!(shell_quote_cmd(""), r#""""#);
+    assert_eq!(
+        shell_quote_cmd(r"C:\Program Files\ffmpeg.exe"),
+        r#""C:\Program Files\ffmpeg.exe""#
+    );
+    assert_eq!(shell_quote_cmd(r#"say "hi""#), r#""say \"hi\"""#);
+    assert_eq!(shell_quote_cmd(r"dir\ with\"), r#""dir\ with\\""#);
+    assert_eq!(shell_quote_cmd("a&b|c"), r#""a&b|c""#);
+}
+
+#[test]
+fn formats_copy_pasteable_ffmpeg_command() {
+    let settings = EncoderSettings {
+        output: "rtmp://live.example.com/app/key?token=a b".to_string(),
+        ..base_settings()
+    };
+    let command = format_command(&settings.ffmpeg_path, &build_ffmpeg_args(&settings));
+
+    assert!(command.starts_with(&shell_quote("/tmp/ffmpeg")));
+    assert!(command.ends_with(&shell_quote("rtmp://live.example.com/app/key?token=a b")));
+}
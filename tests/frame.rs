@@ -1,4 +1,8 @@
-use browser_stream::frame::decode_screencast_frame;
+use browser_stream::cli::TestPattern;
+use browser_stream::frame::{
+    RgbFrame, composite_grid, decode_screencast_frame, grid_cells, load_image_frame, mean_abs_diff,
+    render_test_pattern, save_png, uniform_color,
+};
 
 #[test]
 fn decodes_and_resizes_frame() {
@@ -11,3 +15,165 @@ fn decodes_and_resizes_frame() {
     assert_eq!(frame.height, 2);
     assert_eq!(frame.data.len(), 2 * 2 * 3);
 }
+
+#[test]
+fn decodes_frame_with_wrapped_base64() {
+    let wrapped = "iVBORw0KGgoAAAANSUhEUgAAAAEAAAABCAAAAAA6fptVAAAA\r\n\
+                   CklEQVR4nGNgAAAAAgABSK+kcQAAAABJRU5ErkJggg==\n";
+
+    let frame = decode_screencast_frame(wrapped, 2, 2).expect("decode should work");
+
+    assert_eq!(frame.data.len(), 2 * 2 * 3);
+    assert!(decode_screencast_frame("iVBORw0K*GgoAAAANSUhEUg", 2, 2).is_err());
+}
+
+#[test]
+fn loads_and_resizes_image_file() {
+    let path =
+        std::env::temp_dir().join(format!("browser-stream-stall-{}.png", std::process::id()));
+    image::RgbImage::from_pixel(4, 2, image::Rgb([0, 128, 255]))
+        .save(&path)
+        .expect("png should be written");
+
+    let frame = load_image_frame(&path, 8, 4);
+    std::fs::remove_file(&path).expect("png should be removed");
+    let frame = frame.expect("image should load");
+
+    assert_eq!((frame.width, frame.height), (8, 4));
+    assert_eq!(frame.data.len(), 8 * 4 * 3);
+    assert_eq!(&frame.data[..3], [0, 128, 255]);
+    assert!(load_image_frame(&path, 8, 4).is_err());
+}
+
+#[test]
+fn saved_png_round_trips() {
+    let path = std::env::temp_dir().join(format!("browser-stream-dump-{}.png", std::process::id()));
+    let frame = render_test_pattern(TestPattern::Bars, 16, 8, 0);
+
+    save_png(&frame, &path).expect("png should be written");
+    let loaded = load_image_frame(&path, 16, 8);
+    std::fs::remove_file(&path).expect("png should be removed");
+
+    assert_eq!(loaded.expect("png should load").data, frame.data);
+    let truncated = RgbFrame {
+        data: frame.data[1..].to_vec(),
+        ..frame
+    };
+    assert!(save_png(&truncated, &path).is_err());
+}
+
+#[test]
+fn renders_deterministic_test_patterns() {
+    let pixel = |frame: &browser_stream::frame::RgbFrame, x: u32, y: u32| {
+        let offset = ((y * frame.width + x) * 3) as usize;
+        [
+            frame.data[offset],
+            frame.data[offset + 1],
+            frame.data[offset + 2],
+        ]
+    };
+
+    let solid = render_test_pattern(TestPattern::Solid([10, 20, 30]), 4, 2, 0);
+    assert_eq!(solid.data, [10, 20, 30].repeat(8));
+
+    let bars = render_test_pattern(TestPattern::Bars, 70, 40, 0);
+    assert_eq!(bars.data.len(), 70 * 40 * 3);
+    assert_eq!(pixel(&bars, 0, 0), [191, 191, 191]);
+    assert_eq!(pixel(&bars, 69, 0), [0, 0, 191]);
+    assert_eq!(pixel(&bars, 0, 39), [0, 33, 76]);
+    assert_eq!(
+        bars.data,
+        render_test_pattern(TestPattern::Bars, 70, 40, 99).data
+    );
+
+    let first = render_test_pattern(TestPattern::MovingBox, 64, 32, 0);
+    let second = render_test_pattern(TestPattern::MovingBox, 64, 32, 1);
+    assert_eq!(pixel(&first, 0, 0), [235, 235, 235]);
+    assert_ne!(first.data, second.data);
+    assert_eq!(
+        second.data,
+        render_test_pattern(TestPattern::MovingBox, 64, 32, 1).data
+    );
+}
+
+#[test]
+fn detects_uniform_frames_within_tolerance() {
+    let mut frame = RgbFrame {
+        width: 4,
+        height: 2,
+        data: [255, 254, 255].repeat(8),
+    };
+    assert_eq!(uniform_color(&frame, 2), Some([255, 254, 255]));
+
+    frame.data[21] = 252;
+    assert_eq!(uniform_color(&frame, 2), None);
+    assert_eq!(uniform_color(&frame, 3), Some([255, 254, 255]));
+
+    let bars = render_test_pattern(TestPattern::Bars, 64, 36, 0);
+    assert_eq!(uniform_color(&bars, 2), None);
+    let solid = render_test_pattern(TestPattern::Solid([10, 20, 30]), 64, 36, 0);
+    assert_eq!(uniform_color(&solid, 0), Some([10, 20, 30]));
+}
+
+#[test]
+fn measures_mean_difference_from_baseline() {
+    let frame = |data: Vec<u8>| RgbFrame {
+        width: 2,
+        height: 1,
+        data,
+    };
+    let baseline = frame(vec![10, 20, 30, 40, 50, 60]);
+
+    assert_eq!(mean_abs_diff(&baseline, &baseline), Some(0.0));
+    assert_eq!(
+        mean_abs_diff(&frame(vec![16, 14, 30, 40, 50, 60]), &baseline),
+        Some(2.0)
+    );
+    assert_eq!(
+        mean_abs_diff(&frame(vec![255, 255, 255, 255, 255, 255]), &baseline),
+        Some((245.0 + 235.0 + 225.0 + 215.0 + 205.0 + 195.0) / 6.0)
+    );
+
+    let taller = RgbFrame {
+        width: 1,
+        height: 2,
+        data: vec![10, 20, 30, 40, 50, 60],
+    };
+    assert_eq!(mean_abs_diff(&taller, &baseline), None);
+}
+
+#[test]
+fn splits_grid_cells_with_odd_remainder_right_and_bottom() {
+    assert_eq!(
+        grid_cells(1920, 1080),
+        [
+            (0, 0, 960, 540),
+            (960, 0, 960, 540),
+            (0, 540, 960, 540),
+            (960, 540, 960, 540),
+        ]
+    );
+    assert_eq!(grid_cells(5, 3)[3], (2, 1, 3, 2));
+}
+
+#[test]
+fn composites_page_frames_into_grid() {
+    let solid = |width: u32, height: u32, value: u8| RgbFrame {
+        width,
+        height,
+        data: vec![value; (width * height * 3) as usize],
+    };
+    let cells = [
+        Some(solid(2, 1, 10)),
+        Some(solid(2, 1, 20)),
+        None,
+        // Wrong size for its cell, so it stays black.
+        Some(solid(1, 1, 40)),
+    ];
+
+    let frame = composite_grid(4, 2, &cells);
+
+    assert_eq!((frame.width, frame.height), (4, 2));
+    let pixels: Vec<u8> = frame.data.chunks_exact(3).map(|pixel| pixel[0]).collect();
+    assert_eq!(pixels, [10, 10, 20, 20, 0, 0, 0, 0]);
+}
@@ -0,0 +1,16 @@
+use browser_stream::frame_source::parse_frame_envelope;
+
+#[test]
+fn extracts_data_from_frame_envelope() {
+    let data = parse_frame_envelope(r#"{"data":"aGVsbG8=","capturedAt":1700000000}"#)
+        .expect("envelope should parse");
+
+    assert_eq!(data, "aGVsbG8=");
+}
+
+#[test]
+fn rejects_envelope_without_string_data() {
+    assert!(parse_frame_envelope(r#"{"frame":"aGVsbG8="}"#).is_err());
+    assert!(parse_frame_envelope(r#"{"data":42}"#).is_err());
+    assert!(parse_frame_envelope("not json").is_err());
+}
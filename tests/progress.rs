@@ -0,0 +1,56 @@
+use browser_stream::progress::{FfmpegProgress, ProgressParser};
+
+#[test]
+fn assembles_snapshot_from_progress_block() {
+    let mut parser = ProgressParser::default();
+    let block = [
+        "frame=150",
+        "fps=29.97",
+        "stream_0_0_q=23.0",
+        "bitrate=3355.4kbits/s",
+        "total_size=2097152",
+        "out_time_us=5000000",
+        "out_time=00:00:05.000000",
+        "dup_frames=2",
+        "drop_frames=1",
+        "speed=1.0x",
+    ];
+
+    for line in block {
+        assert!(parser.push_line(line).is_none());
+    }
+
+    let snapshot = parser
+        .push_line("progress=continue")
+        .expect("block should complete");
+
+    assert_eq!(
+        snapshot,
+        FfmpegProgress {
+            frame: 150,
+            fps: 29.97,
+            bitrate_kbps: Some(3355.4),
+            out_time_us: 5_000_000,
+            drop_frames: 1,
+            dup_frames: 2,
+            ended: false,
+        }
+    );
+}
+
+#[test]
+fn tolerates_unavailable_values_and_marks_end() {
+    let mut parser = ProgressParser::default();
+
+    parser.push_line("frame=0");
+    parser.push_line("bitrate=N/A");
+    parser.push_line("out_time_us=N/A");
+    parser.push_line("not a key value line");
+    let snapshot = parser
+        .push_line("progress=end")
+        .expect("block should complete");
+
+    assert_eq!(snapshot.bitrate_kbps, None);
+    assert_eq!(snapshot.out_time_us, 0);
+    assert!(snapshot.ended);
+}
@@ -0,0 +1,104 @@
+use anyhow::anyhow;
+use clap::Parser;
+use futures::StreamExt;
+
+use browser_stream::cli::{AppConfig, CliArgs};
+use browser_stream::error::RuntimeError;
+use browser_stream::frame::RgbFrame;
+use browser_stream::pump::{SourceItem, checked_frames};
+
+fn config(extra: &[&str]) -> AppConfig {
+    let mut argv = vec![
+        "browser-stream",
+        "--url",
+        "https://example.com",
+        "--output-to-null",
+    ];
+    argv.extend_from_slice(extra);
+    CliArgs::try_parse_from(argv)
+        .expect("args should parse")
+        .into_config()
+        .expect("config should be valid")
+}
+
+fn frame(shade: u8) -> RgbFrame {
+    RgbFrame {
+        width: 1,
+        height: 1,
+        data: vec![shade; 3],
+    }
+}
+
+#[tokio::test]
+async fn checked_frames_skip_undecodable_frames_and_stop_at_the_first_error() {
+    let items: Vec<SourceItem> = vec![
+        Ok(Err(anyhow!("bad frame"))),
+        Ok(Ok(frame(1))),
+        Ok(Err(anyhow!("bad frame"))),
+        Ok(Ok(frame(2))),
+        Err(anyhow!("source went away")),
+        Ok(Ok(frame(3))),
+    ];
+    let results: Vec<_> = checked_frames(futures::stream::iter(items), &config(&[]))
+        .collect()
+        .await;
+
+    assert_eq!(results.len(), 3);
+    assert_eq!(results[0].as_ref().expect("first frame").data, [1; 3]);
+    assert_eq!(results[1].as_ref().expect("second frame").data, [2; 3]);
+    assert!(
+        format!("{:#}", results[2].as_ref().expect_err("source error"))
+            .contains("source went away")
+    );
+}
+
+#[tokio::test]
+async fn checked_frames_fail_without_a_first_frame() {
+    let undecodable = (0..3).map(|_| -> SourceItem { Ok(Err(anyhow!("bad frame"))) });
+    let results: Vec<_> = checked_frames(
+        futures::stream::iter(undecodable),
+        &config(&["--max-decode-errors", "2"]),
+    )
+    .collect()
+    .await;
+    assert_eq!(results.len(), 1);
+    assert!(
+        format!("{:#}", results[0].as_ref().expect_err("decode errors")).contains("2 consecutive")
+    );
+
+    let silent = futures::stream::pending::<SourceItem>();
+    let timed_out = Box::pin(checked_frames(
+        silent,
+        &config(&["--frame-timeout-ms", "1000"]),
+    ))
+    .next()
+    .await
+    .expect("the timeout should be reported");
+    assert!(matches!(
+        timed_out
+            .expect_err("no frame should time out")
+            .downcast_ref::<RuntimeError>(),
+        Some(RuntimeError::ScreencastTimeout)
+    ));
+}
+
+#[tokio::test]
+async fn checked_frames_skip_the_first_decoded_frames() {
+    let items: Vec<SourceItem> = vec![
+        Ok(Ok(frame(1))),
+        Ok(Err(anyhow!("bad frame"))),
+        Ok(Ok(frame(2))),
+        Ok(Err(anyhow!("bad frame"))),
+        Ok(Ok(frame(3))),
+    ];
+    let frames: Vec<_> = checked_frames(
+        futures::stream::iter(items),
+        &config(&["--skip-frames", "2", "--max-decode-errors", "2"]),
+    )
+    .map(|frame| frame.expect("frame should pass").data)
+    .collect()
+    .await;
+
+    // Skipped frames still end a run of decode errors.
+    assert_eq!(frames, [vec![3; 3]]);
+}
@@ -10,3 +10,30 @@ fn retries_up_to_configured_limit() {
     assert!(policy.should_retry(5));
     assert!(!policy.should_retry(6));
 }
+
+#[test]
+fn retries_count_excludes_first_attempt() {
+    let policy = RetryPolicy::new(5, Duration::from_millis(100));
+
+    assert_eq!(policy.max_attempts(), 6);
+
+    // Simulate the retry loop: every attempt fails.
+    let mut attempts = 0;
+    let mut failures = 0;
+    loop {
+        attempts += 1;
+        failures += 1;
+        if !policy.should_retry(failures) {
+            break;
+        }
+    }
+    assert_eq!(attempts, policy.max_attempts());
+}
+
+#[test]
+fn zero_retries_means_single_attempt() {
+    let policy = RetryPolicy::new(0, Duration::ZERO);
+
+    assert_eq!(policy.max_attempts(), 1);
+    assert!(!policy.should_retry(1));
+}
@@ -1,6 +1,12 @@
+use std::time::{Duration, UNIX_EPOCH};
+
 use assert_matches::assert_matches;
 
-use browser_stream::rtmp::{RtmpError, build_output};
+use browser_stream::error::RuntimeError;
+use browser_stream::rtmp::{
+    RtmpError, attempt_output_path, build_output, expand_output_template, output_endpoint,
+    preflight_output, redact_output,
+};
 
 #[test]
 fn builds_output_from_split_fields() {
@@ -57,3 +63,125 @@ fn rejects_empty_key() {
 
     assert_matches!(err, RtmpError::EmptyStreamKey);
 }
+
+#[test]
+fn expands_output_template_placeholders() {
+    // 2024-02-29T13:05:09Z
+    let started_at = UNIX_EPOCH + Duration::from_secs(1_709_211_909);
+
+    let expanded = expand_output_template(
+        "rtmp://live.example.com/app/show-{date}-{time}-{n}",
+        started_at,
+        3,
+    );
+
+    assert_eq!(
+        expanded,
+        "rtmp://live.example.com/app/show-2024-02-29-130509-3"
+    );
+}
+
+#[test]
+fn leaves_plain_output_untouched() {
+    let expanded = expand_output_template("rtmp://live.example.com/app/key", UNIX_EPOCH, 7);
+
+    assert_eq!(expanded, "rtmp://live.example.com/app/key");
+}
+
+#[test]
+fn suffixes_file_outputs_from_second_attempt() {
+    assert_eq!(
+        attempt_output_path("recordings/out_%03d.mp4", 1),
+        "recordings/out_%03d.mp4"
+    );
+    assert_eq!(
+        attempt_output_path("recordings/out_%03d.mp4", 3),
+        "recordings/out_%03d.attempt3.mp4"
+    );
+    assert_eq!(
+        attempt_output_path("./v1.2/segment_%d", 2),
+        "./v1.2/segment_%d.attempt2"
+    );
+    assert_eq!(
+        attempt_output_path("rtmp://live.example.com/app/key.v2", 2),
+        "rtmp://live.example.com/app/key.v2"
+    );
+    assert_eq!(
+        attempt_output_path("take-{n}_%03d.mp4", 2),
+        "take-{n}_%03d.mp4"
+    );
+    assert_eq!(attempt_output_path("-", 2), "-");
+}
+
+#[test]
+fn redacts_stream_key_from_network_outputs() {
+    assert_eq!(
+        redact_output("rtmps://live.example.com:443/app/sk_live_123"),
+        "rtmps://live.example.com:443/app/<redacted>"
+    );
+    assert_eq!(
+        redact_output("rtmp://live.example.com/app/nested/key/"),
+        "rtmp://live.example.com/app/nested/<redacted>"
+    );
+    assert_eq!(
+        redact_output("rtmp://live.example.com/app"),
+        "rtmp://live.example.com/app"
+    );
+    assert_eq!(redact_output("out_%03d.mp4"), "out_%03d.mp4");
+}
+
+#[test]
+fn accepts_templated_output() {
+    let output = build_output(
+        Some("rtmp://live.example.com/app/key-{date}".to_string()),
+        None,
+        None,
+    )
+    .expect("build should succeed");
+
+    assert_eq!(output, "rtmp://live.example.com/app/key-{date}");
+}
+
+#[test]
+fn derives_output_endpoint_with_default_ports() {
+    assert_eq!(
+        output_endpoint("rtmp://live.example.com/app/key"),
+        Some(("live.example.com".to_string(), 1935))
+    );
+    assert_eq!(
+        output_endpoint("rtmps://live.example.com:4443/app/key"),
+        Some(("live.example.com".to_string(), 4443))
+    );
+    assert_eq!(
+        output_endpoint("rtmps://live.example.com/app/key"),
+        Some(("live.example.com".to_string(), 443))
+    );
+    assert_eq!(output_endpoint("out_%03d.mp4"), None);
+}
+
+#[tokio::test]
+async fn preflight_reports_unreachable_output() {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").expect("bind should succeed");
+    let port = listener.local_addr().expect("local addr").port();
+    drop(listener);
+
+    let err = preflight_output(&format!("rtmp://127.0.0.1:{port}/app/key"))
+        .await
+        .expect_err("closed port should be unreachable");
+    assert_matches!(err, RuntimeError::OutputUnreachable { host, .. } if host == format!("127.0.0.1:{port}"));
+}
+
+#[tokio::test]
+async fn preflight_accepts_listening_output_and_skips_files() {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("bind should succeed");
+    let port = listener.local_addr().expect("local addr").port();
+
+    preflight_output(&format!("rtmp://127.0.0.1:{port}/app/key"))
+        .await
+        .expect("listening port should be reachable");
+    preflight_output("out_%03d.mp4")
+        .await
+        .expect("file outputs are not checked");
+}
@@ -0,0 +1,254 @@
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use assert_matches::assert_matches;
+
+use browser_stream::cli::FfmpegSource;
+use browser_stream::error::RuntimeError;
+use browser_stream::sidecar::{
+    DEFAULT_MANIFEST, SidecarKind, SidecarManifest, exe_dirs, fetch_sidecars, install_from_archive,
+    resolve_ffmpeg_path, sha256_hex, verify_sha256,
+};
+
+#[test]
+fn exe_dirs_try_the_symlink_then_its_target() {
+    let dirs = exe_dirs(
+        Path::new("/usr/local/bin/browser-stream"),
+        Some(Path::new("/opt/browser-stream/bin/browser-stream")),
+        Some(Path::new("/usr/local/bin/browser-stream")),
+    )
+    .expect("executable has a parent");
+    assert_eq!(
+        dirs,
+        [
+            PathBuf::from("/usr/local/bin"),
+            PathBuf::from("/opt/browser-stream/bin"),
+        ]
+    );
+
+    // Without a symlink every candidate is the same directory.
+    let dirs = exe_dirs(
+        Path::new("/opt/browser-stream/bin/browser-stream"),
+        Some(Path::new("/opt/browser-stream/bin/browser-stream")),
+        None,
+    )
+    .expect("executable has a parent");
+    assert_eq!(dirs, [PathBuf::from("/opt/browser-stream/bin")]);
+
+    // A bare invoked name has no directory to add.
+    let dirs = exe_dirs(
+        Path::new("/opt/browser-stream/bin/browser-stream"),
+        None,
+        Some(Path::new("browser-stream")),
+    )
+    .expect("executable has a parent");
+    assert_eq!(dirs, [PathBuf::from("/opt/browser-stream/bin")]);
+}
+
+#[test]
+fn ffmpeg_source_picks_sidecar_or_system() {
+    let dir = std::env::temp_dir().join(format!("browser-stream-sidecar-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).expect("temp dir should be creatable");
+    let sidecar = dir.join("ffmpeg");
+    std::fs::write(&sidecar, "").expect("sidecar should be writable");
+    let system = PathBuf::from("/usr/bin/ffmpeg");
+    let missing = dir.join("missing-ffmpeg");
+
+    let resolve = |sidecar: &Path, system: Option<&Path>, source| {
+        resolve_ffmpeg_path(
+            None,
+            sidecar.to_path_buf(),
+            system.map(Path::to_path_buf),
+            source,
+        )
+    };
+
+    assert_eq!(
+        resolve(&sidecar, Some(&system), FfmpegSource::Sidecar).expect("sidecar exists"),
+        sidecar
+    );
+    let err = resolve(&missing, Some(&system), FfmpegSource::Sidecar)
+        .expect_err("a missing sidecar must not fall back to PATH");
+    assert_matches!(
+        err.downcast_ref::<RuntimeError>(),
+        Some(RuntimeError::MissingSidecar { name: "ffmpeg", .. })
+    );
+
+    assert_eq!(
+        resolve(&sidecar, Some(&system), FfmpegSource::System).expect("ffmpeg is on PATH"),
+        system
+    );
+    resolve(&sidecar, None, FfmpegSource::System)
+        .expect_err("system ffmpeg must not fall back to the sidecar");
+
+    // `auto` falls back to PATH when the sidecar is missing.
+    assert_eq!(
+        resolve(&missing, Some(&system), FfmpegSource::Auto).expect("ffmpeg is on PATH"),
+        system
+    );
+
+    std::fs::remove_dir_all(&dir).expect("temp dir should be removable");
+}
+
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("browser-stream-{name}-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).expect("temp dir should be creatable");
+    dir
+}
+
+fn write_zip(path: &Path, files: &[(&str, &str)]) {
+    let mut zip =
+        zip::ZipWriter::new(std::fs::File::create(path).expect("zip should be creatable"));
+    let options =
+        zip::write::SimpleFileOptions::default().compression_method(zip::CompressionMethod::Stored);
+    for (name, contents) in files {
+        zip.start_file(*name, options)
+            .expect("zip entry should start");
+        zip.write_all(contents.as_bytes())
+            .expect("zip entry should be writable");
+    }
+    zip.finish().expect("zip should finish");
+}
+
+#[test]
+fn built_in_manifest_covers_every_packaged_target() {
+    let manifest = SidecarManifest::parse(DEFAULT_MANIFEST).expect("manifest should parse");
+    for target in ["linux-x86_64", "macos-aarch64", "windows-x86_64"] {
+        let kinds: Vec<_> = manifest
+            .for_target(target)
+            .expect("target should be covered")
+            .iter()
+            .map(|download| download.name)
+            .collect();
+        assert_eq!(kinds, [SidecarKind::HeadlessShell, SidecarKind::Ffmpeg]);
+    }
+
+    let err = manifest
+        .for_target("freebsd-x86_64")
+        .expect_err("unsupported target should fail");
+    assert!(err.to_string().contains("linux-x86_64"));
+}
+
+#[test]
+fn checks_downloads_against_the_pinned_checksum() {
+    let abc = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+    assert_eq!(sha256_hex(b"abc"), abc);
+
+    let manifest = SidecarManifest::parse(&format!(
+        r#"
+        [[sidecar]]
+        name = "ffmpeg"
+        target = "linux-x86_64"
+        url = "https://example.com/ffmpeg.zip"
+        sha256 = "{}"
+        executable = "ffmpeg"
+        "#,
+        abc.to_uppercase()
+    ))
+    .expect("manifest should parse");
+    let download = &manifest.sidecars[0];
+
+    verify_sha256(download, abc).expect("checksum should match regardless of case");
+    let err = verify_sha256(download, &sha256_hex(b"abd")).expect_err("checksum should differ");
+    assert_matches!(
+        err,
+        RuntimeError::SidecarChecksumMismatch { name: "ffmpeg", expected, .. } if expected == abc
+    );
+}
+
+#[tokio::test]
+async fn refuses_to_fetch_unpinned_sidecars() {
+    let manifest = SidecarManifest::parse(
+        r#"
+        [[sidecar]]
+        name = "ffmpeg"
+        target = "linux-x86_64"
+        url = "https://example.invalid/ffmpeg.zip"
+        sha256 = ""
+        executable = "ffmpeg"
+        "#,
+    )
+    .expect("manifest should parse");
+    let dir = temp_dir("unpinned");
+
+    let err = fetch_sidecars(&manifest, "linux-x86_64", &dir)
+        .await
+        .expect_err("an unpinned sidecar must not be downloaded");
+    assert!(err.to_string().contains("no sha256 for ffmpeg"));
+    assert_eq!(std::fs::read_dir(&dir).expect("dir exists").count(), 0);
+
+    std::fs::remove_dir_all(&dir).expect("temp dir should be removable");
+}
+
+#[test]
+fn installs_executables_from_archives() {
+    let dir = temp_dir("install");
+    let manifest = SidecarManifest::parse(
+        r#"
+        [[sidecar]]
+        name = "headless_shell"
+        target = "linux-x86_64"
+        url = "https://example.com/chrome.zip"
+        sha256 = "00"
+        executable = "chrome-headless-shell"
+
+        [[sidecar]]
+        name = "ffmpeg"
+        target = "linux-x86_64"
+        url = "https://example.com/ffmpeg.zip"
+        sha256 = "00"
+        executable = "ffmpeg"
+        "#,
+    )
+    .expect("manifest should parse");
+    let sidecar_dir = dir.join("sidecar");
+
+    let chrome_zip = dir.join("chrome.zip");
+    write_zip(
+        &chrome_zip,
+        &[
+            (
+                "chrome-headless-shell-linux64/chrome-headless-shell",
+                "shell",
+            ),
+            ("chrome-headless-shell-linux64/locales/en-US.pak", "pak"),
+        ],
+    );
+    let shell = install_from_archive(&chrome_zip, &manifest.sidecars[0], &sidecar_dir)
+        .expect("headless_shell should install");
+    assert_eq!(
+        shell,
+        browser_stream::chromium::default_chromium_sidecar_path(&sidecar_dir)
+    );
+    assert_eq!(std::fs::read_to_string(&shell).unwrap(), "shell");
+    assert_eq!(
+        std::fs::read_to_string(sidecar_dir.join("chromium/locales/en-US.pak")).unwrap(),
+        "pak"
+    );
+
+    let ffmpeg_zip = dir.join("ffmpeg.zip");
+    write_zip(
+        &ffmpeg_zip,
+        &[("ffmpeg", "ffmpeg"), ("README.txt", "readme")],
+    );
+    let ffmpeg = install_from_archive(&ffmpeg_zip, &manifest.sidecars[1], &sidecar_dir)
+        .expect("ffmpeg should install");
+    assert_eq!(
+        ffmpeg,
+        browser_stream::encoder::default_ffmpeg_sidecar_path(&sidecar_dir)
+    );
+    assert!(!sidecar_dir.join("ffmpeg/README.txt").exists());
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = std::fs::metadata(&ffmpeg).unwrap().permissions().mode();
+        assert_eq!(mode & 0o111, 0o111);
+    }
+
+    let err = install_from_archive(&ffmpeg_zip, &manifest.sidecars[0], &sidecar_dir)
+        .expect_err("archive without the executable should fail");
+    assert!(err.to_string().contains("chrome-headless-shell not found"));
+
+    std::fs::remove_dir_all(&dir).expect("temp dir should be removable");
+}
@@ -0,0 +1,142 @@
+use std::time::{Duration, Instant};
+
+use clap::Parser;
+use tokio_util::sync::CancellationToken;
+
+use browser_stream::cli::CliArgs;
+use browser_stream::pipeline;
+use browser_stream::startup::{StartupReport, StartupTimeline, clear_ready, mark_ready};
+
+#[test]
+fn reports_deltas_between_milestones() {
+    let start = Instant::now();
+    let at = |ms: u64| start + Duration::from_millis(ms);
+    let mut timeline = StartupTimeline::new(start);
+
+    timeline.encoder_spawned(at(100));
+    timeline.browser_launched(at(400));
+    timeline.page_loaded(at(1_500));
+    timeline.first_screencast_frame(at(3_600));
+    assert_eq!(timeline.report(), None);
+
+    timeline.first_encoded_frame(at(3_650));
+    timeline.first_encoded_frame(at(9_000));
+
+    assert_eq!(
+        timeline.report(),
+        Some(StartupReport {
+            browser_launch_ms: 400,
+            navigation_ms: 1_100,
+            first_frame_ms: 2_100,
+            encode_ms: 50,
+            total_ms: 3_650,
+            encoder_idle_ms: 3_550,
+        })
+    );
+}
+
+#[test]
+fn skipped_milestones_count_as_zero() {
+    let start = Instant::now();
+    let mut timeline = StartupTimeline::new(start);
+
+    timeline.browser_launched(start + Duration::from_millis(200));
+    timeline.first_screencast_frame(start + Duration::from_millis(500));
+    timeline.first_encoded_frame(start + Duration::from_millis(500));
+
+    let report = timeline.report().expect("first frame was encoded");
+    assert_eq!(report.navigation_ms, 0);
+    assert_eq!(report.first_frame_ms, 300);
+    assert_eq!(report.total_ms, 500);
+    assert_eq!(report.encoder_idle_ms, 500);
+}
+
+#[test]
+fn encoder_idle_time_starts_at_the_later_of_spawn_and_attempt() {
+    let start = Instant::now();
+    let at = |ms: u64| start + Duration::from_millis(ms);
+
+    // `--warm-start`: ffmpeg is spawned only once the page has settled.
+    let mut warm = StartupTimeline::new(start);
+    warm.encoder_spawned(at(3_000));
+    warm.first_encoded_frame(at(3_200));
+    let report = warm.report().expect("first frame was encoded");
+    assert_eq!((report.total_ms, report.encoder_idle_ms), (3_200, 200));
+
+    // `--persistent-encoder`: ffmpeg predates the attempt.
+    let mut persistent = StartupTimeline::new(at(10_000));
+    persistent.encoder_spawned(start);
+    persistent.first_encoded_frame(at(11_000));
+    let report = persistent.report().expect("first frame was encoded");
+    assert_eq!(report.encoder_idle_ms, 1_000);
+}
+
+#[test]
+fn run_id_is_stable_short_hex() {
+    let run_id = browser_stream::startup::run_id();
+    assert_eq!(run_id.len(), 8);
+    assert!(run_id.chars().all(|c| c.is_ascii_hexdigit()));
+    assert_eq!(browser_stream::startup::run_id(), run_id);
+}
+
+#[test]
+fn ready_file_is_written_once_and_removed() {
+    let path = std::env::temp_dir().join(format!("browser-stream-ready-{}", std::process::id()));
+
+    mark_ready(&path);
+    let contents = std::fs::read_to_string(&path).expect("ready file should exist");
+    assert_eq!(contents.trim(), std::process::id().to_string());
+
+    // A later attempt going live again leaves the file alone.
+    std::fs::write(&path, "kept").expect("ready file should be writable");
+    mark_ready(&path);
+    assert_eq!(
+        std::fs::read_to_string(&path).expect("ready file should exist"),
+        "kept"
+    );
+
+    clear_ready(&path);
+    assert!(!path.exists());
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn cancelling_the_token_ends_the_pipeline_cleanly() {
+    use std::os::unix::fs::PermissionsExt;
+
+    // Stands in for ffmpeg: takes every frame and writes nothing.
+    let ffmpeg =
+        std::env::temp_dir().join(format!("browser-stream-fake-ffmpeg-{}", std::process::id()));
+    std::fs::write(&ffmpeg, "#!/bin/sh\ncat >/dev/null\n").expect("stub should be written");
+    std::fs::set_permissions(&ffmpeg, std::fs::Permissions::from_mode(0o755))
+        .expect("stub should be executable");
+    let config = CliArgs::try_parse_from([
+        "browser-stream",
+        "--url",
+        "https://example.com",
+        "--test-pattern",
+        "bars",
+        "--output-to-null",
+        "--width",
+        "64",
+        "--height",
+        "64",
+        "--ffmpeg-path",
+        ffmpeg.to_str().expect("temp path should be UTF-8"),
+    ])
+    .expect("args should parse")
+    .into_config()
+    .expect("config should be valid");
+
+    let cancel = CancellationToken::new();
+    let stop = cancel.clone();
+    tokio::spawn(async move {
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        stop.cancel();
+    });
+    let result = tokio::time::timeout(Duration::from_secs(10), pipeline::run(config, Some(cancel)))
+        .await
+        .expect("cancelling should stop the stream");
+    std::fs::remove_file(&ffmpeg).expect("stub should be removed");
+    result.expect("a cancelled stream should end cleanly");
+}